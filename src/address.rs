@@ -0,0 +1,142 @@
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::Error;
+
+/// A 3-byte Insteon device address, printed as "1A.D0.F4".
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct InsteonAddress(pub [u8; 3]);
+
+impl InsteonAddress {
+    pub fn new(high: u8, middle: u8, low: u8) -> InsteonAddress {
+        InsteonAddress([high, middle, low])
+    }
+
+    pub fn bytes(&self) -> [u8; 3] {
+        self.0
+    }
+}
+
+impl fmt::Display for InsteonAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02X}.{:02X}.{:02X}", self.0[0], self.0[1], self.0[2])
+    }
+}
+
+impl fmt::Debug for InsteonAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseAddressError(String);
+
+impl fmt::Display for ParseAddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid Insteon address: {:?}", self.0)
+    }
+}
+
+impl error::Error for ParseAddressError {
+    fn description(&self) -> &str {
+        "invalid Insteon address"
+    }
+}
+
+/// Accepts both the dotted "1A.D0.F4" and the packed "1AD0F4" forms.
+impl FromStr for InsteonAddress {
+    type Err = ParseAddressError;
+
+    fn from_str(s: &str) -> Result<InsteonAddress, ParseAddressError> {
+        let error = || ParseAddressError(s.to_owned());
+
+        let digits : String = match s.trim().split('.').collect::<Vec<&str>>().as_slice() {
+            [high, middle, low] if high.len() == 2 && middle.len() == 2 && low.len() == 2 =>
+                [*high, *middle, *low].concat(),
+            [packed] if packed.len() == 6 => packed.to_string(),
+            _ => return Err(error()),
+        };
+
+        if !digits.chars().all(|c| c.is_digit(16)) {
+            return Err(error())
+        }
+
+        let byte = |i: usize| u8::from_str_radix(&digits[i..i + 2], 16).map_err(|_| error());
+        Ok(InsteonAddress([byte(0)?, byte(2)?, byte(4)?]))
+    }
+}
+
+/// The gRPC API carries addresses in the low 24 bits of a `uint32`.
+impl From<u32> for InsteonAddress {
+    fn from(x: u32) -> InsteonAddress {
+        InsteonAddress([(x >> 16) as u8, (x >> 8) as u8, x as u8])
+    }
+}
+
+impl From<InsteonAddress> for u32 {
+    fn from(addr: InsteonAddress) -> u32 {
+        (addr.0[0] as u32) << 16 | (addr.0[1] as u32) << 8 | addr.0[2] as u32
+    }
+}
+
+impl Serialize for InsteonAddress {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for InsteonAddress {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<InsteonAddress, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn a() -> InsteonAddress {
+        InsteonAddress::new(0x1A, 0xD0, 0xF4)
+    }
+
+    #[test]
+    fn parses_dotted_and_packed_forms() {
+        assert_eq!("1A.D0.F4".parse(), Ok(a()));
+        assert_eq!("1AD0F4".parse(), Ok(a()));
+        assert_eq!("1a.d0.f4".parse(), Ok(a()));
+        assert_eq!(" 1AD0F4\n".parse(), Ok(a()));
+    }
+
+    #[test]
+    fn rejects_anything_else() {
+        for s in &["", "1A.D0", "1AD0F", "1AD0F4F4", "1A.D0.F4.00", "1A.D.0F4", "1A:D0:F4",
+                   "1A D0 F4", "1A.D0.G4", "+1AD0F", "1A.D0.é"] {
+            assert_eq!(s.parse::<InsteonAddress>(), Err(ParseAddressError(s.to_string())), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn formats_as_dotted_hex() {
+        assert_eq!(a().to_string(), "1A.D0.F4");
+        assert_eq!(format!("{:?}", InsteonAddress::new(0x00, 0x0B, 0xC0)), "00.0B.C0");
+    }
+
+    #[test]
+    fn round_trips_through_u32() {
+        assert_eq!(u32::from(a()), 0x1AD0F4);
+        assert_eq!(InsteonAddress::from(0x1AD0F4), a());
+        assert_eq!(InsteonAddress::from(0xFF1AD0F4), a());
+    }
+
+    #[test]
+    fn serialises_as_a_string() {
+        assert_eq!(serde_json::to_string(&a()).unwrap(), "\"1A.D0.F4\"");
+        assert_eq!(serde_json::from_str::<InsteonAddress>("\"1AD0F4\"").unwrap(), a());
+        assert!(serde_json::from_str::<InsteonAddress>("\"1A.D0\"").is_err());
+    }
+}
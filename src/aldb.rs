@@ -0,0 +1,555 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use robots::actors::{Actor, ActorCell, ActorContext, ActorRef, Any};
+
+use address::InsteonAddress;
+use engine::*;
+use insteon_structs::*;
+use rpc::RpcActorMsg;
+use timer;
+
+/// Address of the first record; the database grows downwards from here.
+/// Records are addressed by their last byte, which is where extended
+/// reads and writes point; peeks and pokes start `RECORD_SIZE - 1` lower.
+pub const ALDB_START :u16 = 0x0FFF;
+pub const RECORD_SIZE :u16 = 8;
+pub const MAX_RECORDS :usize = 512;
+
+pub const ALDB_WAIT_INTERVAL_MS :u64 = 1500;
+pub const ALDB_RETRIES :usize = 4;
+
+const ALDB_READ :u8 = 0x00;
+const ALDB_RESPONSE :u8 = 0x01;
+const ALDB_WRITE :u8 = 0x02;
+
+/// One record of a device's ALL-Link database.
+#[derive(Debug)]
+#[derive(Copy, Clone)]
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct AldbRecord {
+    pub mem_addr: u16,
+    pub in_use: bool,
+    /// Controller of `address` for `group` if set, responder otherwise.
+    pub controller: bool,
+    /// Cleared only on the first record that has never been used, which
+    /// marks the end of the database.
+    pub used: bool,
+    pub group: u8,
+    pub address: InsteonAddress,
+    pub data: [u8; 3],
+}
+
+impl AldbRecord {
+    const IN_USE :u8 = 0b1000_0000;
+    const CONTROLLER :u8 = 0b0100_0000;
+    const USED :u8 = 0b0000_0010;
+
+    pub fn from_bytes(mem_addr: u16, b: &[u8]) -> AldbRecord {
+        AldbRecord {
+            mem_addr: mem_addr,
+            in_use: b[0] & AldbRecord::IN_USE != 0,
+            controller: b[0] & AldbRecord::CONTROLLER != 0,
+            used: b[0] & AldbRecord::USED != 0,
+            group: b[1],
+            address: InsteonAddress::new(b[2], b[3], b[4]),
+            data: [b[5], b[6], b[7]],
+        }
+    }
+
+    pub fn flags(&self) -> u8 {
+        (if self.in_use { AldbRecord::IN_USE } else { 0 }) |
+        (if self.controller { AldbRecord::CONTROLLER } else { 0 }) |
+        (if self.used { AldbRecord::USED } else { 0 })
+    }
+
+    pub fn to_bytes(&self) -> [u8; 8] {
+        let address = self.address.bytes();
+        [self.flags(), self.group, address[0], address[1], address[2],
+         self.data[0], self.data[1], self.data[2]]
+    }
+
+    pub fn is_high_water_mark(&self) -> bool {
+        !self.used
+    }
+
+    /// The record to write at `mem_addr` to delete whatever link is there,
+    /// without moving the high water mark.
+    pub fn deleted(mem_addr: u16) -> AldbRecord {
+        AldbRecord {
+            mem_addr: mem_addr,
+            in_use: false,
+            controller: false,
+            used: true,
+            group: 0,
+            address: InsteonAddress::new(0, 0, 0),
+            data: [0, 0, 0],
+        }
+    }
+}
+
+pub fn read_request(dialect: Dialect, device: InsteonAddress, mem_addr: u16)
+    -> Option<InsteonMsg> {
+    let mut user_data = [0u8; 14];
+    user_data[1] = ALDB_READ;
+    user_data[2] = (mem_addr >> 8) as u8;
+    user_data[3] = mem_addr as u8;
+    user_data[4] = 0x01;
+    dialect.extended(device, READ_WRITE_ALDB, 0x00, user_data)
+}
+
+pub fn write_request(dialect: Dialect, device: InsteonAddress, record: &AldbRecord)
+    -> Option<InsteonMsg> {
+    let mut user_data = [0u8; 14];
+    user_data[1] = ALDB_WRITE;
+    user_data[2] = (record.mem_addr >> 8) as u8;
+    user_data[3] = record.mem_addr as u8;
+    user_data[4] = RECORD_SIZE as u8;
+    user_data[5..13].copy_from_slice(&record.to_bytes());
+    dialect.extended(device, READ_WRITE_ALDB, 0x00, user_data)
+}
+
+/// A record sent by `device` in answer to a read request.
+pub fn parse_response(msg: &InsteonMsg, device: InsteonAddress) -> Option<AldbRecord> {
+    match *msg {
+        InsteonMsg::ExtendedMsg { addr_from, cmd1, ref user_data, .. }
+            if addr_from == device && cmd1 == READ_WRITE_ALDB &&
+               user_data[1] == ALDB_RESPONSE => {
+            let mem_addr = (user_data[2] as u16) << 8 | user_data[3] as u16;
+            Some(AldbRecord::from_bytes(mem_addr, &user_data[5..13]))
+        },
+        _ => None,
+    }
+}
+
+/// Where the bytes of the record at `mem_addr` start, for peeks and pokes.
+fn record_start(mem_addr: u16) -> u16 {
+    mem_addr.saturating_sub(RECORD_SIZE - 1)
+}
+
+#[derive(Debug)]
+#[derive(Copy, Clone)]
+pub enum AldbOp {
+    Read,
+    Write(AldbRecord),
+}
+
+#[derive(Clone)]
+pub struct AldbDump {
+    pub success: bool,
+    pub records: Vec<AldbRecord>,
+}
+
+/// What an `AldbJob` needs done next.
+#[derive(Debug, PartialEq)]
+enum Progress {
+    Send(InsteonMsg),
+    Wait,
+    Done(bool),
+}
+
+/// Reads a device's database one record at a time, from `ALDB_START` down
+/// to the high water mark, or writes one record.
+///
+/// i2 devices take one extended message per record. i1 devices only take
+/// standard messages, so every byte is peeked or poked on its own, each
+/// message waiting for the ACK of the one before.
+struct AldbJob {
+    device: InsteonAddress,
+    dialect: Dialect,
+    op: AldbOp,
+    /// The record being read or written.
+    mem_addr: u16,
+    records: Vec<AldbRecord>,
+    /// The messages left for the current record; the front one is in flight.
+    steps: VecDeque<InsteonMsg>,
+    /// The bytes of the current record an i1 device has returned so far.
+    peeked: Vec<u8>,
+}
+
+impl AldbJob {
+    fn new(device: InsteonAddress, dialect: Dialect, op: AldbOp) -> AldbJob {
+        AldbJob {
+            device: device,
+            dialect: dialect,
+            op: op,
+            mem_addr: match op {
+                AldbOp::Read => ALDB_START,
+                AldbOp::Write(record) => record.mem_addr,
+            },
+            records: Vec::new(),
+            steps: VecDeque::new(),
+            peeked: Vec::new(),
+        }
+    }
+
+    /// Queues the messages that read or write the record at `mem_addr`.
+    fn plan(&mut self) -> Progress {
+        let start = record_start(self.mem_addr);
+        let steps = match (self.op, self.dialect) {
+            (AldbOp::Read, Dialect::PeekPoke) =>
+                peek_requests(self.device, start, RECORD_SIZE),
+            (AldbOp::Write(record), Dialect::PeekPoke) =>
+                poke_requests(self.device, start, &record.to_bytes()),
+            (AldbOp::Read, dialect) =>
+                read_request(dialect, self.device, self.mem_addr).into_iter().collect(),
+            (AldbOp::Write(record), dialect) =>
+                write_request(dialect, self.device, &record).into_iter().collect(),
+        };
+
+        self.steps = steps.into_iter().collect();
+        self.peeked.clear();
+        match self.current() {
+            Some(msg) => Progress::Send(msg),
+            None => Progress::Done(false),
+        }
+    }
+
+    fn current(&self) -> Option<InsteonMsg> {
+        self.steps.front().cloned()
+    }
+
+    fn observe(&mut self, msg: &InsteonMsg) -> Progress {
+        if msg.is_direct_nak_from(self.device) {
+            warn!("{} refused {:?}", self.device, self.current());
+            return Progress::Done(false)
+        }
+
+        match (self.op, self.dialect) {
+            // The record itself answers an extended read; the ACK before it says nothing.
+            (AldbOp::Read, Dialect::Extended) | (AldbOp::Read, Dialect::ChecksummedExtended(_)) =>
+                match parse_response(msg, self.device) {
+                    Some(record) if record.mem_addr == self.mem_addr => self.record_read(record),
+                    _ => Progress::Wait,
+                },
+            _ => self.acked(msg),
+        }
+    }
+
+    /// Moves on to the next message once the device ACKs the one in flight.
+    fn acked(&mut self, msg: &InsteonMsg) -> Progress {
+        let sent = match self.steps.front() {
+            Some(&InsteonMsg::SendStandardMsg { cmd1, .. }) |
+            Some(&InsteonMsg::SendExtendedMsg { cmd1, .. }) => cmd1,
+            _ => return Progress::Wait,
+        };
+
+        match msg.direct_ack_from(self.device) {
+            Some((cmd1, _)) if cmd1 == sent => (),
+            _ => return Progress::Wait,
+        }
+
+        if let Some(byte) = peeked_byte(msg, self.device) {
+            self.peeked.push(byte);
+        }
+        self.steps.pop_front();
+
+        match self.current() {
+            Some(next) => Progress::Send(next),
+            None => match self.op {
+                AldbOp::Read => {
+                    let record = AldbRecord::from_bytes(self.mem_addr, &self.peeked);
+                    self.record_read(record)
+                },
+                AldbOp::Write(_) => Progress::Done(true),
+            },
+        }
+    }
+
+    fn record_read(&mut self, record: AldbRecord) -> Progress {
+        trace!("ALDB record: {:?}", record);
+        if record.is_high_water_mark() {
+            return Progress::Done(true)
+        }
+
+        self.records.push(record);
+        if record.mem_addr < RECORD_SIZE || self.records.len() >= MAX_RECORDS {
+            return Progress::Done(true)
+        }
+
+        self.mem_addr -= RECORD_SIZE;
+        self.plan()
+    }
+}
+
+#[derive(Clone)]
+pub enum AldbActorMsg {
+    Start(ActorRef),
+    Timeout(usize, usize),
+}
+
+struct AldbState {
+    future: ActorRef,
+    job: AldbJob,
+    /// Sequence number of the only timeout still allowed to resend.
+    seq: usize,
+}
+
+/// Runs one `AldbJob`, resending whatever the device does not answer.
+/// Completes a read with an `AldbDump` and a write with a `bool`.
+pub struct AldbActor {
+    ser_tx_actor : ActorRef,
+    device       : InsteonAddress,
+    dialect      : Dialect,
+    op           : AldbOp,
+    state        : Mutex<Option<AldbState>>,
+}
+
+impl AldbActor {
+    pub fn new(tuple: (ActorRef, InsteonAddress, Dialect, AldbOp)) -> AldbActor {
+        let (ser_tx_actor, device, dialect, op) = tuple;
+        AldbActor {
+            ser_tx_actor: ser_tx_actor,
+            device: device,
+            dialect: dialect,
+            op: op,
+            state: Mutex::new(None),
+        }
+    }
+
+    fn send(&self, state: &mut AldbState, msg: InsteonMsg, retries: usize, context: &ActorCell) {
+        self.ser_tx_actor.tell_to(self.ser_tx_actor.clone(), ActorMsg::Send(msg));
+
+        state.seq += 1;
+        timer::schedule(context.actor_ref(), AldbActorMsg::Timeout(state.seq, retries),
+                        Duration::from_millis(ALDB_WAIT_INTERVAL_MS));
+    }
+
+    fn progress(&self, interior: &mut Option<AldbState>, progress: Progress, context: &ActorCell) {
+        let success = match progress {
+            Progress::Send(msg) => {
+                self.send(interior.as_mut().unwrap(), msg, 0, context);
+                return
+            },
+            Progress::Wait => return,
+            Progress::Done(success) => success,
+        };
+
+        let state = interior.take().unwrap();
+        match state.job.op {
+            AldbOp::Read => {
+                info!("Read {} record(s) from {}", state.job.records.len(), self.device);
+                context.complete(state.future, AldbDump {
+                    success: success,
+                    records: state.job.records,
+                });
+            },
+            AldbOp::Write(record) => {
+                info!("Wrote {:?} to {}: {}", record, self.device, success);
+                context.complete(state.future, success);
+            },
+        }
+
+        context.tell(context.father(), RpcActorMsg::Done(self.device));
+        context.kill_me();
+    }
+
+    pub fn handle_insteon_msg(&self, message: InsteonMsg, context: ActorCell) {
+        let mut interior = self.state.lock().unwrap();
+        let progress = match *interior {
+            Some(ref mut state) => state.job.observe(&message),
+            None => return,
+        };
+        self.progress(&mut interior, progress, &context);
+    }
+
+    pub fn handle_aldb_msg(&self, message: AldbActorMsg, context: ActorCell) {
+        let mut interior = self.state.lock().unwrap();
+
+        match message {
+            AldbActorMsg::Start(future) => {
+                let mut job = AldbJob::new(self.device, self.dialect, self.op);
+                let progress = job.plan();
+                *interior = Some(AldbState {
+                    future: future,
+                    job: job,
+                    seq: 0,
+                });
+                self.progress(&mut interior, progress, &context);
+            },
+
+            AldbActorMsg::Timeout(seq, retries) => {
+                let current = match *interior {
+                    Some(ref state) if state.seq == seq => state.job.current(),
+                    _ => return,
+                };
+
+                match current {
+                    Some(msg) if retries < ALDB_RETRIES =>
+                        self.send(interior.as_mut().unwrap(), msg, retries + 1, &context),
+                    _ => {
+                        warn!("{} stopped answering at {:#06x}", self.device,
+                              interior.as_ref().unwrap().job.mem_addr);
+                        self.progress(&mut interior, Progress::Done(false), &context);
+                    },
+                }
+            },
+        }
+    }
+}
+
+impl Actor for AldbActor {
+    fn receive(&self, msg: Box<Any>, context: ActorCell) {
+        match msg.downcast_ref::<AldbActorMsg>() {
+            Some(aldb_msg) => self.handle_aldb_msg(aldb_msg.clone(), context),
+            None => match msg.downcast_ref::<InsteonMsg>() {
+                Some(insteon_msg) => self.handle_insteon_msg(*insteon_msg, context),
+                None => unreachable!(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use checksum::Checksum;
+
+    fn a() -> InsteonAddress {
+        InsteonAddress::new(0x1A, 0xD0, 0xF4)
+    }
+
+    fn plm() -> InsteonAddress {
+        InsteonAddress::new(0x44, 0x85, 0x11)
+    }
+
+    fn controller_link() -> AldbRecord {
+        AldbRecord {
+            mem_addr: 0x0FFF,
+            in_use: true,
+            controller: true,
+            used: true,
+            group: 0x01,
+            address: plm(),
+            data: [0x03, 0x1C, 0x01],
+        }
+    }
+
+    fn ack(cmd1: u8, cmd2: u8) -> InsteonMsg {
+        InsteonMsg::StandardMsg { addr_from: a(), addr_to: plm(),
+                                  msg_flags: MessageFlags::new(MessageType::DirectAck, false),
+                                  cmd1: cmd1, cmd2: cmd2 }
+    }
+
+    fn response(mem_addr: u16, record: [u8; 8]) -> InsteonMsg {
+        let mut user_data = [0u8; 14];
+        user_data[1] = ALDB_RESPONSE;
+        user_data[2] = (mem_addr >> 8) as u8;
+        user_data[3] = mem_addr as u8;
+        user_data[5..13].copy_from_slice(&record);
+        InsteonMsg::ExtendedMsg { addr_from: a(), addr_to: plm(),
+                                  msg_flags: MessageFlags::new(MessageType::Direct, true),
+                                  cmd1: READ_WRITE_ALDB, cmd2: 0x00, user_data: user_data }
+    }
+
+    fn standard(progress: Progress) -> (u8, u8) {
+        match progress {
+            Progress::Send(InsteonMsg::SendStandardMsg { cmd1, cmd2, .. }) => (cmd1, cmd2),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn parses_records() {
+        let record = AldbRecord::from_bytes(0x0FFF, &[0xE2, 0x01, 0x44, 0x85, 0x11, 0x03, 0x1C, 0x01]);
+        assert_eq!(record, controller_link());
+        assert_eq!(record.flags(), 0xC2);
+        assert_eq!(record.to_bytes(), [0xC2, 0x01, 0x44, 0x85, 0x11, 0x03, 0x1C, 0x01]);
+
+        let responder = AldbRecord::from_bytes(0x0FF7, &[0xA2, 0x00, 0x44, 0x85, 0x11, 0xFF, 0x1F, 0x01]);
+        assert!(responder.in_use && !responder.controller && !responder.is_high_water_mark());
+
+        assert!(AldbRecord::from_bytes(0x0FEF, &[0x00; 8]).is_high_water_mark());
+        assert!(!AldbRecord::deleted(0x0FEF).in_use);
+        assert!(!AldbRecord::deleted(0x0FEF).is_high_water_mark());
+    }
+
+    #[test]
+    fn parses_read_responses_from_the_device_only() {
+        let msg = response(0x0FFF, [0xE2, 0x01, 0x44, 0x85, 0x11, 0x03, 0x1C, 0x01]);
+        assert_eq!(parse_response(&msg, a()), Some(controller_link()));
+        assert_eq!(parse_response(&msg, plm()), None);
+    }
+
+    #[test]
+    fn writes_a_record_in_one_extended_message() {
+        let dialect = Dialect::ChecksummedExtended(Checksum::Classic);
+        match write_request(dialect, a(), &controller_link()) {
+            Some(InsteonMsg::SendExtendedMsg { addr_to, cmd1, cmd2, user_data, .. }) => {
+                assert_eq!((addr_to, cmd1, cmd2), (a(), READ_WRITE_ALDB, 0x00));
+                assert_eq!(&user_data[..13],
+                           &[0x00, ALDB_WRITE, 0x0F, 0xFF, 0x08,
+                             0xC2, 0x01, 0x44, 0x85, 0x11, 0x03, 0x1C, 0x01]);
+                assert!(Checksum::Classic.verify(cmd1, cmd2, &user_data));
+            },
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(write_request(Dialect::PeekPoke, a(), &controller_link()), None);
+    }
+
+    #[test]
+    fn reads_an_i2_database_down_to_the_high_water_mark() {
+        let mut job = AldbJob::new(a(), Dialect::Extended, AldbOp::Read);
+        assert_eq!(job.plan(), Progress::Send(read_request(Dialect::Extended, a(), 0x0FFF).unwrap()));
+
+        assert_eq!(job.observe(&ack(READ_WRITE_ALDB, 0x00)), Progress::Wait);
+        assert_eq!(job.observe(&response(0x0FF7, [0xA2; 8])), Progress::Wait);
+        assert_eq!(job.observe(&response(0x0FFF, controller_link().to_bytes())),
+                   Progress::Send(read_request(Dialect::Extended, a(), 0x0FF7).unwrap()));
+        assert_eq!(job.observe(&response(0x0FF7, [0x00; 8])), Progress::Done(true));
+        assert_eq!(job.records, vec![controller_link()]);
+    }
+
+    #[test]
+    fn reads_an_i1_record_a_byte_at_a_time() {
+        let mut job = AldbJob::new(a(), Dialect::PeekPoke, AldbOp::Read);
+        assert_eq!(standard(job.plan()), (0x28, 0x0F));
+        assert_eq!(standard(job.observe(&ack(0x28, 0x0F))), (0x2B, 0xF8));
+
+        // A status ACK does not answer a peek.
+        assert_eq!(job.observe(&ack(0x19, 0x00)), Progress::Wait);
+
+        let bytes = controller_link().to_bytes();
+        for i in 0..7 {
+            assert_eq!(standard(job.observe(&ack(0x2B, bytes[i]))), (0x2B, 0xF9 + i as u8));
+        }
+        // The last byte completes the record and starts on the next one.
+        assert_eq!(standard(job.observe(&ack(0x2B, bytes[7]))), (0x28, 0x0F));
+        assert_eq!(job.records, vec![controller_link()]);
+        assert_eq!(job.mem_addr, 0x0FF7);
+    }
+
+    #[test]
+    fn writes_an_i1_record_a_byte_at_a_time() {
+        let mut job = AldbJob::new(a(), Dialect::PeekPoke, AldbOp::Write(controller_link()));
+        let mut sent = vec![standard(job.plan())];
+        loop {
+            let (cmd1, cmd2) = *sent.last().unwrap();
+            match job.observe(&ack(cmd1, cmd2)) {
+                Progress::Done(success) => {
+                    assert!(success);
+                    break
+                },
+                progress => sent.push(standard(progress)),
+            }
+        }
+
+        let mut expected = vec![(0x28, 0x0F)];
+        for (i, byte) in controller_link().to_bytes().iter().enumerate() {
+            expected.push((0x2B, 0xF8 + i as u8));
+            expected.push((0x29, *byte));
+        }
+        assert_eq!(sent, expected);
+    }
+
+    #[test]
+    fn gives_up_when_the_device_refuses() {
+        let mut job = AldbJob::new(a(), Dialect::Extended, AldbOp::Write(controller_link()));
+        job.plan();
+        let nak = InsteonMsg::StandardMsg { addr_from: a(), addr_to: plm(),
+                                            msg_flags: MessageFlags::new(MessageType::DirectNak, false),
+                                            cmd1: READ_WRITE_ALDB, cmd2: 0xFF };
+        assert_eq!(job.observe(&nak), Progress::Done(false));
+    }
+}
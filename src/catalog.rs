@@ -0,0 +1,134 @@
+use phf;
+
+pub struct Product {
+    pub model: &'static str,
+    pub description: &'static str,
+}
+
+static CATEGORIES: phf::Map<u8, &'static str> = phf_map!(
+    0x00u8 => "Generalized Controllers",
+    0x01u8 => "Dimmable Lighting Control",
+    0x02u8 => "Switched Lighting Control",
+    0x03u8 => "Network Bridges",
+    0x04u8 => "Irrigation Control",
+    0x05u8 => "Climate Control",
+    0x06u8 => "Pool and Spa Control",
+    0x07u8 => "Sensors and Actuators",
+    0x08u8 => "Home Entertainment",
+    0x09u8 => "Energy Management",
+    0x0Au8 => "Built-In Appliance Control",
+    0x0Bu8 => "Plumbing",
+    0x0Cu8 => "Communication",
+    0x0Du8 => "Computer Control",
+    0x0Eu8 => "Window Coverings",
+    0x0Fu8 => "Access Control",
+    0x10u8 => "Security, Health, Safety",
+    0x11u8 => "Surveillance",
+    0x12u8 => "Automotive",
+    0x13u8 => "Pet Care",
+    0x14u8 => "Toys",
+    0x15u8 => "Timekeeping",
+    0x16u8 => "Holiday",
+);
+
+/// Keyed by `category << 8 | subcategory`.
+static PRODUCTS: phf::Map<u16, Product> = phf_map!(
+    0x0004u16 => Product { model: "2430", description: "ControLinc" },
+    0x0005u16 => Product { model: "2440", description: "RemoteLinc" },
+    0x0006u16 => Product { model: "2830", description: "Icon Tabletop Controller" },
+    0x0009u16 => Product { model: "2442", description: "SignaLinc RF Signal Enhancer" },
+    0x000Bu16 => Product { model: "2443", description: "Access Point" },
+    0x0010u16 => Product { model: "2444A2xx4", description: "Mini Remote - 4 Scene" },
+    0x0011u16 => Product { model: "2444A3", description: "Mini Remote - Switch" },
+    0x0012u16 => Product { model: "2444A2xx8", description: "Mini Remote - 8 Scene" },
+
+    0x0100u16 => Product { model: "2456D3", description: "LampLinc 3-Pin" },
+    0x0101u16 => Product { model: "2476D", description: "SwitchLinc Dimmer 600W" },
+    0x0102u16 => Product { model: "2475D", description: "In-LineLinc Dimmer" },
+    0x0103u16 => Product { model: "2876D", description: "Icon SwitchLinc Dimmer" },
+    0x0104u16 => Product { model: "2476DH", description: "SwitchLinc Dimmer 1000W" },
+    0x0105u16 => Product { model: "2484DWH8", description: "KeypadLinc Dimmer Countdown Timer" },
+    0x0106u16 => Product { model: "2456D2", description: "LampLinc 2-Pin" },
+    0x0107u16 => Product { model: "2856D2", description: "Icon LampLinc" },
+    0x0109u16 => Product { model: "2486D", description: "KeypadLinc Dimmer 8-Button" },
+    0x010Au16 => Product { model: "2886D", description: "Icon In-Wall Controller" },
+    0x010Cu16 => Product { model: "2486DWH6", description: "KeypadLinc Dimmer 6-Button" },
+    0x010Du16 => Product { model: "2454D", description: "SocketLinc" },
+    0x010Eu16 => Product { model: "2457D2", description: "LampLinc Dual-Band" },
+    0x0117u16 => Product { model: "2466D", description: "ToggleLinc Dimmer" },
+    0x0118u16 => Product { model: "2474D", description: "Icon SwitchLinc Dimmer Inline Companion" },
+    0x0119u16 => Product { model: "2476D", description: "SwitchLinc Dimmer 800W" },
+    0x011Au16 => Product { model: "2475D", description: "In-LineLinc Dimmer with Sense" },
+    0x011Bu16 => Product { model: "2486DWH6", description: "KeypadLinc Dimmer 6-Button" },
+    0x011Cu16 => Product { model: "2486DWH8", description: "KeypadLinc Dimmer 8-Button" },
+    0x011Du16 => Product { model: "2476DH", description: "SwitchLinc Dimmer 1200W" },
+    0x011Eu16 => Product { model: "2876DB", description: "Icon Dimmer Switch" },
+    0x011Fu16 => Product { model: "2466DW", description: "ToggleLinc Dimmer" },
+    0x0120u16 => Product { model: "2477D", description: "SwitchLinc Dimmer (Dual-Band)" },
+    0x0121u16 => Product { model: "2472D", description: "OutletLinc Dimmer" },
+    0x0122u16 => Product { model: "2457D2X", description: "LampLinc" },
+    0x0124u16 => Product { model: "2474DWH", description: "SwitchLinc 2-Wire Dimmer" },
+    0x0125u16 => Product { model: "2475DA1", description: "In-LineLinc Dimmer" },
+    0x012Du16 => Product { model: "2477DH", description: "SwitchLinc Dimmer (Dual-Band) 1000W" },
+    0x012Eu16 => Product { model: "2475F", description: "FanLinc" },
+    0x0130u16 => Product { model: "2476D", description: "SwitchLinc Dimmer" },
+    0x0132u16 => Product { model: "2475DA2", description: "In-LineLinc Dimmer" },
+    0x013Au16 => Product { model: "2672-222", description: "LED Bulb" },
+    0x0141u16 => Product { model: "2334-232", description: "KeypadLinc Dimmer 8-Button" },
+    0x0149u16 => Product { model: "2674-222", description: "LED Bulb PAR38" },
+
+    0x0205u16 => Product { model: "2486SWH8", description: "KeypadLinc Relay 8-Button" },
+    0x0206u16 => Product { model: "2456S3E", description: "Outdoor ApplianceLinc" },
+    0x0207u16 => Product { model: "2456ST3", description: "TimerLinc" },
+    0x0208u16 => Product { model: "2473S", description: "OutletLinc" },
+    0x0209u16 => Product { model: "2456S3", description: "ApplianceLinc" },
+    0x020Au16 => Product { model: "2476S", description: "SwitchLinc Relay" },
+    0x020Bu16 => Product { model: "2876S", description: "Icon On/Off Switch" },
+    0x020Cu16 => Product { model: "2856S3", description: "Icon Appliance Adapter" },
+    0x020Du16 => Product { model: "2466S", description: "ToggleLinc Relay" },
+    0x020Eu16 => Product { model: "2476ST", description: "SwitchLinc Relay Countdown Timer" },
+    0x020Fu16 => Product { model: "2486SWH6", description: "KeypadLinc Relay 6-Button" },
+    0x0210u16 => Product { model: "2475S", description: "In-LineLinc Relay" },
+    0x0214u16 => Product { model: "2475S2", description: "In-LineLinc Relay with Sense" },
+    0x0215u16 => Product { model: "2476SS", description: "SwitchLinc Relay with Sense" },
+    0x021Au16 => Product { model: "2466Sx", description: "ToggleLinc Relay" },
+    0x021Cu16 => Product { model: "2476S", description: "SwitchLinc Relay" },
+    0x021Eu16 => Product { model: "2487S", description: "KeypadLinc Relay 6-Button" },
+    0x021Fu16 => Product { model: "2475SDB", description: "In-LineLinc Relay" },
+    0x022Au16 => Product { model: "2477S", description: "SwitchLinc Relay (Dual-Band)" },
+    0x022Cu16 => Product { model: "2487S", description: "KeypadLinc On/Off" },
+    0x022Fu16 => Product { model: "2443-222", description: "Micro Module On/Off" },
+    0x0237u16 => Product { model: "2635-222", description: "On/Off Module" },
+    0x0239u16 => Product { model: "2663-222", description: "On/Off Outlet" },
+
+    0x0315u16 => Product { model: "2413U", description: "PowerLinc Modem USB" },
+
+    0x050Bu16 => Product { model: "2441TH", description: "Thermostat" },
+    0x050Eu16 => Product { model: "2491T", description: "All-In-One Thermostat" },
+    0x0510u16 => Product { model: "2441ZTH", description: "Wireless Thermostat" },
+
+    0x0700u16 => Product { model: "2450", description: "IOLinc" },
+    0x0703u16 => Product { model: "EZIO2X4", description: "EZIO 2 Relays, 4 Inputs" },
+    0x0704u16 => Product { model: "EZIO8SA", description: "EZIO 8 Relays" },
+    0x0707u16 => Product { model: "EZIO6I", description: "EZIO 6 Inputs" },
+    0x0708u16 => Product { model: "EZIO4O", description: "EZIO 4 Relays" },
+
+    0x0907u16 => Product { model: "2423A1", description: "iMeter Solo" },
+
+    0x0E01u16 => Product { model: "2444-222", description: "Micro Module Open/Close" },
+
+    0x1001u16 => Product { model: "2842-222", description: "Motion Sensor" },
+    0x1002u16 => Product { model: "2843-222", description: "Open/Close Sensor" },
+    0x1008u16 => Product { model: "2852-222", description: "Leak Sensor" },
+    0x100Au16 => Product { model: "2982-222", description: "Smoke Bridge" },
+    0x1011u16 => Product { model: "2845-222", description: "Hidden Door Sensor" },
+    0x1016u16 => Product { model: "2844-222", description: "Motion Sensor II" },
+);
+
+pub fn category_name(category: u8) -> Option<&'static str> {
+    CATEGORIES.get(&category).cloned()
+}
+
+pub fn lookup(category: u8, subcategory: u8) -> Option<&'static Product> {
+    PRODUCTS.get(&((category as u16) << 8 | subcategory as u16))
+}
@@ -0,0 +1,100 @@
+/// How the tail of an extended message's user data protects the message.
+/// i2cs devices silently drop extended commands whose checksum is wrong.
+#[derive(Debug)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum Checksum {
+    /// Two's complement of the sum of cmd1, cmd2 and D1-D13, stored in D14.
+    Classic,
+    /// CRC-16 over cmd1, cmd2 and D1-D12, stored big-endian in D13-D14.
+    Crc,
+}
+
+impl Checksum {
+    /// Overwrites the checksum bytes of `user_data`.
+    pub fn apply(&self, cmd1: u8, cmd2: u8, user_data: &mut [u8; 14]) {
+        match *self {
+            Checksum::Classic => {
+                user_data[13] = classic_checksum(cmd1, cmd2, &user_data[..13]);
+            },
+            Checksum::Crc => {
+                let crc = crc16(cmd1, cmd2, &user_data[..12]);
+                user_data[12] = (crc >> 8) as u8;
+                user_data[13] = crc as u8;
+            },
+        }
+    }
+
+    pub fn verify(&self, cmd1: u8, cmd2: u8, user_data: &[u8; 14]) -> bool {
+        let mut expected = *user_data;
+        self.apply(cmd1, cmd2, &mut expected);
+        expected == *user_data
+    }
+}
+
+pub fn classic_checksum(cmd1: u8, cmd2: u8, data: &[u8]) -> u8 {
+    let sum = data.iter().fold(cmd1.wrapping_add(cmd2), |acc, x| acc.wrapping_add(*x));
+    (!sum).wrapping_add(1)
+}
+
+pub fn crc16(cmd1: u8, cmd2: u8, data: &[u8]) -> u16 {
+    let mut crc : u16 = 0;
+
+    for byte in [cmd1, cmd2].iter().chain(data.iter()) {
+        let mut byte = *byte;
+        for _ in 0..8 {
+            let mut feedback = (byte & 0x01) as u16;
+            if crc & 0x8000 != 0 { feedback ^= 1; }
+            if crc & 0x4000 != 0 { feedback ^= 1; }
+            if crc & 0x1000 != 0 { feedback ^= 1; }
+            if crc & 0x0008 != 0 { feedback ^= 1; }
+            crc = (crc << 1) | feedback;
+            byte >>= 1;
+        }
+    }
+
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An extended get/set asking for the data set of button 1.
+    fn get_set() -> [u8; 14] {
+        let mut user_data = [0u8; 14];
+        user_data[0] = 0x01;
+        user_data
+    }
+
+    #[test]
+    fn classic_is_the_negated_sum() {
+        assert_eq!(classic_checksum(0x2E, 0x00, &get_set()[..13]), 0xD1);
+        assert_eq!(classic_checksum(0x00, 0x00, &[0u8; 13]), 0x00);
+        assert_eq!(classic_checksum(0x2F, 0x00, &[0x00, 0x00, 0x0F, 0xFF, 0x01]), 0xC2);
+    }
+
+    #[test]
+    fn crc_covers_commands_and_twelve_data_bytes() {
+        assert_eq!(crc16(0x2E, 0x00, &get_set()[..12]), 0x9A88);
+        assert_eq!(crc16(0x2E, 0x02, &[0x01, 0x05, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]), 0x2EF2);
+        assert_eq!(crc16(0x00, 0x00, &[0u8; 12]), 0x0000);
+    }
+
+    #[test]
+    fn apply_fills_in_what_verify_checks() {
+        for checksum in &[Checksum::Classic, Checksum::Crc] {
+            let mut user_data = get_set();
+            checksum.apply(0x2E, 0x00, &mut user_data);
+            assert!(checksum.verify(0x2E, 0x00, &user_data));
+
+            user_data[1] ^= 0x01;
+            assert!(!checksum.verify(0x2E, 0x00, &user_data));
+        }
+
+        let mut user_data = get_set();
+        Checksum::Crc.apply(0x2E, 0x00, &mut user_data);
+        assert_eq!(&user_data[12..], &[0x9A, 0x88]);
+        Checksum::Classic.apply(0x2E, 0x00, &mut user_data);
+        assert_eq!(user_data[13], classic_checksum(0x2E, 0x00, &user_data[..13]));
+    }
+}
@@ -1,38 +1,347 @@
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_io::AsyncRead;
 use tokio_io::codec::{Decoder, Encoder};
 use bytes::BytesMut;
+use futures::{Async, Future, Poll, Stream};
 use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 use insteon_structs::*;
+use wire::{WireFormat, DecodeError};
 
-pub struct LineCodec;
+/// Counters of everything the decoder had to throw away to stay in sync
+/// with the PLM.
+#[derive(Default)]
+pub struct FramingErrors {
+    pub discarded_bytes: AtomicUsize,
+    pub unknown_frames: AtomicUsize,
+    pub malformed_frames: AtomicUsize,
+    pub timed_out_frames: AtomicUsize,
+}
+
+pub struct LineCodec {
+    errors: Arc<FramingErrors>,
+    partial_len: usize,
+    last_progress: Instant,
+}
+
+impl LineCodec {
+    /// A partial frame that receives no further bytes within this interval is dropped.
+    pub const INTER_BYTE_TIMEOUT_MS : u64 = 250;
+
+    pub fn new() -> LineCodec {
+        LineCodec {
+            errors: Arc::new(FramingErrors::default()),
+            partial_len: 0,
+            last_progress: Instant::now(),
+        }
+    }
+
+    pub fn errors(&self) -> Arc<FramingErrors> {
+        self.errors.clone()
+    }
+
+    /// When the partial frame the last `decode` left in the buffer goes
+    /// stale, if it left one.
+    pub fn partial_deadline(&self) -> Option<Instant> {
+        if self.partial_len > 0 {
+            Some(self.last_progress + Duration::from_millis(Self::INTER_BYTE_TIMEOUT_MS))
+        } else {
+            None
+        }
+    }
+
+    fn discard(&self, src: &mut BytesMut, count: usize) {
+        trace!("Discarding {} byte(s): {:?}", count, &src[..count]);
+        self.errors.discarded_bytes.fetch_add(count, Ordering::Relaxed);
+        src.split_to(count);
+    }
+
+    fn expire_partial_frame(&mut self, src: &mut BytesMut, now: Instant) {
+        let grew = src.len() > self.partial_len;
+
+        if self.partial_deadline().map_or(false, |deadline| now >= deadline) {
+            warn!("Partial frame timed out after {} byte(s)", self.partial_len);
+            self.errors.timed_out_frames.fetch_add(1, Ordering::Relaxed);
+            let stale = self.partial_len;
+            self.discard(src, stale);
+        }
+
+        if grew {
+            self.last_progress = now;
+        }
+        self.partial_len = 0;
+    }
+
+    fn decode_at(&mut self, src: &mut BytesMut, now: Instant) -> Result<Option<InsteonMsg>, io::Error> {
+        self.expire_partial_frame(src, now);
+
+        loop {
+            match src.iter().position(|x| *x == MSG_BEGIN) {
+                Some(0) => (),
+                Some(idx) => self.discard(src, idx),
+                None => {
+                    let len = src.len();
+                    if len > 0 {
+                        self.discard(src, len);
+                    }
+                    return Ok(None)
+                }
+            };
+
+            match InsteonMsg::decode(&src) {
+                Ok(Some((msg, msg_size))) => {
+                    src.split_to(msg_size);
+                    return Ok(Some(msg))
+                },
+                Ok(None) => {
+                    self.partial_len = src.len();
+                    return Ok(None)
+                },
+                Err(e) => {
+                    warn!("Resynchronising after a bad frame: {}", e);
+                    match e {
+                        DecodeError::UnknownType(_) =>
+                            self.errors.unknown_frames.fetch_add(1, Ordering::Relaxed),
+                        _ =>
+                            self.errors.malformed_frames.fetch_add(1, Ordering::Relaxed),
+                    };
+                    self.discard(src, 1);
+                },
+            }
+        }
+    }
+}
 
 impl Decoder for LineCodec {
     type Item = InsteonMsg;
     type Error = io::Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        const COMMAND_START :u8 = 0x02;
-        match src.iter().position(|x| *x == COMMAND_START ) {
-            Some(idx) => src.split_to(idx + 1),
-            None => return Ok(None)
-        };
+        self.decode_at(src, Instant::now())
+    }
+
+    /// A frame still incomplete when the stream ends never will be.
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let msg = self.decode(src)?;
+        if msg.is_none() && !src.is_empty() {
+            warn!("Stream ended {} byte(s) into a frame", src.len());
+            self.errors.timed_out_frames.fetch_add(1, Ordering::Relaxed);
+            let len = src.len();
+            self.discard(src, len);
+            self.partial_len = 0;
+        }
+        Ok(msg)
+    }
+}
+
+impl Encoder for LineCodec {
+    type Item = InsteonMsg;
+    type Error = io::Error;
+
+    fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        item.encode(dst).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+}
 
-        match InsteonMsg::new(&src) {
-            Some((msg, msg_size)) => {
-                src.split_to(msg_size);
-                Ok(Some(msg))
+/// Decodes the frames coming from the PLM.
+///
+/// Unlike a `Framed` reader, which only runs the codec when bytes arrive,
+/// it wakes up when a partial frame goes stale, so that a frame cut short
+/// by the modem is dropped on time instead of when the next one comes in.
+pub struct FrameReader<R> {
+    io: R,
+    codec: LineCodec,
+    buffer: BytesMut,
+    handle: Handle,
+    expiry: Option<(Instant, Timeout)>,
+}
+
+impl<R: AsyncRead> FrameReader<R> {
+    pub fn new(io: R, codec: LineCodec, handle: Handle) -> FrameReader<R> {
+        FrameReader {
+            io: io,
+            codec: codec,
+            buffer: BytesMut::new(),
+            handle: handle,
+            expiry: None,
+        }
+    }
+
+    /// Waits for the partial frame in the buffer to go stale; true once it has.
+    fn poll_expiry(&mut self) -> Result<bool, io::Error> {
+        let deadline = match self.codec.partial_deadline() {
+            Some(deadline) => deadline,
+            None => {
+                self.expiry = None;
+                return Ok(false)
             },
-            None => Ok(None)
+        };
+
+        let armed = match self.expiry {
+            Some((armed_for, _)) => armed_for == deadline,
+            None => false,
+        };
+        if !armed {
+            self.expiry = Some((deadline, Timeout::new_at(deadline, &self.handle)?));
         }
 
+        let fired = match self.expiry {
+            Some((_, ref mut timeout)) => timeout.poll()?.is_ready(),
+            None => false,
+        };
+        if fired {
+            self.expiry = None;
+        }
+        Ok(fired)
     }
 }
 
-impl Encoder for LineCodec {
-    type Item = Vec<u8>;
+impl<R: AsyncRead> Stream for FrameReader<R> {
+    type Item = InsteonMsg;
     type Error = io::Error;
 
-    fn encode(&mut self, _item: Self::Item, _dst: &mut BytesMut) -> Result<(), Self::Error> {
-        _dst.extend_from_slice(&_item);
-        Ok(())
+    fn poll(&mut self) -> Poll<Option<InsteonMsg>, io::Error> {
+        loop {
+            if let Some(msg) = self.codec.decode(&mut self.buffer)? {
+                return Ok(Async::Ready(Some(msg)))
+            }
+
+            self.buffer.reserve(1);
+            match self.io.read_buf(&mut self.buffer)? {
+                Async::Ready(0) => return self.codec.decode_eof(&mut self.buffer).map(Async::Ready),
+                Async::Ready(_) => continue,
+                Async::NotReady => (),
+            }
+
+            if !self.poll_expiry()? {
+                return Ok(Async::NotReady)
+            }
+        }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status_report() -> Vec<u8> {
+        vec![0x02, 0x58, PlmStatus::ACK]
+    }
+
+    fn counts(errors: &FramingErrors) -> (usize, usize, usize, usize) {
+        (errors.discarded_bytes.load(Ordering::Relaxed),
+         errors.unknown_frames.load(Ordering::Relaxed),
+         errors.malformed_frames.load(Ordering::Relaxed),
+         errors.timed_out_frames.load(Ordering::Relaxed))
+    }
+
+    /// Feeds `chunks` to a fresh codec, each one `gap` after the last.
+    fn feed(chunks: &[&[u8]], gap: Duration) -> (Vec<InsteonMsg>, BytesMut, LineCodec) {
+        let mut codec = LineCodec::new();
+        let mut src = BytesMut::new();
+        let mut now = Instant::now();
+        let mut msgs = Vec::new();
+
+        for chunk in chunks {
+            src.extend_from_slice(chunk);
+            while let Some(msg) = codec.decode_at(&mut src, now).unwrap() {
+                msgs.push(msg);
+            }
+            now += gap;
+        }
+        (msgs, src, codec)
+    }
+
+    #[test]
+    fn decodes_back_to_back_frames() {
+        let mut bytes = status_report();
+        bytes.extend_from_slice(&[0x02, 0x65, PlmStatus::ACK]);
+        let (msgs, src, codec) = feed(&[&bytes], Duration::from_millis(0));
+
+        assert_eq!(msgs, vec![InsteonMsg::AllLinkCleanupStatusReport { status_byte: PlmStatus::ACK },
+                              InsteonMsg::CancelAllLinkingEcho { status: PlmStatus::Ack }]);
+        assert!(src.is_empty());
+        assert_eq!(counts(&codec.errors), (0, 0, 0, 0));
+    }
+
+    #[test]
+    fn reassembles_a_frame_split_across_reads() {
+        let bytes = status_report();
+        let (msgs, _, codec) = feed(&[&bytes[..1], &bytes[1..2], &bytes[2..]], Duration::from_millis(10));
+
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(counts(&codec.errors), (0, 0, 0, 0));
+    }
+
+    #[test]
+    fn skips_noise_before_a_frame() {
+        let mut bytes = vec![0x00, 0xFF, 0x15];
+        bytes.extend_from_slice(&status_report());
+        let (msgs, _, codec) = feed(&[&bytes], Duration::from_millis(0));
+
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(counts(&codec.errors), (3, 0, 0, 0));
+    }
+
+    #[test]
+    fn resynchronises_after_bad_frames() {
+        let mut bytes = vec![0x02, 0x42];
+        bytes.extend_from_slice(&[0x02, 0x65, 0x07]);
+        bytes.extend_from_slice(&status_report());
+        let (msgs, _, codec) = feed(&[&bytes], Duration::from_millis(0));
+
+        assert_eq!(msgs, vec![InsteonMsg::AllLinkCleanupStatusReport { status_byte: PlmStatus::ACK }]);
+        // Each bad frame costs its start byte, then the rest of it goes as noise.
+        assert_eq!(counts(&codec.errors), (5, 1, 1, 0));
+    }
+
+    #[test]
+    fn drops_a_stale_partial_frame_when_the_next_one_arrives() {
+        let bytes = status_report();
+        let gap = Duration::from_millis(LineCodec::INTER_BYTE_TIMEOUT_MS + 1);
+        let (msgs, src, codec) = feed(&[&bytes[..2], &bytes], gap);
+
+        assert_eq!(msgs.len(), 1);
+        assert!(src.is_empty());
+        assert_eq!(counts(&codec.errors), (2, 0, 0, 1));
+    }
+
+    #[test]
+    fn drops_a_stale_partial_frame_without_more_bytes() {
+        let bytes = status_report();
+        let (msgs, mut src, mut codec) = feed(&[&bytes[..2]], Duration::from_millis(0));
+        assert!(msgs.is_empty());
+
+        let deadline = codec.partial_deadline().expect("no deadline for a partial frame");
+        assert!(codec.decode_at(&mut src, deadline - Duration::from_millis(1)).unwrap().is_none());
+        assert_eq!(src.len(), 2);
+
+        assert!(codec.decode_at(&mut src, deadline).unwrap().is_none());
+        assert!(src.is_empty());
+        assert_eq!(codec.partial_deadline(), None);
+        assert_eq!(counts(&codec.errors), (2, 0, 0, 1));
+    }
+
+    #[test]
+    fn keeps_a_slow_frame_that_makes_progress() {
+        let bytes = status_report();
+        let gap = Duration::from_millis(LineCodec::INTER_BYTE_TIMEOUT_MS - 50);
+        let (msgs, _, codec) = feed(&[&bytes[..1], &bytes[1..2], &bytes[2..]], gap);
+
+        assert_eq!(msgs.len(), 1);
+        assert_eq!(counts(&codec.errors), (0, 0, 0, 0));
+    }
+
+    #[test]
+    fn drops_a_frame_cut_short_by_the_end_of_the_stream() {
+        let bytes = status_report();
+        let mut codec = LineCodec::new();
+        let mut src = BytesMut::from(&bytes[..2]);
+
+        assert!(codec.decode_eof(&mut src).unwrap().is_none());
+        assert!(src.is_empty());
+        assert_eq!(counts(&codec.errors), (2, 0, 0, 1));
+    }
+}
@@ -0,0 +1,233 @@
+use address::InsteonAddress;
+use insteon_structs::*;
+
+/// Ramp time in seconds for each ramp rate byte, 0x00 to 0x1F.
+static RAMP_RATE_SECONDS: [f32; 32] = [
+    540.0, 480.0, 420.0, 360.0, 300.0, 270.0, 240.0, 210.0,
+    180.0, 150.0, 120.0, 90.0, 60.0, 47.0, 43.0, 38.5,
+    34.0, 32.0, 30.0, 28.0, 26.0, 23.5, 21.5, 19.0,
+    8.5, 6.5, 4.5, 2.0, 0.5, 0.3, 0.2, 0.1,
+];
+
+/// A dimmer ramp rate, stored as the device's 5-bit ramp rate byte.
+#[derive(Debug)]
+#[derive(Copy, Clone, PartialEq)]
+pub struct RampRate(u8);
+
+impl RampRate {
+    pub fn from_byte(byte: u8) -> RampRate {
+        RampRate(byte & 0x1F)
+    }
+
+    /// The rate whose ramp time is closest to `seconds`.
+    pub fn from_seconds(seconds: f32) -> RampRate {
+        let mut best = 0;
+        for (i, rate) in RAMP_RATE_SECONDS.iter().enumerate() {
+            if (rate - seconds).abs() < (RAMP_RATE_SECONDS[best] - seconds).abs() {
+                best = i;
+            }
+        }
+        RampRate(best as u8)
+    }
+
+    pub fn seconds(&self) -> f32 {
+        RAMP_RATE_SECONDS[self.0 as usize]
+    }
+
+    pub fn byte(&self) -> u8 {
+        self.0
+    }
+
+    /// OnAtRate/OffAtRate only carry 4 bits of ramp rate, meaning 2 * n + 1.
+    fn nibble(&self) -> u8 {
+        self.0 >> 1
+    }
+}
+
+#[derive(Debug)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum Direction {
+    Up,
+    Down,
+}
+
+/// The lighting commands every dimmer and switch understands. Levels are
+/// percentages.
+#[derive(Debug)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum DeviceCommand {
+    On { level: u32 },
+    FastOn,
+    Off,
+    FastOff,
+    BrightStep,
+    DimStep,
+    StartChange(Direction),
+    StopChange,
+    OnAtRate { level: u32, ramp: RampRate },
+    OffAtRate { ramp: RampRate },
+}
+
+/// Scales a 0-100 percentage to the 0-255 on-level devices use.
+pub fn percent_to_level(percent: u32) -> u8 {
+    let scale = percent.min(100) as f64 / 100.0;
+    (scale * 255.0).round() as u8
+}
+
+pub fn level_to_percent(level: u8) -> u32 {
+    (level as f64 * 100.0 / 255.0).round() as u32
+}
+
+/// The on-level nibble of OnAtRate. The device turns it back into
+/// nibble * 16 + 0x0F, so this picks the nibble landing closest to `percent`.
+fn on_level_nibble(percent: u32) -> u8 {
+    let level = percent_to_level(percent) as f64;
+    ((level - 15.0) / 16.0).round().max(0.0).min(15.0) as u8
+}
+
+impl DeviceCommand {
+    pub fn cmd1(&self) -> u8 {
+        u8_command(match *self {
+            DeviceCommand::On { .. } => Command::On,
+            DeviceCommand::FastOn => Command::FastOn,
+            DeviceCommand::Off => Command::Off,
+            DeviceCommand::FastOff => Command::FastOff,
+            DeviceCommand::BrightStep => Command::BrightStep,
+            DeviceCommand::DimStep => Command::DimStep,
+            DeviceCommand::StartChange(_) => Command::StartChange,
+            DeviceCommand::StopChange => Command::StopChange,
+            // Even nibble 0 lights the load, so a ramp to 0% is a ramp off.
+            DeviceCommand::OnAtRate { level: 0, .. } => Command::OffAtRate,
+            DeviceCommand::OnAtRate { .. } => Command::OnAtRate,
+            DeviceCommand::OffAtRate { .. } => Command::OffAtRate,
+        })
+    }
+
+    pub fn cmd2(&self) -> u8 {
+        match *self {
+            DeviceCommand::On { level } => percent_to_level(level),
+            DeviceCommand::FastOn => 0xFF,
+            DeviceCommand::StartChange(Direction::Up) => 0x01,
+            DeviceCommand::StartChange(Direction::Down) => 0x00,
+            DeviceCommand::OnAtRate { level: 0, ramp } => ramp.nibble(),
+            // Bits 4-7 hold the on-level nibble, bits 0-3 the ramp rate.
+            DeviceCommand::OnAtRate { level, ramp } =>
+                (on_level_nibble(level) << 4) | ramp.nibble(),
+            DeviceCommand::OffAtRate { ramp } => ramp.nibble(),
+            DeviceCommand::Off | DeviceCommand::FastOff | DeviceCommand::BrightStep |
+            DeviceCommand::DimStep | DeviceCommand::StopChange => 0x00,
+        }
+    }
+
+    pub fn to_msg(&self, device: InsteonAddress) -> InsteonMsg {
+        InsteonMsg::SendStandardMsg {
+            addr_to: device,
+            msg_flags: MessageFlags::direct(),
+            cmd1: self.cmd1(),
+            cmd2: self.cmd2(),
+        }
+    }
+}
+
+/// A device's answer to a status request: the ACK carries the ALDB delta in
+/// cmd1 and the current on-level in cmd2.
+#[derive(Debug)]
+#[derive(Copy, Clone, PartialEq)]
+pub struct StatusReport {
+    pub level: u8,
+    pub aldb_delta: u8,
+}
+
+impl StatusReport {
+    pub fn from_ack(msg: &InsteonMsg, device: InsteonAddress) -> Option<StatusReport> {
+        msg.direct_ack_from(device).map(|(cmd1, cmd2)| StatusReport {
+            level: cmd2,
+            aldb_delta: cmd1,
+        })
+    }
+
+    pub fn level_percent(&self) -> u32 {
+        level_to_percent(self.level)
+    }
+}
+
+pub fn status_request(device: InsteonAddress) -> InsteonMsg {
+    InsteonMsg::SendStandardMsg {
+        addr_to: device,
+        msg_flags: MessageFlags::direct(),
+        cmd1: u8_command(Command::StatusReq),
+        cmd2: 0x00,
+    }
+}
+
+/// Asks a device to answer with its SET-button broadcast, which carries its
+/// category, subcategory and firmware.
+pub fn id_request(device: InsteonAddress) -> InsteonMsg {
+    InsteonMsg::SendStandardMsg {
+        addr_to: device,
+        msg_flags: MessageFlags::direct(),
+        cmd1: u8_command(Command::IdReq),
+        cmd2: 0x00,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn a() -> InsteonAddress {
+        InsteonAddress::new(0x1A, 0xD0, 0xF4)
+    }
+
+    #[test]
+    fn picks_the_nearest_ramp_rate() {
+        assert_eq!(RampRate::from_seconds(0.5), RampRate::from_byte(0x1C));
+        assert_eq!(RampRate::from_seconds(2.2), RampRate::from_byte(0x1B));
+        assert_eq!(RampRate::from_seconds(1000.0), RampRate::from_byte(0x00));
+        assert_eq!(RampRate::from_seconds(0.0), RampRate::from_byte(0x1F));
+    }
+
+    #[test]
+    fn scales_percentages_to_levels() {
+        assert_eq!(percent_to_level(0), 0x00);
+        assert_eq!(percent_to_level(50), 0x80);
+        assert_eq!(percent_to_level(100), 0xFF);
+        assert_eq!(percent_to_level(250), 0xFF);
+        assert_eq!(level_to_percent(0x80), 50);
+    }
+
+    #[test]
+    fn on_at_rate_encodes_the_nearest_on_level_nibble() {
+        let ramp = RampRate::from_byte(0x1B);
+        let cmd2 = |level| DeviceCommand::OnAtRate { level: level, ramp: ramp }.cmd2();
+
+        // 0x7F is the level nibble 7 comes back as, the closest to 50%.
+        assert_eq!(cmd2(50), 0x7D);
+        assert_eq!(cmd2(100), 0xFD);
+        assert_eq!(cmd2(1), 0x0D);
+    }
+
+    #[test]
+    fn on_at_rate_to_zero_ramps_off() {
+        let ramp = RampRate::from_byte(0x1B);
+        let msg = DeviceCommand::OnAtRate { level: 0, ramp: ramp }.to_msg(a());
+        assert_eq!(msg, DeviceCommand::OffAtRate { ramp: ramp }.to_msg(a()));
+
+        match msg {
+            InsteonMsg::SendStandardMsg { cmd1, cmd2, .. } => {
+                assert_eq!(cmd1, u8_command(Command::OffAtRate));
+                assert_eq!(cmd2, 0x0D);
+            },
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn encodes_the_plain_commands() {
+        let bytes = |cmd: DeviceCommand| (cmd.cmd1(), cmd.cmd2());
+        assert_eq!(bytes(DeviceCommand::On { level: 100 }), (0x11, 0xFF));
+        assert_eq!(bytes(DeviceCommand::FastOff), (0x14, 0x00));
+        assert_eq!(bytes(DeviceCommand::StartChange(Direction::Up)), (0x17, 0x01));
+        assert_eq!(bytes(DeviceCommand::StopChange), (0x18, 0x00));
+    }
+}
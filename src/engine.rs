@@ -0,0 +1,219 @@
+use address::InsteonAddress;
+use checksum::Checksum;
+use insteon_structs::*;
+use registry::Identity;
+
+/// The Insteon engine a device runs, as reported by an engine version request.
+#[derive(Debug)]
+#[derive(Copy, Clone)]
+#[derive(Serialize, Deserialize, PartialEq)]
+pub enum EngineVersion {
+    I1,
+    I2,
+    I2cs,
+}
+
+/// How extended operations, such as ALDB access, must be phrased for a device.
+#[derive(Debug)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum Dialect {
+    /// i1 devices only understand standard messages, so memory is accessed
+    /// one byte at a time with SetHiAddr/PeekEE/PokeEE.
+    PeekPoke,
+    Extended,
+    /// i2cs devices drop extended messages without a valid checksum.
+    ChecksummedExtended(Checksum),
+}
+
+impl Dialect {
+    pub fn checksum(&self) -> Option<Checksum> {
+        match *self {
+            Dialect::ChecksummedExtended(checksum) => Some(checksum),
+            _ => None,
+        }
+    }
+
+    /// A direct extended message phrased in this dialect, or `None` for i1
+    /// devices that cannot receive one.
+    pub fn extended(&self, device: InsteonAddress, cmd1: u8, cmd2: u8, user_data: [u8; 14])
+        -> Option<InsteonMsg> {
+        match *self {
+            Dialect::PeekPoke => None,
+            _ => Some(InsteonMsg::send_extended(device, cmd1, cmd2, user_data, self.checksum())),
+        }
+    }
+}
+
+impl EngineVersion {
+    /// An i2cs device that is not linked to the modem NAKs the request with 0xFF.
+    const NOT_LINKED :u8 = 0xFF;
+
+    pub fn from_cmd2(cmd2: u8) -> Option<EngineVersion> {
+        match cmd2 {
+            0x00 => Some(EngineVersion::I1),
+            0x01 => Some(EngineVersion::I2),
+            0x02 => Some(EngineVersion::I2cs),
+            _ => None,
+        }
+    }
+
+    pub fn from_reply(msg: &InsteonMsg, device: InsteonAddress) -> Option<EngineVersion> {
+        let engine_ver = u8_command(Command::InsteonVer);
+        match *msg {
+            InsteonMsg::StandardMsg { addr_from, msg_flags, cmd1, cmd2, .. }
+                if addr_from == device && cmd1 == engine_ver => match msg_flags.msg_type {
+                    MessageType::DirectAck => EngineVersion::from_cmd2(cmd2),
+                    MessageType::DirectNak if cmd2 == EngineVersion::NOT_LINKED =>
+                        Some(EngineVersion::I2cs),
+                    _ => None,
+                },
+            _ => None,
+        }
+    }
+
+    /// The dialect of a device running this engine. Which checksum an i2cs
+    /// device checks depends on the product: thermostats want the CRC, the
+    /// rest the classic checksum.
+    pub fn dialect(&self, identity: Option<Identity>) -> Dialect {
+        match *self {
+            EngineVersion::I1 => Dialect::PeekPoke,
+            EngineVersion::I2 => Dialect::Extended,
+            EngineVersion::I2cs => match identity {
+                Some(identity) if identity.category == CLIMATE_CONTROL_CATEGORY =>
+                    Dialect::ChecksummedExtended(Checksum::Crc),
+                _ => Dialect::ChecksummedExtended(Checksum::Classic),
+            },
+        }
+    }
+}
+
+const CLIMATE_CONTROL_CATEGORY :u8 = 0x05;
+
+fn direct(device: InsteonAddress, cmd: Command, cmd2: u8) -> InsteonMsg {
+    InsteonMsg::SendStandardMsg {
+        addr_to: device,
+        msg_flags: MessageFlags::direct(),
+        cmd1: u8_command(cmd),
+        cmd2: cmd2,
+    }
+}
+
+pub fn engine_version_request(device: InsteonAddress) -> InsteonMsg {
+    direct(device, Command::InsteonVer, 0x00)
+}
+
+/// Points an i1 device's memory accesses at the page holding `mem_addr`.
+pub fn set_msb_request(device: InsteonAddress, mem_addr: u16) -> InsteonMsg {
+    direct(device, Command::SetHiAddr, (mem_addr >> 8) as u8)
+}
+
+/// Reads the byte at `mem_addr` in the page the last SetHiAddr chose; the
+/// ACK carries it in cmd2. It also sets the address the next PokeEE writes.
+pub fn peek_request(device: InsteonAddress, mem_addr: u16) -> InsteonMsg {
+    direct(device, Command::PeekEE, mem_addr as u8)
+}
+
+pub fn peeked_byte(msg: &InsteonMsg, device: InsteonAddress) -> Option<u8> {
+    match *msg {
+        InsteonMsg::StandardMsg { addr_from, msg_flags, cmd1, cmd2, .. }
+            if addr_from == device && msg_flags.msg_type == MessageType::DirectAck &&
+               cmd1 == u8_command(Command::PeekEE) => Some(cmd2),
+        _ => None,
+    }
+}
+
+/// Writes `value` at the address the last SetHiAddr and PeekEE chose.
+pub fn poke_request(device: InsteonAddress, value: u8) -> InsteonMsg {
+    direct(device, Command::PokeEE, value)
+}
+
+/// The standard messages that read the `len` bytes at `mem_addr` of an i1
+/// device, none of which may cross a page.
+pub fn peek_requests(device: InsteonAddress, mem_addr: u16, len: u16) -> Vec<InsteonMsg> {
+    let mut msgs = vec![set_msb_request(device, mem_addr)];
+    msgs.extend((0..len).map(|i| peek_request(device, mem_addr + i)));
+    msgs
+}
+
+/// The standard messages that write `data` at `mem_addr` of an i1 device,
+/// each byte a PeekEE choosing the address followed by the PokeEE.
+pub fn poke_requests(device: InsteonAddress, mem_addr: u16, data: &[u8]) -> Vec<InsteonMsg> {
+    let mut msgs = vec![set_msb_request(device, mem_addr)];
+    for (i, value) in data.iter().enumerate() {
+        msgs.push(peek_request(device, mem_addr + i as u16));
+        msgs.push(poke_request(device, *value));
+    }
+    msgs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn a() -> InsteonAddress {
+        InsteonAddress::new(0x1A, 0xD0, 0xF4)
+    }
+
+    fn bytes(msgs: &[InsteonMsg]) -> Vec<(u8, u8)> {
+        msgs.iter().map(|msg| match *msg {
+            InsteonMsg::SendStandardMsg { addr_to, msg_flags, cmd1, cmd2 } => {
+                assert_eq!(addr_to, a());
+                assert_eq!(msg_flags, MessageFlags::direct());
+                (cmd1, cmd2)
+            },
+            ref other => panic!("{:?}", other),
+        }).collect()
+    }
+
+    fn identity(category: u8, subcategory: u8) -> Option<Identity> {
+        Some(Identity { category: category, subcategory: subcategory, firmware: 0x45 })
+    }
+
+    #[test]
+    fn peeks_a_record_after_setting_the_page() {
+        assert_eq!(bytes(&peek_requests(a(), 0x0FF8, 3)),
+                   vec![(0x28, 0x0F), (0x2B, 0xF8), (0x2B, 0xF9), (0x2B, 0xFA)]);
+    }
+
+    #[test]
+    fn pokes_each_byte_after_peeking_its_address() {
+        assert_eq!(bytes(&poke_requests(a(), 0x0FF0, &[0xE2, 0x01])),
+                   vec![(0x28, 0x0F), (0x2B, 0xF0), (0x29, 0xE2), (0x2B, 0xF1), (0x29, 0x01)]);
+    }
+
+    #[test]
+    fn reads_the_peeked_byte_from_the_ack() {
+        let ack = |cmd1, msg_type| InsteonMsg::StandardMsg {
+            addr_from: a(), addr_to: InsteonAddress::new(0x44, 0x85, 0x11),
+            msg_flags: MessageFlags::new(msg_type, false), cmd1: cmd1, cmd2: 0xA2 };
+
+        assert_eq!(peeked_byte(&ack(0x2B, MessageType::DirectAck), a()), Some(0xA2));
+        assert_eq!(peeked_byte(&ack(0x28, MessageType::DirectAck), a()), None);
+        assert_eq!(peeked_byte(&ack(0x2B, MessageType::DirectNak), a()), None);
+    }
+
+    #[test]
+    fn picks_the_checksum_by_product() {
+        let thermostat = identity(0x05, 0x0B);
+        let keypad = identity(0x01, 0x41);
+        let iolinc = identity(0x07, 0x00);
+
+        assert_eq!(EngineVersion::I2cs.dialect(thermostat), Dialect::ChecksummedExtended(Checksum::Crc));
+        assert_eq!(EngineVersion::I2cs.dialect(keypad), Dialect::ChecksummedExtended(Checksum::Classic));
+        assert_eq!(EngineVersion::I2cs.dialect(iolinc), Dialect::ChecksummedExtended(Checksum::Classic));
+        assert_eq!(EngineVersion::I2cs.dialect(None), Dialect::ChecksummedExtended(Checksum::Classic));
+        assert_eq!(EngineVersion::I2.dialect(thermostat), Dialect::Extended);
+        assert_eq!(EngineVersion::I1.dialect(keypad), Dialect::PeekPoke);
+    }
+
+    #[test]
+    fn i1_devices_cannot_be_sent_extended_messages() {
+        assert_eq!(Dialect::PeekPoke.extended(a(), 0x2E, 0x00, [0; 14]), None);
+
+        match Dialect::ChecksummedExtended(Checksum::Crc).extended(a(), 0x2E, 0x00, [0; 14]) {
+            Some(InsteonMsg::SendExtendedMsg { user_data, .. }) =>
+                assert!(Checksum::Crc.verify(0x2E, 0x00, &user_data)),
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use address::InsteonAddress;
+use engine::Dialect;
+use insteon_structs::*;
+
+/// The FanLinc's light answers to group 1 like any dimmer; the fan is group 2.
+pub const FAN_GROUP :u8 = 0x02;
+
+/// cmd2 of a status request that asks for the fan speed instead of the light level.
+const FAN_STATUS :u8 = 0x03;
+
+#[derive(Debug)]
+#[derive(Copy, Clone)]
+#[derive(Serialize, Deserialize, PartialEq)]
+pub enum FanSpeed {
+    Off,
+    Low,
+    Medium,
+    High,
+}
+
+impl FanSpeed {
+    pub fn level(&self) -> u8 {
+        match *self {
+            FanSpeed::Off => 0x00,
+            FanSpeed::Low => 0x55,
+            FanSpeed::Medium => 0xAA,
+            FanSpeed::High => 0xFF,
+        }
+    }
+
+    /// The speed a reported fan level stands for: any level but 0 means the
+    /// fan runs, at the speed whose level is nearest.
+    pub fn from_level(level: u8) -> FanSpeed {
+        match level {
+            0x00 => FanSpeed::Off,
+            0x01...0x7F => FanSpeed::Low,
+            0x80...0xD4 => FanSpeed::Medium,
+            _ => FanSpeed::High,
+        }
+    }
+}
+
+/// An extended On carrying the speed in cmd2 and the fan group in D1, or
+/// `None` for i1 devices that cannot receive one.
+pub fn speed_request(dialect: Dialect, device: InsteonAddress, speed: FanSpeed)
+    -> Option<InsteonMsg> {
+    let mut user_data = [0u8; 14];
+    user_data[0] = FAN_GROUP;
+    dialect.extended(device, u8_command(Command::On), speed.level(), user_data)
+}
+
+/// Asks for the fan speed; the ACK carries the fan level in cmd2.
+pub fn fan_status_request(device: InsteonAddress) -> InsteonMsg {
+    InsteonMsg::SendStandardMsg {
+        addr_to: device,
+        msg_flags: MessageFlags::direct(),
+        cmd1: u8_command(Command::StatusReq),
+        cmd2: FAN_STATUS,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use checksum::Checksum;
+
+    #[test]
+    fn reads_the_speed_nearest_a_level() {
+        for &speed in &[FanSpeed::Off, FanSpeed::Low, FanSpeed::Medium, FanSpeed::High] {
+            assert_eq!(FanSpeed::from_level(speed.level()), speed);
+        }
+        assert_eq!(FanSpeed::from_level(0x01), FanSpeed::Low);
+        assert_eq!(FanSpeed::from_level(0x7F), FanSpeed::Low);
+        assert_eq!(FanSpeed::from_level(0x80), FanSpeed::Medium);
+        assert_eq!(FanSpeed::from_level(0xD4), FanSpeed::Medium);
+        assert_eq!(FanSpeed::from_level(0xD5), FanSpeed::High);
+    }
+
+    #[test]
+    fn sets_the_speed_with_an_extended_on_to_the_fan_group() {
+        let device = InsteonAddress::new(0x2D, 0x44, 0x1B);
+        match speed_request(Dialect::ChecksummedExtended(Checksum::Classic), device, FanSpeed::Medium) {
+            Some(InsteonMsg::SendExtendedMsg { cmd1, cmd2, user_data, .. }) => {
+                assert_eq!((cmd1, cmd2), (0x11, 0xAA));
+                assert_eq!(user_data[0], FAN_GROUP);
+                assert!(Checksum::Classic.verify(cmd1, cmd2, &user_data));
+            },
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(speed_request(Dialect::PeekPoke, device, FanSpeed::High), None);
+    }
+}
@@ -0,0 +1,223 @@
+use std::error;
+use std::fmt;
+
+/// How long a door takes to open or close when nothing says otherwise.
+pub const DEFAULT_TRAVEL_SEC :u32 = 20;
+
+/// The IOLinc input wired to the door sensor.
+pub const SENSOR_INPUT :u8 = 0;
+
+#[derive(Debug)]
+#[derive(Copy, Clone)]
+#[derive(Serialize, Deserialize, PartialEq)]
+pub enum DoorState {
+    Unknown,
+    Closed,
+    Open,
+    Opening,
+    Closing,
+}
+
+#[derive(Debug)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum GarageError {
+    AlreadyOpen,
+    AlreadyClosed,
+}
+
+impl fmt::Display for GarageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GarageError::AlreadyOpen => write!(f, "the door is already open"),
+            GarageError::AlreadyClosed => write!(f, "the door is already closed"),
+        }
+    }
+}
+
+impl error::Error for GarageError {
+    fn description(&self) -> &str {
+        "garage door command refused"
+    }
+}
+
+/// A garage door driven by an IOLinc: the relay pulses the opener and a
+/// sensor on input 0 tells whether the door is fully closed.
+#[derive(Debug)]
+#[derive(Copy, Clone)]
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct GarageDoor {
+    pub sensor_on_when_closed: bool,
+    pub travel_sec: u32,
+    pub state: DoorState,
+    /// Set when the door did not reach the state it was sent to in time.
+    pub fault: bool,
+    /// What the sensor said since the door last moved, if anything.
+    pub sensor_closed: Option<bool>,
+    /// Counts the moves, so that `travel_timeout` can tell a stale timer.
+    pub moves: usize,
+}
+
+impl GarageDoor {
+    pub fn new(sensor_on_when_closed: bool, travel_sec: u32) -> GarageDoor {
+        GarageDoor {
+            sensor_on_when_closed: sensor_on_when_closed,
+            travel_sec: if travel_sec == 0 { DEFAULT_TRAVEL_SEC } else { travel_sec },
+            state: DoorState::Unknown,
+            fault: false,
+            sensor_closed: None,
+            moves: 0,
+        }
+    }
+
+    /// Refuses to send the door where it already is or is already going.
+    pub fn check(&self, open: bool) -> Result<(), GarageError> {
+        match (open, self.state) {
+            (true, DoorState::Open) | (true, DoorState::Opening) => Err(GarageError::AlreadyOpen),
+            (false, DoorState::Closed) | (false, DoorState::Closing) => Err(GarageError::AlreadyClosed),
+            _ => Ok(()),
+        }
+    }
+
+    /// Records that the relay was pulsed, returning the move to time out.
+    pub fn start(&mut self, open: bool) -> usize {
+        self.state = if open { DoorState::Opening } else { DoorState::Closing };
+        self.fault = false;
+        self.sensor_closed = None;
+        self.moves += 1;
+        self.moves
+    }
+
+    pub fn on_sensor(&mut self, sensor_on: bool) {
+        let closed = sensor_on == self.sensor_on_when_closed;
+        self.sensor_closed = Some(closed);
+        self.state = match (self.state, closed) {
+            (_, true) => DoorState::Closed,
+            // The sensor only sees the closed position, so a moving door
+            // stays moving until it closes or its travel time is over.
+            (DoorState::Opening, false) => DoorState::Opening,
+            (DoorState::Closing, false) => DoorState::Closing,
+            (_, false) => DoorState::Open,
+        };
+        if closed {
+            self.fault = false;
+        }
+    }
+
+    /// Called once the travel time of `moves` is over. A door the sensor said
+    /// nothing about during its travel is in an unknown state.
+    pub fn travel_timeout(&mut self, moves: usize) {
+        if moves != self.moves {
+            return
+        }
+
+        let (target, reached) = match self.state {
+            DoorState::Opening => ("open", DoorState::Open),
+            DoorState::Closing => ("close", DoorState::Closed),
+            _ => return,
+        };
+
+        self.state = match self.sensor_closed {
+            Some(true) => DoorState::Closed,
+            Some(false) => DoorState::Open,
+            None => DoorState::Unknown,
+        };
+
+        if self.state != reached {
+            warn!("Garage door did not {} within {}s", target, self.travel_sec);
+            self.fault = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn closed_door() -> GarageDoor {
+        let mut door = GarageDoor::new(true, 0);
+        door.on_sensor(true);
+        door
+    }
+
+    #[test]
+    fn follows_the_sensor() {
+        let mut door = GarageDoor::new(false, 0);
+        assert_eq!(door.travel_sec, DEFAULT_TRAVEL_SEC);
+        assert_eq!(door.state, DoorState::Unknown);
+
+        door.on_sensor(false);
+        assert_eq!(door.state, DoorState::Closed);
+        door.on_sensor(true);
+        assert_eq!(door.state, DoorState::Open);
+    }
+
+    #[test]
+    fn refuses_to_send_the_door_where_it_is_going() {
+        let mut door = closed_door();
+        assert_eq!(door.check(false), Err(GarageError::AlreadyClosed));
+        assert_eq!(door.check(true), Ok(()));
+
+        door.start(true);
+        assert_eq!(door.check(true), Err(GarageError::AlreadyOpen));
+        assert_eq!(door.check(false), Ok(()));
+    }
+
+    #[test]
+    fn opens_once_the_sensor_left_closed_and_the_travel_is_over() {
+        let mut door = closed_door();
+        let moves = door.start(true);
+        door.on_sensor(false);
+        assert_eq!(door.state, DoorState::Opening);
+
+        door.travel_timeout(moves);
+        assert_eq!(door.state, DoorState::Open);
+        assert!(!door.fault);
+    }
+
+    #[test]
+    fn closes_as_soon_as_the_sensor_says_so() {
+        let mut door = GarageDoor::new(true, 0);
+        door.on_sensor(false);
+        assert_eq!(door.state, DoorState::Open);
+        let moves = door.start(false);
+        door.on_sensor(true);
+        assert_eq!(door.state, DoorState::Closed);
+
+        door.travel_timeout(moves);
+        assert_eq!(door.state, DoorState::Closed);
+        assert!(!door.fault);
+    }
+
+    #[test]
+    fn a_door_that_did_not_close_in_time_is_a_fault() {
+        let mut door = GarageDoor::new(true, 0);
+        let moves = door.start(false);
+        door.on_sensor(false);
+
+        door.travel_timeout(moves);
+        assert_eq!(door.state, DoorState::Open);
+        assert!(door.fault);
+
+        door.on_sensor(true);
+        assert_eq!(door.state, DoorState::Closed);
+        assert!(!door.fault);
+    }
+
+    #[test]
+    fn a_silent_sensor_leaves_the_door_unknown() {
+        let mut door = closed_door();
+        let moves = door.start(false);
+        door.travel_timeout(moves);
+        assert_eq!(door.state, DoorState::Unknown);
+        assert!(door.fault);
+    }
+
+    #[test]
+    fn ignores_the_timer_of_an_earlier_move() {
+        let mut door = closed_door();
+        let first = door.start(true);
+        door.start(false);
+        door.travel_timeout(first);
+        assert_eq!(door.state, DoorState::Closing);
+    }
+}
@@ -0,0 +1,370 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use robots::actors::{Actor, ActorCell, ActorContext, ActorRef, Any};
+
+use address::InsteonAddress;
+use device_control::DeviceCommand;
+use insteon_structs::*;
+use timer;
+
+/// How long to wait for the modem to report the end of the cleanup phase.
+pub const GROUP_CLEANUP_TIMEOUT_MS :u64 = 15000;
+pub const CLEANUP_RETRY_INTERVAL_MS :u64 = 1000;
+/// Direct cleanups sent to each responder the modem's own cleanup missed.
+pub const CLEANUP_RETRIES :usize = 3;
+
+/// An ALL-Link broadcast of `cmd` to every responder of `group`.
+pub fn group_command(group: u8, cmd: DeviceCommand) -> InsteonMsg {
+    InsteonMsg::SendAllLinkCmd {
+        all_link_group: group,
+        cmd1: cmd.cmd1(),
+        cmd2: cmd.cmd2(),
+    }
+}
+
+/// The direct cleanup message that asks `device` to apply `cmd1` for `group`.
+pub fn direct_cleanup(device: InsteonAddress, group: u8, cmd1: u8) -> InsteonMsg {
+    InsteonMsg::SendStandardMsg {
+        addr_to: device,
+        msg_flags: MessageFlags::new(MessageType::GroupCleanup, false),
+        cmd1: cmd1,
+        cmd2: group,
+    }
+}
+
+/// What the cleanup phase of a group command revealed about its responders.
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct GroupCleanup {
+    pub group: u8,
+    /// Set once the modem reported the end of the cleanup phase without
+    /// aborting it.
+    pub finished: bool,
+    pub acknowledged: Vec<InsteonAddress>,
+    pub failed: Vec<InsteonAddress>,
+    /// Responders that failed the modem's cleanup but acknowledged one of
+    /// our direct retries; they are listed in `acknowledged` too.
+    pub recovered: Vec<InsteonAddress>,
+}
+
+impl GroupCleanup {
+    /// The responder that acknowledged a cleanup of `cmd1` for this group.
+    pub fn acknowledger(&self, msg: &InsteonMsg, cmd1: u8) -> Option<InsteonAddress> {
+        match *msg {
+            InsteonMsg::StandardMsg { addr_from, msg_flags, cmd1: ack_cmd1, cmd2, .. }
+                if msg_flags.msg_type == MessageType::GroupCleanupAck &&
+                   ack_cmd1 == cmd1 && cmd2 == self.group => Some(addr_from),
+            _ => None,
+        }
+    }
+
+    pub fn new(group: u8) -> GroupCleanup {
+        GroupCleanup {
+            group: group,
+            finished: false,
+            acknowledged: Vec::new(),
+            failed: Vec::new(),
+            recovered: Vec::new(),
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        self.finished && self.failed.is_empty()
+    }
+
+    /// Moves `device` from the failed responders to the acknowledged ones.
+    pub fn recover(&mut self, device: InsteonAddress) {
+        self.failed.retain(|&addr| addr != device);
+        self.recovered.push(device);
+        if !self.acknowledged.contains(&device) {
+            self.acknowledged.push(device);
+        }
+    }
+
+    /// Records `msg` if it belongs to the cleanup of `cmd1`, returning true
+    /// once the modem reports that the cleanup phase is over.
+    pub fn observe(&mut self, msg: &InsteonMsg, cmd1: u8) -> bool {
+        if let Some(addr_from) = self.acknowledger(msg, cmd1) {
+            if !self.acknowledged.contains(&addr_from) {
+                self.acknowledged.push(addr_from);
+            }
+            return false
+        }
+
+        match *msg {
+            InsteonMsg::AllLinkCleanupFailureReport { all_link_group, id, .. }
+                if all_link_group == self.group => {
+                warn!("{} did not acknowledge the cleanup of group {}", id, self.group);
+                if !self.failed.contains(&id) {
+                    self.failed.push(id);
+                }
+                false
+            },
+
+            InsteonMsg::AllLinkCleanupStatusReport { status_byte } => {
+                self.finished = status_byte == PlmStatus::ACK;
+                if !self.finished {
+                    warn!("Cleanup of group {} aborted due to traffic", self.group);
+                }
+                true
+            },
+
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum GroupActorMsg {
+    Send(ActorRef),
+    Timeout,
+    RetryTimeout(InsteonAddress, usize),
+}
+
+struct GroupState {
+    future: ActorRef,
+    cleanup: GroupCleanup,
+    /// Failed responders still waiting for a direct cleanup, once the
+    /// modem's cleanup phase is over or has timed out.
+    retries: VecDeque<InsteonAddress>,
+    /// The responder a direct cleanup is in flight for, and its attempts.
+    retrying: Option<(InsteonAddress, usize)>,
+}
+
+/// Broadcasts one group command, follows its cleanup phase, then sends
+/// direct cleanups to every responder the modem reported as failed.
+pub struct GroupActor {
+    ser_tx_actor : ActorRef,
+    group        : u8,
+    cmd          : DeviceCommand,
+    state        : Mutex<Option<GroupState>>,
+}
+
+impl GroupActor {
+    pub fn new(tuple: (ActorRef, u8, DeviceCommand)) -> GroupActor {
+        let (ser_tx_actor, group, cmd) = tuple;
+        GroupActor {
+            ser_tx_actor: ser_tx_actor,
+            group: group,
+            cmd: cmd,
+            state: Mutex::new(None),
+        }
+    }
+
+    fn send(&self, msg: InsteonMsg) {
+        self.ser_tx_actor.tell_to(self.ser_tx_actor.clone(), ActorMsg::Send(msg));
+    }
+
+    fn retry(&self, device: InsteonAddress, attempts: usize, state: &mut GroupState,
+             context: &ActorCell) {
+        debug!("Direct cleanup of group {} for {}, attempt {}", self.group, device, attempts + 1);
+        state.retrying = Some((device, attempts));
+        self.send(direct_cleanup(device, self.group, self.cmd.cmd1()));
+        timer::schedule(context.actor_ref(), GroupActorMsg::RetryTimeout(device, attempts),
+                        Duration::from_millis(CLEANUP_RETRY_INTERVAL_MS));
+    }
+
+    /// Starts on the next failed responder, returning false once none is left.
+    fn retry_next(&self, state: &mut GroupState, context: &ActorCell) -> bool {
+        match state.retries.pop_front() {
+            Some(device) => {
+                self.retry(device, 0, state, context);
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn finish(&self, state: GroupState, context: &ActorCell) {
+        let cleanup = state.cleanup;
+        info!("Group {} cleanup: {} acknowledged ({} after a retry), {} failed", cleanup.group,
+              cleanup.acknowledged.len(), cleanup.recovered.len(), cleanup.failed.len());
+        for device in &cleanup.failed {
+            warn!("{} never applied the group {} command", device, cleanup.group);
+        }
+        context.complete(state.future, cleanup);
+        context.kill_me();
+    }
+
+    pub fn handle_insteon_msg(&self, message: InsteonMsg, context: ActorCell) {
+        let mut interior = self.state.lock().unwrap();
+
+        let done = match *interior {
+            Some(ref mut state) => match state.retrying.clone() {
+                Some((device, _)) => {
+                    let acked = state.cleanup.acknowledger(&message, self.cmd.cmd1()) == Some(device) ||
+                        message.direct_ack_from(device) == Some((self.cmd.cmd1(), self.group));
+                    if acked {
+                        info!("{} acknowledged the direct cleanup of group {}", device, self.group);
+                        state.cleanup.recover(device);
+                        state.retrying = None;
+                        !self.retry_next(state, &context)
+                    } else {
+                        false
+                    }
+                },
+                None => {
+                    let cmd1 = self.cmd.cmd1();
+                    if state.cleanup.observe(&message, cmd1) {
+                        state.retries = state.cleanup.failed.iter().cloned().collect();
+                        !self.retry_next(state, &context)
+                    } else {
+                        false
+                    }
+                },
+            },
+            None => false,
+        };
+
+        if done {
+            let state = interior.take().unwrap();
+            self.finish(state, &context);
+        }
+    }
+
+    pub fn handle_group_msg(&self, message: GroupActorMsg, context: ActorCell) {
+        let mut interior = self.state.lock().unwrap();
+
+        let done = match message {
+            GroupActorMsg::Send(future) => {
+                *interior = Some(GroupState {
+                    future: future,
+                    cleanup: GroupCleanup::new(self.group),
+                    retries: VecDeque::new(),
+                    retrying: None,
+                });
+                self.send(group_command(self.group, self.cmd));
+                timer::schedule(context.actor_ref(), GroupActorMsg::Timeout,
+                                Duration::from_millis(GROUP_CLEANUP_TIMEOUT_MS));
+                false
+            },
+
+            // Without a status report the cleanup phase is over all the same.
+            // Only the responders the modem reported as failed get direct
+            // retries; any it never reached are not known here.
+            GroupActorMsg::Timeout => match *interior {
+                Some(ref mut state) if state.retrying.is_none() && state.retries.is_empty() => {
+                    warn!("The modem never reported the end of the group {} cleanup", self.group);
+                    state.retries = state.cleanup.failed.iter().cloned().collect();
+                    !self.retry_next(state, &context)
+                },
+                _ => false,
+            },
+
+            GroupActorMsg::RetryTimeout(device, attempts) => match *interior {
+                Some(ref mut state) if state.retrying == Some((device, attempts)) => {
+                    if attempts + 1 < CLEANUP_RETRIES {
+                        self.retry(device, attempts + 1, state, &context);
+                        false
+                    } else {
+                        state.retrying = None;
+                        !self.retry_next(state, &context)
+                    }
+                },
+                _ => false,
+            },
+        };
+
+        if done {
+            let state = interior.take().unwrap();
+            self.finish(state, &context);
+        }
+    }
+}
+
+impl Actor for GroupActor {
+    fn receive(&self, msg: Box<Any>, context: ActorCell) {
+        match msg.downcast_ref::<GroupActorMsg>() {
+            Some(group_msg) => self.handle_group_msg(group_msg.clone(), context),
+            None => match msg.downcast_ref::<InsteonMsg>() {
+                Some(insteon_msg) => self.handle_insteon_msg(*insteon_msg, context),
+                None => unreachable!(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ON :u8 = 0x11;
+
+    fn a() -> InsteonAddress {
+        InsteonAddress::new(0x1A, 0xD0, 0xF4)
+    }
+
+    fn b() -> InsteonAddress {
+        InsteonAddress::new(0x2B, 0x3C, 0x4D)
+    }
+
+    fn cleanup_ack(from: InsteonAddress, cmd1: u8, group: u8) -> InsteonMsg {
+        InsteonMsg::StandardMsg { addr_from: from, addr_to: InsteonAddress::new(0x44, 0x85, 0x11),
+                                  msg_flags: MessageFlags::new(MessageType::GroupCleanupAck, false),
+                                  cmd1: cmd1, cmd2: group }
+    }
+
+    fn failure(from: InsteonAddress, group: u8) -> InsteonMsg {
+        InsteonMsg::AllLinkCleanupFailureReport { x01: 0x01, all_link_group: group, id: from }
+    }
+
+    #[test]
+    fn collects_the_responders_until_the_status_report() {
+        let mut cleanup = GroupCleanup::new(0x02);
+
+        assert!(!cleanup.observe(&cleanup_ack(a(), ON, 0x02), ON));
+        assert!(!cleanup.observe(&cleanup_ack(a(), ON, 0x02), ON));
+        // ACKs of another command or group are not part of this cleanup.
+        assert!(!cleanup.observe(&cleanup_ack(b(), 0x13, 0x02), ON));
+        assert!(!cleanup.observe(&cleanup_ack(b(), ON, 0x03), ON));
+        assert!(!cleanup.observe(&failure(b(), 0x03), ON));
+        assert!(!cleanup.observe(&failure(b(), 0x02), ON));
+
+        assert!(cleanup.observe(&InsteonMsg::AllLinkCleanupStatusReport {
+            status_byte: PlmStatus::ACK }, ON));
+        assert_eq!(cleanup.acknowledged, vec![a()]);
+        assert_eq!(cleanup.failed, vec![b()]);
+        assert!(cleanup.finished);
+        assert!(!cleanup.success());
+    }
+
+    #[test]
+    fn an_aborted_cleanup_is_not_a_success() {
+        let mut cleanup = GroupCleanup::new(0x02);
+        cleanup.observe(&cleanup_ack(a(), ON, 0x02), ON);
+        assert!(cleanup.observe(&InsteonMsg::AllLinkCleanupStatusReport {
+            status_byte: PlmStatus::NAK }, ON));
+        assert!(!cleanup.finished);
+        assert!(!cleanup.success());
+    }
+
+    #[test]
+    fn a_recovered_responder_counts_as_acknowledged() {
+        let mut cleanup = GroupCleanup::new(0x02);
+        cleanup.observe(&failure(a(), 0x02), ON);
+        cleanup.observe(&failure(b(), 0x02), ON);
+        cleanup.observe(&InsteonMsg::AllLinkCleanupStatusReport { status_byte: PlmStatus::ACK }, ON);
+
+        assert_eq!(cleanup.acknowledger(&cleanup_ack(a(), ON, 0x02), ON), Some(a()));
+        cleanup.recover(a());
+        assert_eq!(cleanup.acknowledged, vec![a()]);
+        assert_eq!(cleanup.recovered, vec![a()]);
+        assert_eq!(cleanup.failed, vec![b()]);
+        assert!(!cleanup.success());
+
+        cleanup.recover(b());
+        assert!(cleanup.success());
+    }
+
+    #[test]
+    fn retries_are_direct_cleanup_messages() {
+        assert_eq!(direct_cleanup(a(), 0x02, ON), InsteonMsg::SendStandardMsg {
+            addr_to: a(),
+            msg_flags: MessageFlags::new(MessageType::GroupCleanup, false),
+            cmd1: ON,
+            cmd2: 0x02,
+        });
+    }
+}
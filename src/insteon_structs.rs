@@ -1,12 +1,41 @@
-use bytes::BytesMut;
-use bytes::{Buf, IntoBuf};
-use bincode::{deserialize};
 use phf;
 
+use address::InsteonAddress;
+use checksum::Checksum;
+
+
+/// The trailing byte the PLM appends to every echo of a host command.
+#[derive(Debug)]
+#[derive(Copy, Clone)]
+#[derive(Serialize, Deserialize, PartialEq)]
+pub enum PlmStatus {
+    Ack,
+    Nak,
+}
+
+impl PlmStatus {
+    pub const ACK :u8 = 0x06;
+    pub const NAK :u8 = 0x15;
+
+    pub fn from_u8(byte: u8) -> Option<PlmStatus> {
+        match byte {
+            PlmStatus::ACK => Some(PlmStatus::Ack),
+            PlmStatus::NAK => Some(PlmStatus::Nak),
+            _ => None,
+        }
+    }
+
+    pub fn as_u8(&self) -> u8 {
+        match *self {
+            PlmStatus::Ack => PlmStatus::ACK,
+            PlmStatus::Nak => PlmStatus::NAK,
+        }
+    }
+}
 
 #[derive(Copy, Clone)]
 pub enum ActorMsg {
-    Level(([u8; 3], u32)),
+    Send(InsteonMsg),
 }
 
 #[derive(Debug)]
@@ -15,17 +44,17 @@ pub enum ActorMsg {
 pub enum InsteonMsg {
 
     StandardMsg {
-        addr_from: [u8; 3],
-        addr_to: [u8; 3],
-        msg_flags: u8,
+        addr_from: InsteonAddress,
+        addr_to: InsteonAddress,
+        msg_flags: MessageFlags,
         cmd1: u8,
         cmd2: u8,
     },
 
     ExtendedMsg {
-        addr_from: [u8; 3],
-        addr_to: [u8; 3],
-        msg_flags: u8,
+        addr_from: InsteonAddress,
+        addr_to: InsteonAddress,
+        msg_flags: MessageFlags,
         cmd1: u8,
         cmd2: u8,
         user_data: [u8; 14],
@@ -39,7 +68,7 @@ pub enum InsteonMsg {
     AllLinkingCompleted {
         link_code: u8,
         all_link_group: u8,
-        id: [u8; 3],
+        id: InsteonAddress,
         device_category: u8,
         device_subcategory: u8,
         firmware_version: u8,
@@ -54,13 +83,13 @@ pub enum InsteonMsg {
     AllLinkCleanupFailureReport {
         x01: u8,
         all_link_group: u8,
-        id: [u8; 3],
+        id: InsteonAddress,
     },
 
     AllLinkRecordResponse {
         all_link_record_flags: u8,
         all_link_group: u8,
-        id: [u8; 3],
+        id: InsteonAddress,
         link_data: [u8; 3],
     },
 
@@ -69,11 +98,158 @@ pub enum InsteonMsg {
     },
 
     SendStandardMsg {
-        addr_to: [u8; 3],
-        msg_flags: u8,
+        addr_to: InsteonAddress,
+        msg_flags: MessageFlags,
         cmd1: u8,
         cmd2: u8,
-    }
+    },
+
+    SendExtendedMsg {
+        addr_to: InsteonAddress,
+        msg_flags: MessageFlags,
+        cmd1: u8,
+        cmd2: u8,
+        user_data: [u8; 14],
+    },
+
+    SendAllLinkCmd {
+        all_link_group: u8,
+        cmd1: u8,
+        cmd2: u8,
+    },
+
+    StartAllLinking {
+        link_code: u8,
+        all_link_group: u8,
+    },
+
+    CancelAllLinking {},
+
+    GetFirstAllLinkRecord {},
+
+    GetNextAllLinkRecord {},
+
+    GetImInfoEcho {
+        id: InsteonAddress,
+        device_category: u8,
+        device_subcategory: u8,
+        firmware_version: u8,
+        status: PlmStatus,
+    },
+
+    SendAllLinkCmdEcho {
+        all_link_group: u8,
+        cmd1: u8,
+        cmd2: u8,
+        status: PlmStatus,
+    },
+
+    SendStandardMsgEcho {
+        addr_to: InsteonAddress,
+        msg_flags: MessageFlags,
+        cmd1: u8,
+        cmd2: u8,
+        status: PlmStatus,
+    },
+
+    SendExtendedMsgEcho {
+        addr_to: InsteonAddress,
+        msg_flags: MessageFlags,
+        cmd1: u8,
+        cmd2: u8,
+        user_data: [u8; 14],
+        status: PlmStatus,
+    },
+
+    SendX10Echo {
+        raw_x10: u8,
+        x10_flag: u8,
+        status: PlmStatus,
+    },
+
+    StartAllLinkingEcho {
+        link_code: u8,
+        all_link_group: u8,
+        status: PlmStatus,
+    },
+
+    CancelAllLinkingEcho {
+        status: PlmStatus,
+    },
+
+    SetHostDeviceCategoryEcho {
+        device_category: u8,
+        device_subcategory: u8,
+        firmware_version: u8,
+        status: PlmStatus,
+    },
+
+    ResetImEcho {
+        status: PlmStatus,
+    },
+
+    SetAckMessageByteEcho {
+        cmd2: u8,
+        status: PlmStatus,
+    },
+
+    GetFirstAllLinkRecordEcho {
+        status: PlmStatus,
+    },
+
+    GetNextAllLinkRecordEcho {
+        status: PlmStatus,
+    },
+
+    SetImConfigEcho {
+        im_config_flags: u8,
+        status: PlmStatus,
+    },
+
+    GetAllLinkRecordForSenderEcho {
+        status: PlmStatus,
+    },
+
+    LedOnEcho {
+        status: PlmStatus,
+    },
+
+    LedOffEcho {
+        status: PlmStatus,
+    },
+
+    ManageAllLinkRecordEcho {
+        control_code: u8,
+        all_link_record_flags: u8,
+        all_link_group: u8,
+        id: InsteonAddress,
+        link_data: [u8; 3],
+        status: PlmStatus,
+    },
+
+    SetNakMessageByteEcho {
+        cmd2: u8,
+        status: PlmStatus,
+    },
+
+    SetAckMessageTwoBytesEcho {
+        cmd1: u8,
+        cmd2: u8,
+        status: PlmStatus,
+    },
+
+    RfSleepEcho {
+        cmd1: u8,
+        cmd2: u8,
+        status: PlmStatus,
+    },
+
+    GetImConfigEcho {
+        im_config_flags: u8,
+        spare1: u8,
+        spare2: u8,
+        status: PlmStatus,
+    },
 
 }
 
@@ -99,6 +275,8 @@ pub enum Command {
     PeekEE = 0x2B,
     OnAtRate = 0x2E,
     OffAtRate = 0x2F,
+    OutputOn = 0x45,
+    OutputOff = 0x46,
     WriteOutput = 0x48,
     ReadInput = 0x49,
     GetSensorVal = 0x4A,
@@ -108,53 +286,271 @@ pub enum Command {
     SetImCfg = 0x6B
 }
 
+impl InsteonMsg {
+    /// A direct extended message to `addr_to`, with the checksum filled in
+    /// when the device expects one.
+    pub fn send_extended(addr_to: InsteonAddress, cmd1: u8, cmd2: u8,
+                         user_data: [u8; 14], checksum: Option<Checksum>) -> InsteonMsg {
+        let mut user_data = user_data;
+        if let Some(checksum) = checksum {
+            checksum.apply(cmd1, cmd2, &mut user_data);
+        }
+
+        InsteonMsg::SendExtendedMsg {
+            addr_to: addr_to,
+            msg_flags: MessageFlags::direct_extended(),
+            cmd1: cmd1,
+            cmd2: cmd2,
+            user_data: user_data,
+        }
+    }
+
+    /// If `self` is the PLM's echo of the host command `sent`, returns the
+    /// ACK/NAK status it carries.
+    pub fn echo_status(&self, sent: &InsteonMsg) -> Option<PlmStatus> {
+        match (*sent, *self) {
+            (InsteonMsg::SendStandardMsg { addr_to, msg_flags, cmd1, cmd2 },
+             InsteonMsg::SendStandardMsgEcho {
+                 addr_to: echo_addr_to, msg_flags: echo_msg_flags,
+                 cmd1: echo_cmd1, cmd2: echo_cmd2, status })
+                if addr_to == echo_addr_to && msg_flags == echo_msg_flags &&
+                   cmd1 == echo_cmd1 && cmd2 == echo_cmd2 => Some(status),
+
+            (InsteonMsg::SendExtendedMsg { addr_to, msg_flags, cmd1, cmd2, user_data },
+             InsteonMsg::SendExtendedMsgEcho {
+                 addr_to: echo_addr_to, msg_flags: echo_msg_flags,
+                 cmd1: echo_cmd1, cmd2: echo_cmd2, user_data: echo_user_data, status })
+                if addr_to == echo_addr_to && msg_flags == echo_msg_flags &&
+                   cmd1 == echo_cmd1 && cmd2 == echo_cmd2 &&
+                   user_data == echo_user_data => Some(status),
+
+            (InsteonMsg::SendAllLinkCmd { all_link_group, cmd1, cmd2 },
+             InsteonMsg::SendAllLinkCmdEcho {
+                 all_link_group: echo_all_link_group, cmd1: echo_cmd1, cmd2: echo_cmd2, status })
+                if all_link_group == echo_all_link_group &&
+                   cmd1 == echo_cmd1 && cmd2 == echo_cmd2 => Some(status),
+
+            (InsteonMsg::StartAllLinking { link_code, all_link_group },
+             InsteonMsg::StartAllLinkingEcho {
+                 link_code: echo_link_code, all_link_group: echo_all_link_group, status })
+                if link_code == echo_link_code &&
+                   all_link_group == echo_all_link_group => Some(status),
+
+            (InsteonMsg::CancelAllLinking {},
+             InsteonMsg::CancelAllLinkingEcho { status }) |
+            (InsteonMsg::GetFirstAllLinkRecord {},
+             InsteonMsg::GetFirstAllLinkRecordEcho { status }) |
+            (InsteonMsg::GetNextAllLinkRecord {},
+             InsteonMsg::GetNextAllLinkRecordEcho { status }) => Some(status),
+
+            _ => None,
+        }
+    }
+
+    /// Whether a NAK echo of this host command means the PLM was too busy to
+    /// take it. Record queries are NAKed when there are no more records, and
+    /// linking mode when the modem refuses to enter it.
+    pub fn nak_means_busy(&self) -> bool {
+        match *self {
+            InsteonMsg::GetFirstAllLinkRecord {} | InsteonMsg::GetNextAllLinkRecord {} |
+            InsteonMsg::StartAllLinking { .. } => false,
+            _ => true,
+        }
+    }
+
+    /// `(cmd1, cmd2)` of a standard direct ACK sent by `device`.
+    pub fn direct_ack_from(&self, device: InsteonAddress) -> Option<(u8, u8)> {
+        match *self {
+            InsteonMsg::StandardMsg { addr_from, msg_flags, cmd1, cmd2, .. }
+                if addr_from == device && msg_flags.msg_type == MessageType::DirectAck =>
+                Some((cmd1, cmd2)),
+            _ => None,
+        }
+    }
+
+    pub fn is_direct_nak_from(&self, device: InsteonAddress) -> bool {
+        match *self {
+            InsteonMsg::StandardMsg { addr_from, msg_flags, .. } |
+            InsteonMsg::ExtendedMsg { addr_from, msg_flags, .. } =>
+                addr_from == device && msg_flags.msg_type == MessageType::DirectNak,
+            _ => false,
+        }
+    }
+
+    /// `(addr_from, group, cmd1)` of a group broadcast; the group is the low
+    /// byte of the address it was sent to.
+    pub fn group_broadcast(&self) -> Option<(InsteonAddress, u8, u8)> {
+        match *self {
+            InsteonMsg::StandardMsg { addr_from, addr_to, msg_flags, cmd1, .. }
+                if msg_flags.msg_type == MessageType::GroupBroadcast =>
+                Some((addr_from, addr_to.bytes()[2], cmd1)),
+            _ => None,
+        }
+    }
+
+    /// `(addr_from, group, on)` of an On or Off group broadcast.
+    pub fn group_on_off(&self) -> Option<(InsteonAddress, u8, bool)> {
+        let on = u8_command(Command::On);
+        let off = u8_command(Command::Off);
+        match self.group_broadcast() {
+            Some((addr_from, group, cmd1)) if cmd1 == on || cmd1 == off =>
+                Some((addr_from, group, cmd1 == on)),
+            _ => None,
+        }
+    }
+
+    /// Whether an extended message carries either a classic or a CRC checksum
+    /// that matches its contents; `None` for every other message.
+    pub fn has_valid_checksum(&self) -> Option<bool> {
+        match *self {
+            InsteonMsg::ExtendedMsg { cmd1, cmd2, ref user_data, .. } =>
+                Some(Checksum::Classic.verify(cmd1, cmd2, user_data) ||
+                     Checksum::Crc.verify(cmd1, cmd2, user_data)),
+            _ => None,
+        }
+    }
+}
+
+/// Extended-only command that shares its cmd1 with `Command::OffAtRate`.
+pub const READ_WRITE_ALDB :u8 = 0x2F;
+/// Extended get/set; cmd2 and D1-D2 choose what is read or written.
+pub const EXTENDED_GET_SET :u8 = 0x2E;
+
 pub fn u8_command(cmd: Command) -> u8 {
     cmd as u8
 }
 
-#[allow(dead_code)]
+/// Bits 7-5 of the message flags byte.
+#[derive(Debug)]
 #[derive(Copy, Clone)]
-pub enum Flags {
+#[derive(Serialize, Deserialize, PartialEq)]
+pub enum MessageType {
+    Direct,
+    DirectAck,
+    DirectNak,
+    Broadcast,
+    GroupBroadcast,
+    GroupCleanup,
+    GroupCleanupAck,
+    GroupCleanupNak,
 }
 
-#[allow(dead_code)]
-impl Flags {
-    pub const DIRECT_MSG :u8 = 0b000_0_00_00;
-    pub const DIRECT_MSG_ACK :u8 = 0b001_0_00_00;
-    pub const DIRECT_MSG_NACK :u8 = 0b101_0_00_00;
-    pub const GROUP_BROADCAST_MSG :u8 = 0b110_0_00_00;
-    pub const GROUP_CLEANUP_BROADCAST_MSG:u8 = 0b010_0_00_00;
-    pub const GROUP_CLEANUP_BROADCAST_MSG_ACK :u8 = 0b011_0_00_00;
-    pub const GROUP_CLEANUP_BROADCAST_MSG_NACK :u8 = 0b111_0_00_00;
-
-    pub const STANDARD_MSG :u8 = 0b000_0_00_00;
-    pub const EXTENDED_MSG :u8 = 0b000_1_00_00;
-
-    pub const MSG_REMAINING_0 :u8 = 0b000_0_00_00;
-    pub const MSG_REMAINING_1 :u8 = 0b000_0_01_00;
-    pub const MSG_REMAINING_2 :u8 = 0b000_0_10_00;
-    pub const MSG_REMAINING_3 :u8 = 0b000_0_11_00;
-
-    pub const DO_NOT_RETRAINSMIT :u8 = 0b000_0_00_00;
-    pub const RETRANSMIT_1 :u8 = 0b000_0_00_01;
-    pub const RETRANSMIT_2 :u8 = 0b000_0_00_10;
-    pub const RETRANSMIT_3 :u8 = 0b000_0_00_11;
+impl MessageType {
+    pub fn from_bits(bits: u8) -> MessageType {
+        match bits & 0b111 {
+            0b000 => MessageType::Direct,
+            0b001 => MessageType::DirectAck,
+            0b101 => MessageType::DirectNak,
+            0b100 => MessageType::Broadcast,
+            0b110 => MessageType::GroupBroadcast,
+            0b010 => MessageType::GroupCleanup,
+            0b011 => MessageType::GroupCleanupAck,
+            _ => MessageType::GroupCleanupNak,
+        }
+    }
+
+    pub fn bits(&self) -> u8 {
+        match *self {
+            MessageType::Direct => 0b000,
+            MessageType::DirectAck => 0b001,
+            MessageType::DirectNak => 0b101,
+            MessageType::Broadcast => 0b100,
+            MessageType::GroupBroadcast => 0b110,
+            MessageType::GroupCleanup => 0b010,
+            MessageType::GroupCleanupAck => 0b011,
+            MessageType::GroupCleanupNak => 0b111,
+        }
+    }
+}
+
+/// The message flags byte: `TTT E HH MM`, i.e. message type, extended bit,
+/// hops left and max hops.
+#[derive(Debug)]
+#[derive(Copy, Clone)]
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct MessageFlags {
+    pub msg_type: MessageType,
+    pub extended: bool,
+    pub hops_left: u8,
+    pub max_hops: u8,
+}
+
+impl MessageFlags {
+    pub const MAX_HOPS :u8 = 3;
+
+    pub fn new(msg_type: MessageType, extended: bool) -> MessageFlags {
+        MessageFlags {
+            msg_type: msg_type,
+            extended: extended,
+            hops_left: MessageFlags::MAX_HOPS,
+            max_hops: MessageFlags::MAX_HOPS,
+        }
+    }
+
+    pub fn direct() -> MessageFlags {
+        MessageFlags::new(MessageType::Direct, false)
+    }
+
+    pub fn direct_extended() -> MessageFlags {
+        MessageFlags::new(MessageType::Direct, true)
+    }
 }
 
-pub const DISCRIMINANT_SIZE : usize = 4;
+impl From<u8> for MessageFlags {
+    fn from(byte: u8) -> MessageFlags {
+        MessageFlags {
+            msg_type: MessageType::from_bits(byte >> 5),
+            extended: byte & 0b000_1_00_00 != 0,
+            hops_left: (byte >> 2) & 0b11,
+            max_hops: byte & 0b11,
+        }
+    }
+}
+
+impl From<MessageFlags> for u8 {
+    fn from(flags: MessageFlags) -> u8 {
+        flags.msg_type.bits() << 5 |
+            (flags.extended as u8) << 4 |
+            (flags.hops_left & 0b11) << 2 |
+            (flags.max_hops & 0b11)
+    }
+}
 
 pub const MSG_BEGIN :u8 = 0x02;
 
-pub const _STANDARD_MSG :u8 = 0x50;
-pub const _EXTENDED_MSG :u8 = 0x51;
-pub const _X10_RECEIVED :u8 = 0x52;
-pub const _ALL_LINKING_COMPLETED :u8 = 0x53;
-pub const _BUTTON_EVENT_REPORT :u8 = 0x54;
-pub const _USER_RESET_DETECTED :u8 = 0x55;
-pub const _ALL_LINK_CLEANUP_FAILURE_REPORT :u8 = 0x56;
-pub const _ALL_LINK_RECORD_RESPONSE :u8 = 0x57;
-pub const _ALL_LINK_CLEANUP_STATUS_REPORT :u8 = 0x58;
+pub const STANDARD_MSG :u8 = 0x50;
+pub const EXTENDED_MSG :u8 = 0x51;
+pub const X10_RECEIVED :u8 = 0x52;
+pub const ALL_LINKING_COMPLETED :u8 = 0x53;
+pub const BUTTON_EVENT_REPORT :u8 = 0x54;
+pub const USER_RESET_DETECTED :u8 = 0x55;
+pub const ALL_LINK_CLEANUP_FAILURE_REPORT :u8 = 0x56;
+pub const ALL_LINK_RECORD_RESPONSE :u8 = 0x57;
+pub const ALL_LINK_CLEANUP_STATUS_REPORT :u8 = 0x58;
+pub const GET_IM_INFO :u8 = 0x60;
+pub const SEND_ALL_LINK_CMD :u8 = 0x61;
 pub const SEND_STANDARD_MSG :u8 = 0x62;
+pub const SEND_X10 :u8 = 0x63;
+pub const START_ALL_LINKING :u8 = 0x64;
+pub const CANCEL_ALL_LINKING :u8 = 0x65;
+pub const SET_HOST_DEVICE_CATEGORY :u8 = 0x66;
+pub const RESET_IM :u8 = 0x67;
+pub const SET_ACK_MESSAGE_BYTE :u8 = 0x68;
+pub const GET_FIRST_ALL_LINK_RECORD :u8 = 0x69;
+pub const GET_NEXT_ALL_LINK_RECORD :u8 = 0x6A;
+pub const SET_IM_CONFIG :u8 = 0x6B;
+pub const GET_ALL_LINK_RECORD_FOR_SENDER :u8 = 0x6C;
+pub const LED_ON :u8 = 0x6D;
+pub const LED_OFF :u8 = 0x6E;
+pub const MANAGE_ALL_LINK_RECORD :u8 = 0x6F;
+pub const SET_NAK_MESSAGE_BYTE :u8 = 0x70;
+pub const SET_ACK_MESSAGE_TWO_BYTES :u8 = 0x71;
+pub const RF_SLEEP :u8 = 0x72;
+pub const GET_IM_CONFIG :u8 = 0x73;
+
+/// Size of a 0x62 echo whose flags have the extended bit set; `SIZE_MAP`
+/// holds the standard one.
+pub const SEND_EXTENDED_MSG_SIZE : usize = 21;
 
 static SIZE_MAP: phf::Map<u8, usize> = phf_map!(
     0x50u8 => 9,
@@ -163,64 +559,31 @@ static SIZE_MAP: phf::Map<u8, usize> = phf_map!(
     0x53u8 => 8,
     0x54u8 => 1,
     0x55u8 => 0,
-    0x56u8 => 1,
+    0x56u8 => 5,
     0x57u8 => 8,
     0x58u8 => 1,
-    0x62u8 => 6,
-);
-
-static DISCRIMINANT_MAP: phf::Map<u8, u8> = phf_map!(
-    0x50u8 => 0,
-    0x51u8 => 1,
-    0x52u8 => 2,
-    0x53u8 => 3,
-    0x54u8 => 4,
-    0x55u8 => 5,
-    0x56u8 => 6,
-    0x57u8 => 7,
-    0x58u8 => 8,
-    0x62u8 => 9,
+    0x60u8 => 7,
+    0x61u8 => 4,
+    0x62u8 => 7,
+    0x63u8 => 3,
+    0x64u8 => 3,
+    0x65u8 => 1,
+    0x66u8 => 4,
+    0x67u8 => 1,
+    0x68u8 => 2,
+    0x69u8 => 1,
+    0x6Au8 => 1,
+    0x6Bu8 => 2,
+    0x6Cu8 => 1,
+    0x6Du8 => 1,
+    0x6Eu8 => 1,
+    0x6Fu8 => 10,
+    0x70u8 => 2,
+    0x71u8 => 3,
+    0x72u8 => 3,
+    0x73u8 => 4,
 );
 
 pub fn get_msg_size(msg_type: &u8) -> Option<usize> {
     SIZE_MAP.get(msg_type).cloned()
 }
-
-pub fn get_discriminant(msg_type: &u8) -> Option<u8> {
-    DISCRIMINANT_MAP.get(msg_type).cloned()
-}
-
-impl InsteonMsg {
-    pub fn new(buf: &BytesMut) -> Option<(InsteonMsg, usize)> {
-
-        if buf.is_empty() {
-            return None
-        };
-
-        let mut current = buf.into_buf().iter();
-        let command_type = current.next().unwrap();
-        let buf_size = buf.len() - 1;
-
-        pub fn decode(discriminant: u8, v: Vec<u8>) -> InsteonMsg {
-            let mut encoded = vec!(discriminant, 0, 0, 0);
-            encoded.extend(v);
-            deserialize(&encoded).unwrap()
-        }
-
-        match get_msg_size(&command_type) {
-            Some(size) if buf_size < size  => {
-                None
-            },
-
-            Some(size) => {
-                let v : Vec<u8> = current.take(size).collect();
-                let discriminant = get_discriminant(&command_type).unwrap();
-                Some((decode(discriminant, v), size))
-            },
-
-            None => {
-                None
-            },
-        }
-    }
-}
\ No newline at end of file
@@ -0,0 +1,218 @@
+use address::InsteonAddress;
+use engine::Dialect;
+use insteon_structs::*;
+use opflags::OpFlagsCommand;
+
+/// Device category shared by the IOLinc and the EZIO modules.
+pub const IO_CATEGORY :u8 = 0x07;
+
+const SET_MOMENTARY_TIME :u8 = 0x06;
+
+/// How the IOLinc relay reacts to On and Off commands.
+#[derive(Debug)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum RelayMode {
+    /// The relay stays where the last command left it.
+    Latching,
+    /// Any command closes the relay for the momentary time.
+    MomentaryA,
+    /// Only On closes the relay, Off is ignored.
+    MomentaryB,
+    /// On closes the relay only if the sensor is off, Off only if it is on.
+    MomentaryC,
+}
+
+impl RelayMode {
+    const MOMENTARY_A_ON :u8 = 0x06;
+    const MOMENTARY_A_OFF :u8 = 0x07;
+    const MOMENTARY_B_ON :u8 = 0x12;
+    const MOMENTARY_B_OFF :u8 = 0x13;
+    const MOMENTARY_C_ON :u8 = 0x14;
+    const MOMENTARY_C_OFF :u8 = 0x15;
+
+    /// The SetOpFlags (0x20) cmd2 values that select this mode, in order.
+    pub fn op_flags(&self) -> [u8; 3] {
+        match *self {
+            RelayMode::Latching =>
+                [RelayMode::MOMENTARY_A_OFF, RelayMode::MOMENTARY_B_OFF, RelayMode::MOMENTARY_C_OFF],
+            RelayMode::MomentaryA =>
+                [RelayMode::MOMENTARY_A_ON, RelayMode::MOMENTARY_B_OFF, RelayMode::MOMENTARY_C_OFF],
+            RelayMode::MomentaryB =>
+                [RelayMode::MOMENTARY_A_ON, RelayMode::MOMENTARY_B_ON, RelayMode::MOMENTARY_C_OFF],
+            RelayMode::MomentaryC =>
+                [RelayMode::MOMENTARY_A_ON, RelayMode::MOMENTARY_B_OFF, RelayMode::MOMENTARY_C_ON],
+        }
+    }
+}
+
+/// The commands of the IOLinc and EZIO modules. Outputs, inputs and sensors
+/// are numbered from 0.
+#[derive(Debug)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum IoCommand {
+    /// The IOLinc relay, switched with the plain On/Off commands.
+    Relay(bool),
+    OutputOn(u8),
+    OutputOff(u8),
+    /// Sets every output at once from a bit mask.
+    WriteOutputs(u8),
+    /// The ACK carries the input bit mask in cmd2.
+    ReadInputs,
+    /// The ACK carries the sensor's value in cmd2.
+    SensorValue(u8),
+    /// IO Module Control (0x4F), with a subcommand from the module's manual.
+    ModuleControl(u8),
+    /// One SetOpFlags (0x20) step, see `RelayMode::op_flags`.
+    OpFlag(u8),
+    /// Momentary relay closing time, in tenths of a second.
+    MomentaryTime(u8),
+}
+
+impl IoCommand {
+    pub fn cmd1(&self) -> u8 {
+        let cmd = match *self {
+            IoCommand::Relay(true) => Command::On,
+            IoCommand::Relay(false) => Command::Off,
+            IoCommand::OutputOn(_) => Command::OutputOn,
+            IoCommand::OutputOff(_) => Command::OutputOff,
+            IoCommand::WriteOutputs(_) => Command::WriteOutput,
+            IoCommand::ReadInputs => Command::ReadInput,
+            IoCommand::SensorValue(_) => Command::GetSensorVal,
+            IoCommand::ModuleControl(_) => Command::IoModuleCtrl,
+            IoCommand::OpFlag(code) => return OpFlagsCommand::Set(code).cmd1(),
+            IoCommand::MomentaryTime(_) => return EXTENDED_GET_SET,
+        };
+        u8_command(cmd)
+    }
+
+    pub fn cmd2(&self) -> u8 {
+        match *self {
+            IoCommand::Relay(true) => 0xFF,
+            IoCommand::OutputOn(n) | IoCommand::OutputOff(n) | IoCommand::WriteOutputs(n) |
+            IoCommand::SensorValue(n) | IoCommand::ModuleControl(n) => n,
+            IoCommand::OpFlag(code) => OpFlagsCommand::Set(code).cmd2(),
+            IoCommand::Relay(false) | IoCommand::ReadInputs | IoCommand::MomentaryTime(_) => 0x00,
+        }
+    }
+
+    /// `None` if the command needs an extended message `dialect` cannot carry.
+    pub fn to_msg(&self, device: InsteonAddress, dialect: Dialect) -> Option<InsteonMsg> {
+        match *self {
+            IoCommand::MomentaryTime(tenths) => {
+                let mut user_data = [0u8; 14];
+                user_data[1] = SET_MOMENTARY_TIME;
+                user_data[2] = tenths;
+                dialect.extended(device, self.cmd1(), self.cmd2(), user_data)
+            },
+            IoCommand::OpFlag(code) => OpFlagsCommand::Set(code).to_msg(device, dialect),
+            _ => Some(InsteonMsg::SendStandardMsg {
+                addr_to: device,
+                msg_flags: MessageFlags::direct(),
+                cmd1: self.cmd1(),
+                cmd2: self.cmd2(),
+            }),
+        }
+    }
+}
+
+/// An input of an I/O module changing state, which it reports with an
+/// On or Off broadcast to the group numbered after the input.
+#[derive(Debug)]
+#[derive(Copy, Clone, PartialEq)]
+pub struct IoEvent {
+    pub input: u8,
+    pub on: bool,
+}
+
+impl IoEvent {
+    pub fn from_broadcast(msg: &InsteonMsg) -> Option<(InsteonAddress, IoEvent)> {
+        match msg.group_on_off() {
+            Some((addr_from, group, on)) if group != 0 => Some((addr_from, IoEvent {
+                input: group - 1,
+                on: on,
+            })),
+            _ => None,
+        }
+    }
+}
+
+/// The last known state of an I/O module's inputs.
+#[derive(Debug)]
+#[derive(Copy, Clone, Default)]
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct IoState {
+    /// Bit n is set while input n is on.
+    pub inputs: u8,
+}
+
+impl IoState {
+    pub fn apply(&mut self, event: IoEvent) {
+        let bit = 1u8.checked_shl(event.input as u32).unwrap_or(0);
+        if event.on {
+            self.inputs |= bit;
+        } else {
+            self.inputs &= !bit;
+        }
+    }
+
+    pub fn input(&self, input: u8) -> bool {
+        self.inputs & 1u8.checked_shl(input as u32).unwrap_or(0) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use checksum::Checksum;
+
+    fn a() -> InsteonAddress {
+        InsteonAddress::new(0x1A, 0xD0, 0xF4)
+    }
+
+    fn broadcast(cmd1: u8, group: u8) -> InsteonMsg {
+        InsteonMsg::StandardMsg { addr_from: a(), addr_to: InsteonAddress::new(0x00, 0x00, group),
+                                  msg_flags: MessageFlags::new(MessageType::GroupBroadcast, false),
+                                  cmd1: cmd1, cmd2: 0x00 }
+    }
+
+    #[test]
+    fn tracks_inputs_from_their_group_broadcasts() {
+        let on = IoEvent::from_broadcast(&broadcast(0x11, 2));
+        assert_eq!(on, Some((a(), IoEvent { input: 1, on: true })));
+        assert_eq!(IoEvent::from_broadcast(&broadcast(0x11, 0)), None);
+        assert_eq!(IoEvent::from_broadcast(&broadcast(0x19, 2)), None);
+
+        let mut state = IoState::default();
+        state.apply(on.unwrap().1);
+        state.apply(IoEvent::from_broadcast(&broadcast(0x13, 1)).unwrap().1);
+        assert_eq!(state.inputs, 0b10);
+        assert!(state.input(1) && !state.input(0));
+    }
+
+    #[test]
+    fn sets_the_momentary_time_with_extended_get_set() {
+        let cmd = IoCommand::MomentaryTime(15);
+        match cmd.to_msg(a(), Dialect::ChecksummedExtended(Checksum::Classic)) {
+            Some(InsteonMsg::SendExtendedMsg { cmd1, cmd2, user_data, .. }) => {
+                assert_eq!((cmd1, cmd2), (EXTENDED_GET_SET, 0x00));
+                assert_eq!(&user_data[..3], &[0x00, SET_MOMENTARY_TIME, 15]);
+                assert!(Checksum::Classic.verify(cmd1, cmd2, &user_data));
+            },
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(cmd.to_msg(a(), Dialect::PeekPoke), None);
+    }
+
+    #[test]
+    fn sends_operating_flags_extended_only_to_i2cs_devices() {
+        let flag = IoCommand::OpFlag(RelayMode::MomentaryB.op_flags()[1]);
+        match flag.to_msg(a(), Dialect::Extended) {
+            Some(InsteonMsg::SendStandardMsg { cmd1, cmd2, .. }) => assert_eq!((cmd1, cmd2), (0x20, 0x12)),
+            other => panic!("{:?}", other),
+        }
+        match flag.to_msg(a(), Dialect::ChecksummedExtended(Checksum::Classic)) {
+            Some(InsteonMsg::SendExtendedMsg { cmd1, cmd2, .. }) => assert_eq!((cmd1, cmd2), (0x20, 0x12)),
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use address::InsteonAddress;
+use engine::Dialect;
+use insteon_structs::*;
+
+const SET_ON_MASK :u8 = 0x02;
+const SET_OFF_MASK :u8 = 0x03;
+const SET_LED_BRIGHTNESS :u8 = 0x07;
+const SET_NON_TOGGLE_MASK :u8 = 0x08;
+const SET_LED_MASK :u8 = 0x09;
+const SET_ON_OFF_MASK :u8 = 0x0B;
+
+/// cmd2 of a status request that asks for the LED bit mask instead of the load level.
+const LED_STATUS :u8 = 0x01;
+
+/// `(category, subcategory)` of every KeypadLinc, dimmer and relay alike.
+pub const KEYPAD_DEVICES : [(u8, u8); 10] = [
+    (0x01, 0x05), (0x01, 0x09), (0x01, 0x0C), (0x01, 0x1B), (0x01, 0x1C), (0x01, 0x41),
+    (0x02, 0x05), (0x02, 0x0F), (0x02, 0x1E), (0x02, 0x2C),
+];
+
+/// Dimmest and brightest LED levels the KeypadLinc accepts.
+pub const MIN_LED_BRIGHTNESS :u8 = 0x11;
+pub const MAX_LED_BRIGHTNESS :u8 = 0x7F;
+
+/// Buttons are numbered by the group they control, 1 to 8; button n is bit
+/// n - 1 of every mask. On a 6-button KeypadLinc, ON/OFF is 1 and A-D are 3-6.
+pub fn button_bit(button: u8) -> u8 {
+    match button {
+        1...8 => 1 << (button - 1),
+        _ => 0,
+    }
+}
+
+/// What a button sends when pressed.
+#[derive(Debug)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum ButtonMode {
+    /// Alternates between On and Off.
+    Toggle,
+    AlwaysOn,
+    AlwaysOff,
+}
+
+/// The KeypadLinc settings written with extended Set (0x2E) commands.
+#[derive(Debug)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum KeypadCommand {
+    /// Lights exactly the buttons in the mask.
+    LedMask(u8),
+    LedBrightness(u8),
+    /// Buttons that always send the same command instead of toggling.
+    NonToggleMask(u8),
+    /// Which of the non-toggle buttons send On rather than Off.
+    OnOffMask(u8),
+    /// Buttons turned on whenever `button` turns on.
+    OnMask { button: u8, mask: u8 },
+    /// Buttons turned off whenever `button` turns on.
+    OffMask { button: u8, mask: u8 },
+}
+
+impl KeypadCommand {
+    fn data(&self) -> (u8, u8, u8) {
+        match *self {
+            KeypadCommand::LedMask(mask) => (0x01, SET_LED_MASK, mask),
+            KeypadCommand::LedBrightness(level) => (0x01, SET_LED_BRIGHTNESS,
+                level.max(MIN_LED_BRIGHTNESS).min(MAX_LED_BRIGHTNESS)),
+            KeypadCommand::NonToggleMask(mask) => (0x01, SET_NON_TOGGLE_MASK, mask),
+            KeypadCommand::OnOffMask(mask) => (0x01, SET_ON_OFF_MASK, mask),
+            KeypadCommand::OnMask { button, mask } => (button, SET_ON_MASK, mask),
+            KeypadCommand::OffMask { button, mask } => (button, SET_OFF_MASK, mask),
+        }
+    }
+
+    pub fn cmd1(&self) -> u8 {
+        EXTENDED_GET_SET
+    }
+
+    pub fn to_msg(&self, device: InsteonAddress, dialect: Dialect) -> Option<InsteonMsg> {
+        let (d1, d2, d3) = self.data();
+        let mut user_data = [0u8; 14];
+        user_data[0] = d1;
+        user_data[1] = d2;
+        user_data[2] = d3;
+        dialect.extended(device, self.cmd1(), 0x00, user_data)
+    }
+}
+
+/// The non-toggle and on/off masks for the given button modes; buttons
+/// that are not listed toggle.
+pub fn button_mode_commands(modes: &[(u8, ButtonMode)]) -> [KeypadCommand; 2] {
+    let mut non_toggle = 0;
+    let mut on_off = 0;
+    for &(button, mode) in modes {
+        match mode {
+            ButtonMode::Toggle => (),
+            ButtonMode::AlwaysOn => {
+                non_toggle |= button_bit(button);
+                on_off |= button_bit(button);
+            },
+            ButtonMode::AlwaysOff => non_toggle |= button_bit(button),
+        }
+    }
+    [KeypadCommand::NonToggleMask(non_toggle), KeypadCommand::OnOffMask(on_off)]
+}
+
+/// Makes `buttons` mutually exclusive: each one turns the others off.
+pub fn radio_group_commands(buttons: &[u8]) -> Vec<KeypadCommand> {
+    let group = buttons.iter().fold(0, |mask, &button| mask | button_bit(button));
+    buttons.iter().map(|&button| KeypadCommand::OffMask {
+        button: button,
+        mask: group & !button_bit(button),
+    }).collect()
+}
+
+/// Asks for the LED states; the ACK carries the LED bit mask in cmd2.
+pub fn led_status_request(device: InsteonAddress) -> InsteonMsg {
+    InsteonMsg::SendStandardMsg {
+        addr_to: device,
+        msg_flags: MessageFlags::direct(),
+        cmd1: u8_command(Command::StatusReq),
+        cmd2: LED_STATUS,
+    }
+}
+
+#[derive(Debug)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum ButtonAction {
+    On,
+    Off,
+    FastOn,
+    FastOff,
+    StartChange,
+    StopChange,
+}
+
+/// A button press, reported with a broadcast to the button's group.
+#[derive(Debug)]
+#[derive(Copy, Clone, PartialEq)]
+pub struct ButtonEvent {
+    pub button: u8,
+    pub action: ButtonAction,
+}
+
+impl ButtonEvent {
+    pub fn from_broadcast(msg: &InsteonMsg) -> Option<(InsteonAddress, ButtonEvent)> {
+        let (addr_from, button, cmd1) = match msg.group_broadcast() {
+            Some(broadcast) => broadcast,
+            None => return None,
+        };
+
+        let action = match cmd1 {
+            c if c == u8_command(Command::On) => ButtonAction::On,
+            c if c == u8_command(Command::Off) => ButtonAction::Off,
+            c if c == u8_command(Command::FastOn) => ButtonAction::FastOn,
+            c if c == u8_command(Command::FastOff) => ButtonAction::FastOff,
+            c if c == u8_command(Command::StartChange) => ButtonAction::StartChange,
+            c if c == u8_command(Command::StopChange) => ButtonAction::StopChange,
+            _ => return None,
+        };
+
+        Some((addr_from, ButtonEvent {
+            button: button,
+            action: action,
+        }))
+    }
+}
+
+/// The last known LED states of a KeypadLinc.
+#[derive(Debug)]
+#[derive(Copy, Clone, Default)]
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct KeypadState {
+    pub leds: u8,
+}
+
+impl KeypadState {
+    pub fn apply(&mut self, event: ButtonEvent) {
+        match event.action {
+            ButtonAction::On | ButtonAction::FastOn => self.leds |= button_bit(event.button),
+            ButtonAction::Off | ButtonAction::FastOff => self.leds &= !button_bit(event.button),
+            ButtonAction::StartChange | ButtonAction::StopChange => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use checksum::Checksum;
+
+    fn a() -> InsteonAddress {
+        InsteonAddress::new(0x2B, 0x1C, 0x07)
+    }
+
+    fn broadcast(cmd1: u8, group: u8) -> InsteonMsg {
+        InsteonMsg::StandardMsg { addr_from: a(), addr_to: InsteonAddress::new(0x00, 0x00, group),
+                                  msg_flags: MessageFlags::new(MessageType::GroupBroadcast, false),
+                                  cmd1: cmd1, cmd2: 0x00 }
+    }
+
+    #[test]
+    fn numbers_buttons_by_their_group() {
+        assert_eq!(button_bit(1), 0x01);
+        assert_eq!(button_bit(8), 0x80);
+        assert_eq!(button_bit(0), 0);
+        assert_eq!(button_bit(9), 0);
+    }
+
+    #[test]
+    fn links_every_radio_button_off_to_the_others() {
+        assert_eq!(radio_group_commands(&[3, 4, 6]), vec![
+            KeypadCommand::OffMask { button: 3, mask: 0b0010_1000 },
+            KeypadCommand::OffMask { button: 4, mask: 0b0010_0100 },
+            KeypadCommand::OffMask { button: 6, mask: 0b0000_1100 },
+        ]);
+        assert_eq!(radio_group_commands(&[]), vec![]);
+    }
+
+    #[test]
+    fn sets_the_non_toggle_buttons_and_what_they_send() {
+        let modes = [(2, ButtonMode::AlwaysOn), (5, ButtonMode::AlwaysOff), (7, ButtonMode::Toggle)];
+        assert_eq!(button_mode_commands(&modes),
+                   [KeypadCommand::NonToggleMask(0b0001_0010), KeypadCommand::OnOffMask(0b0000_0010)]);
+    }
+
+    #[test]
+    fn writes_settings_with_extended_get_set() {
+        let cmd = KeypadCommand::OffMask { button: 3, mask: 0x28 };
+        match cmd.to_msg(a(), Dialect::ChecksummedExtended(Checksum::Classic)) {
+            Some(InsteonMsg::SendExtendedMsg { cmd1, cmd2, user_data, .. }) => {
+                assert_eq!((cmd1, cmd2), (EXTENDED_GET_SET, 0x00));
+                assert_eq!(&user_data[..3], &[3, SET_OFF_MASK, 0x28]);
+                assert!(Checksum::Classic.verify(cmd1, cmd2, &user_data));
+            },
+            other => panic!("{:?}", other),
+        }
+        match KeypadCommand::LedBrightness(0xFF).to_msg(a(), Dialect::Extended) {
+            Some(InsteonMsg::SendExtendedMsg { user_data, .. }) =>
+                assert_eq!(&user_data[..3], &[0x01, SET_LED_BRIGHTNESS, MAX_LED_BRIGHTNESS]),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(cmd.to_msg(a(), Dialect::PeekPoke), None);
+    }
+
+    #[test]
+    fn tracks_leds_from_button_broadcasts() {
+        let on = ButtonEvent::from_broadcast(&broadcast(0x11, 3));
+        assert_eq!(on, Some((a(), ButtonEvent { button: 3, action: ButtonAction::On })));
+        assert_eq!(ButtonEvent::from_broadcast(&broadcast(0x19, 3)), None);
+
+        let mut state = KeypadState { leds: 0b0001_0001 };
+        state.apply(on.unwrap().1);
+        state.apply(ButtonEvent::from_broadcast(&broadcast(0x14, 5)).unwrap().1);
+        state.apply(ButtonEvent::from_broadcast(&broadcast(0x17, 1)).unwrap().1);
+        assert_eq!(state.leds, 0b0000_0101);
+    }
+}
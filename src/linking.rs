@@ -0,0 +1,212 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use robots::actors::{Actor, ActorCell, ActorContext, ActorRef, Any};
+
+use address::InsteonAddress;
+use insteon_structs::*;
+use registry::Identity;
+use timer;
+
+/// The PLM leaves linking mode by itself after four minutes.
+pub const LINKING_TIMEOUT_SEC :u64 = 240;
+
+/// The role the modem takes in the link it creates.
+#[derive(Debug)]
+#[derive(Copy, Clone)]
+#[derive(Serialize, Deserialize, PartialEq)]
+pub enum LinkCode {
+    Responder,
+    Controller,
+    /// Controller or responder, whichever the other device is not.
+    Either,
+    /// Removes the link instead of creating it.
+    Delete,
+}
+
+impl LinkCode {
+    pub fn from_byte(byte: u8) -> Option<LinkCode> {
+        match byte {
+            0x00 => Some(LinkCode::Responder),
+            0x01 => Some(LinkCode::Controller),
+            0x03 => Some(LinkCode::Either),
+            0xFF => Some(LinkCode::Delete),
+            _ => None,
+        }
+    }
+
+    pub fn byte(&self) -> u8 {
+        match *self {
+            LinkCode::Responder => 0x00,
+            LinkCode::Controller => 0x01,
+            LinkCode::Either => 0x03,
+            LinkCode::Delete => 0xFF,
+        }
+    }
+}
+
+pub fn start_linking(code: LinkCode, group: u8) -> InsteonMsg {
+    InsteonMsg::StartAllLinking {
+        link_code: code.byte(),
+        all_link_group: group,
+    }
+}
+
+pub fn cancel_linking() -> InsteonMsg {
+    InsteonMsg::CancelAllLinking {}
+}
+
+/// The link the modem reported with an ALL-Linking Completed (0x53).
+#[derive(Debug)]
+#[derive(Copy, Clone)]
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct Linked {
+    pub link_code: Option<LinkCode>,
+    pub group: u8,
+    pub address: InsteonAddress,
+    pub identity: Identity,
+}
+
+impl Linked {
+    pub fn from_completed(msg: &InsteonMsg) -> Option<Linked> {
+        match *msg {
+            InsteonMsg::AllLinkingCompleted { link_code, all_link_group, .. } =>
+                Identity::from_broadcast(msg).map(|(address, identity)| Linked {
+                    link_code: LinkCode::from_byte(link_code),
+                    group: all_link_group,
+                    address: address,
+                    identity: identity,
+                }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[derive(Copy, Clone)]
+pub enum LinkingResult {
+    Completed(Linked),
+    TimedOut,
+    Cancelled,
+    /// The modem NAKed the request to enter linking mode.
+    Refused,
+}
+
+#[derive(Clone)]
+pub enum LinkingActorMsg {
+    Start(ActorRef),
+    Timeout,
+}
+
+/// Puts the modem into linking mode and waits for a device to join, or for
+/// the session to be cancelled or to time out.
+pub struct LinkingActor {
+    ser_tx_actor : ActorRef,
+    code         : LinkCode,
+    group        : u8,
+    timeout      : Duration,
+    future       : Mutex<Option<ActorRef>>,
+}
+
+impl LinkingActor {
+    pub fn new(tuple: (ActorRef, LinkCode, u8, Duration)) -> LinkingActor {
+        let (ser_tx_actor, code, group, timeout) = tuple;
+        LinkingActor {
+            ser_tx_actor: ser_tx_actor,
+            code: code,
+            group: group,
+            timeout: timeout,
+            future: Mutex::new(None),
+        }
+    }
+
+    fn finish(&self, result: LinkingResult, context: &ActorCell) {
+        if let Some(future) = self.future.lock().unwrap().take() {
+            info!("Linking session ended: {:?}", result);
+            context.complete(future, result);
+            context.kill_me();
+        }
+    }
+
+    pub fn handle_insteon_msg(&self, message: InsteonMsg, context: ActorCell) {
+        match message {
+            InsteonMsg::AllLinkingCompleted { .. } => {
+                if let Some(linked) = Linked::from_completed(&message) {
+                    self.finish(LinkingResult::Completed(linked), &context);
+                }
+            },
+            InsteonMsg::StartAllLinkingEcho { status: PlmStatus::Nak, .. } => {
+                warn!("The modem refused to enter linking mode");
+                self.finish(LinkingResult::Refused, &context);
+            },
+            InsteonMsg::CancelAllLinkingEcho { status: PlmStatus::Ack } =>
+                self.finish(LinkingResult::Cancelled, &context),
+            _ => (),
+        }
+    }
+
+    pub fn handle_linking_msg(&self, message: LinkingActorMsg, context: ActorCell) {
+        match message {
+            LinkingActorMsg::Start(future) => {
+                *self.future.lock().unwrap() = Some(future);
+                info!("Linking {:?} for group {}", self.code, self.group);
+                self.ser_tx_actor.tell_to(self.ser_tx_actor.clone(),
+                                          ActorMsg::Send(start_linking(self.code, self.group)));
+
+                timer::schedule(context.actor_ref(), LinkingActorMsg::Timeout, self.timeout);
+            },
+
+            LinkingActorMsg::Timeout => {
+                if self.future.lock().unwrap().is_some() {
+                    self.ser_tx_actor.tell_to(self.ser_tx_actor.clone(),
+                                              ActorMsg::Send(cancel_linking()));
+                    self.finish(LinkingResult::TimedOut, &context);
+                }
+            },
+        }
+    }
+}
+
+impl Actor for LinkingActor {
+    fn receive(&self, msg: Box<Any>, context: ActorCell) {
+        match msg.downcast_ref::<LinkingActorMsg>() {
+            Some(linking_msg) => self.handle_linking_msg(linking_msg.clone(), context),
+            None => match msg.downcast_ref::<InsteonMsg>() {
+                Some(insteon_msg) => self.handle_insteon_msg(*insteon_msg, context),
+                None => unreachable!(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_link_codes() {
+        for code in &[LinkCode::Responder, LinkCode::Controller, LinkCode::Either, LinkCode::Delete] {
+            assert_eq!(LinkCode::from_byte(code.byte()), Some(*code));
+        }
+        assert_eq!(LinkCode::from_byte(0x02), None);
+    }
+
+    #[test]
+    fn reads_the_link_from_a_completion() {
+        let completed = InsteonMsg::AllLinkingCompleted {
+            link_code: 0x01,
+            all_link_group: 0x02,
+            id: InsteonAddress::new(0x1A, 0xD0, 0xF4),
+            device_category: 0x02,
+            device_subcategory: 0x2A,
+            firmware_version: 0x45,
+        };
+        assert_eq!(Linked::from_completed(&completed), Some(Linked {
+            link_code: Some(LinkCode::Controller),
+            group: 0x02,
+            address: InsteonAddress::new(0x1A, 0xD0, 0xF4),
+            identity: Identity { category: 0x02, subcategory: 0x2A, firmware: 0x45 },
+        }));
+        assert_eq!(Linked::from_completed(&cancel_linking()), None);
+    }
+}
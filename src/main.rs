@@ -2,12 +2,32 @@
 #![feature(plugin)]
 #![plugin(phf_macros)]
 
+mod address;
+mod aldb;
+mod catalog;
+mod checksum;
 mod insteon_structs;
 mod rpc;
+mod registry;
+mod sensors;
 mod codec;
+mod device_control;
+mod engine;
+mod fan;
+mod garage;
+mod group;
+mod io;
+mod keypad;
+mod linking;
 mod messages_grpc;
 mod messages;
+mod opflags;
+mod plm;
 mod serial_writer;
+mod thermostat;
+mod timer;
+mod wake;
+mod wire;
 
 #[macro_use] extern crate log;
 extern crate tokio_serial;
@@ -24,8 +44,9 @@ extern crate futures;
 extern crate tls_api;
 extern crate env_logger;
 
+extern crate serde;
 #[macro_use] extern crate serde_derive;
-extern crate bincode;
+#[cfg(test)] extern crate serde_json;
 extern crate robots;
 
 use robots::actors::{ActorSystem, Props};
@@ -36,12 +57,14 @@ use std::thread;
 use std::time::Duration;
 use std::sync::Mutex;
 use std::sync::Arc;
+use std::sync::atomic::AtomicUsize;
 use std::collections::HashMap;
 
 use log::{LogRecord, LogLevelFilter};
 use env_logger::LogBuilder;
 
 use tokio_core::reactor::Core;
+use tokio_io::AsyncRead;
 
 use tokio_serial::*;
 
@@ -51,7 +74,11 @@ use rpc::VinsteonRpcImpl;
 use messages_grpc::*;
 use codec::*;
 use serial_writer::SerialWriterActor;
-use rpc::RpcActor;
+use rpc::{RpcActor, RpcActorMsg};
+use plm::PlmLinks;
+use address::InsteonAddress;
+use registry::DeviceRegistry;
+use wake::WakeQueue;
 
 
 fn setup_logging() {
@@ -63,7 +90,7 @@ fn setup_logging() {
     builder.init().unwrap();
 }
 
-fn setup_serial_port() -> tokio_codec::Framed<tokio_serial::Serial, LineCodec> {
+fn setup_serial_port() -> tokio_serial::Serial {
     const DEFAULT_TTY_PATH: &str = "/dev/ttyUSB0";
 
     let settings = SerialPortSettings {
@@ -80,7 +107,7 @@ fn setup_serial_port() -> tokio_codec::Framed<tokio_serial::Serial, LineCodec> {
     port.set_exclusive(false).expect("Unable to set serial port exclusive");
     info!("... done.");
 
-    tokio_codec::Decoder::framed(LineCodec, port)
+    port
 }
 
 
@@ -91,11 +118,16 @@ fn main() {
     setup_logging();
 
     let mut core = Core::new().unwrap();
-    let serial = setup_serial_port();
-    let (writer, reader) = serial.split();
+    let codec = LineCodec::new();
+    let framing_errors = codec.errors();
+    let (port_reader, port_writer) = setup_serial_port().split();
+    let reader = FrameReader::new(port_reader, codec, core.handle());
+    let writer = tokio_codec::FramedWrite::new(port_writer, LineCodec::new());
 
     let writer_arc = Arc::new(Mutex::new(writer));
     let msg_bus_arc = Arc::new(Mutex::new(Bus::new(10)));
+    let registry_arc = Arc::new(Mutex::new(DeviceRegistry::new()));
+    let wake_queue_arc = Arc::new(Mutex::new(WakeQueue::new()));
 
     let actor_system = ActorSystem::new("vinsteon_system".to_owned());
     actor_system.spawn_threads(ACTOR_SYSTEM_THREAD_NUM);
@@ -107,11 +139,13 @@ fn main() {
 
     let rpc_props = Props::new(
         Arc::new(RpcActor::new),
-        (ser_tx_actor.clone(), msg_bus_arc.clone(), core.remote()));
+        (ser_tx_actor.clone(), msg_bus_arc.clone(), core.remote(), registry_arc.clone(),
+         wake_queue_arc.clone()));
     let rpc_actor = actor_system.actor_of(rpc_props, "rpc".to_owned());
 
     let printer = reader.for_each(|s| {
         msg_bus_arc.lock().unwrap().broadcast(s);
+        actor_system.tell(ser_tx_actor.clone(), s);
         actor_system.tell(rpc_actor.clone(), s);
         Ok(())
     });
@@ -125,15 +159,33 @@ fn main() {
             ser_tx_actor : ser_tx_actor.clone(),
             rpc_actor : rpc_actor.clone(),
             msg_bus : msg_bus_arc.clone(),
+            framing_errors : framing_errors.clone(),
+            next_future : Arc::new(AtomicUsize::new(0)),
+            registry : registry_arc.clone(),
+            wake_queue : wake_queue_arc.clone(),
+            garage_locks : Arc::new(Mutex::new(HashMap::new())),
             actor_system : actor_system.clone() }
     ));
     server.http.set_cpu_pool_threads(4);
     let _server = server.build().expect("server");
 
+    // Battery sensors cannot be asked who they are until they wake up, so
+    // their identities come from the modem's links instead.
+    info!("Spawning modem link reader thread.");
+    let links_system = actor_system.clone();
+    let links_rpc_actor = rpc_actor.clone();
+    let links_registry = registry_arc.clone();
+    thread::spawn(move || {
+        let future = links_system.ask(links_rpc_actor, RpcActorMsg::ModemLinks, "modem_links".to_owned());
+        let links : PlmLinks = links_system.extract_result(future);
+        info!("Learned device identities from {} modem link(s)", links.links.len());
+        links_registry.lock().unwrap().learn_links(&links.links);
+    });
+
     info!("Spawning persitence phread.");
     thread::spawn(|| {
-        let mut devices : HashMap<String, [u8; 3]> = std::collections::HashMap::new();
-        devices.insert("".into(), [1,2,3]);
+        let mut devices : HashMap<String, InsteonAddress> = std::collections::HashMap::new();
+        devices.insert("".into(), InsteonAddress::new(1, 2, 3));
         //println!("{}", serde_json::to_string(&devices).unwrap());
     });
 
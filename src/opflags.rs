@@ -0,0 +1,241 @@
+use address::InsteonAddress;
+use engine::Dialect;
+use insteon_structs::*;
+use io::IO_CATEGORY;
+use registry::Identity;
+
+const DIMMER_CATEGORY :u8 = 0x01;
+const SWITCH_CATEGORY :u8 = 0x02;
+
+#[derive(Debug)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum OpFlag {
+    /// Ignores the SET button, so the device cannot be relinked by accident.
+    ProgramLock,
+    /// Blinks the LED while the device transmits.
+    LedOnTx,
+    /// Turns on at the last level rather than the on level.
+    ResumeDim,
+    /// KeypadLinc button layout: 8 buttons rather than 6.
+    EightKey,
+    LedOff,
+    Beeper,
+    RfDisable,
+    PowerlineDisable,
+    /// IOLinc: the relay follows the sensor input.
+    RelayFollowsInput,
+    /// IOLinc: the relay closes for the momentary time only.
+    Momentary,
+}
+
+// Every flag in the layouts below is bit n of the flags byte, set with
+// SetOpFlags 2n and cleared with 2n + 1. Not every SetOpFlags code has a bit:
+// the IOLinc's momentary B and C modes (0x12-0x15) are left to `RelayMode`.
+fn set_on(bit: u8) -> u8 {
+    bit * 2
+}
+
+fn set_off(bit: u8) -> u8 {
+    bit * 2 + 1
+}
+
+const BASIC_FLAGS : [(OpFlag, u8); 2] = [
+    (OpFlag::ProgramLock, 0),
+    (OpFlag::LedOnTx, 1),
+];
+
+const SWITCH_FLAGS : [(OpFlag, u8); 6] = [
+    (OpFlag::ProgramLock, 0),
+    (OpFlag::LedOnTx, 1),
+    (OpFlag::LedOff, 4),
+    (OpFlag::Beeper, 5),
+    (OpFlag::RfDisable, 6),
+    (OpFlag::PowerlineDisable, 7),
+];
+
+const DIMMER_FLAGS : [(OpFlag, u8); 7] = [
+    (OpFlag::ProgramLock, 0),
+    (OpFlag::LedOnTx, 1),
+    (OpFlag::ResumeDim, 2),
+    (OpFlag::LedOff, 4),
+    (OpFlag::Beeper, 5),
+    (OpFlag::RfDisable, 6),
+    (OpFlag::PowerlineDisable, 7),
+];
+
+const KEYPAD_FLAGS : [(OpFlag, u8); 8] = [
+    (OpFlag::ProgramLock, 0),
+    (OpFlag::LedOnTx, 1),
+    (OpFlag::ResumeDim, 2),
+    (OpFlag::EightKey, 3),
+    (OpFlag::LedOff, 4),
+    (OpFlag::Beeper, 5),
+    (OpFlag::RfDisable, 6),
+    (OpFlag::PowerlineDisable, 7),
+];
+
+const IOLINC_FLAGS : [(OpFlag, u8); 4] = [
+    (OpFlag::ProgramLock, 0),
+    (OpFlag::LedOnTx, 1),
+    (OpFlag::RelayFollowsInput, 2),
+    (OpFlag::Momentary, 3),
+];
+
+/// Which flags a device has, decided by its identity. Devices we know
+/// nothing about only get the flags every device shares.
+#[derive(Debug)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum FlagLayout {
+    Basic,
+    Switch,
+    Dimmer,
+    Keypad,
+    IoLinc,
+}
+
+impl FlagLayout {
+    pub fn from_identity(identity: Option<Identity>) -> FlagLayout {
+        match identity {
+            Some(identity) if identity.is_keypad() => FlagLayout::Keypad,
+            Some(identity) => match identity.category {
+                DIMMER_CATEGORY => FlagLayout::Dimmer,
+                SWITCH_CATEGORY => FlagLayout::Switch,
+                IO_CATEGORY => FlagLayout::IoLinc,
+                _ => FlagLayout::Basic,
+            },
+            None => FlagLayout::Basic,
+        }
+    }
+
+    /// Each flag the device has with its bit in the GetOpFlags byte.
+    fn specs(&self) -> &'static [(OpFlag, u8)] {
+        match *self {
+            FlagLayout::Basic => &BASIC_FLAGS,
+            FlagLayout::Switch => &SWITCH_FLAGS,
+            FlagLayout::Dimmer => &DIMMER_FLAGS,
+            FlagLayout::Keypad => &KEYPAD_FLAGS,
+            FlagLayout::IoLinc => &IOLINC_FLAGS,
+        }
+    }
+
+    /// The flags in the byte GetOpFlags returned.
+    pub fn decode(&self, flags: u8) -> Vec<(OpFlag, bool)> {
+        self.specs().iter().map(|&(flag, bit)| (flag, flags & (1 << bit) != 0)).collect()
+    }
+
+    /// The SetOpFlags cmd2 that sets `flag`, or `None` if the device does not have it.
+    pub fn set_code(&self, flag: OpFlag, on: bool) -> Option<u8> {
+        self.specs().iter()
+            .find(|&&(spec_flag, _)| spec_flag == flag)
+            .map(|&(_, bit)| if on { set_on(bit) } else { set_off(bit) })
+    }
+
+    /// `flags` after the SetOpFlags `code` was accepted.
+    pub fn apply(&self, flags: u8, code: u8) -> u8 {
+        match self.specs().iter().find(|&&(_, bit)| code >> 1 == bit) {
+            Some(&(_, bit)) if code == set_on(bit) => flags | (1 << bit),
+            Some(&(_, bit)) => flags & !(1 << bit),
+            None => flags,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum OpFlagsCommand {
+    /// The ACK carries the flags byte in cmd2.
+    Get,
+    /// One SetOpFlags cmd2 value, see `FlagLayout::set_code`.
+    Set(u8),
+}
+
+impl OpFlagsCommand {
+    pub fn cmd1(&self) -> u8 {
+        match *self {
+            OpFlagsCommand::Get => u8_command(Command::GetOpFlags),
+            OpFlagsCommand::Set(_) => u8_command(Command::SetOpFlags),
+        }
+    }
+
+    pub fn cmd2(&self) -> u8 {
+        match *self {
+            OpFlagsCommand::Get => 0x00,
+            OpFlagsCommand::Set(code) => code,
+        }
+    }
+
+    pub fn to_msg(&self, device: InsteonAddress, dialect: Dialect) -> Option<InsteonMsg> {
+        match *self {
+            // i2cs devices only accept their operating flags extended.
+            OpFlagsCommand::Set(_) if dialect.checksum().is_some() =>
+                dialect.extended(device, self.cmd1(), self.cmd2(), [0u8; 14]),
+            _ => Some(InsteonMsg::SendStandardMsg {
+                addr_to: device,
+                msg_flags: MessageFlags::direct(),
+                cmd1: self.cmd1(),
+                cmd2: self.cmd2(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use checksum::Checksum;
+
+    fn a() -> InsteonAddress {
+        InsteonAddress::new(0x1A, 0x2B, 0x3C)
+    }
+
+    #[test]
+    fn decodes_only_the_flags_of_the_layout() {
+        assert_eq!(FlagLayout::Basic.decode(0b0000_0011),
+                   vec![(OpFlag::ProgramLock, true), (OpFlag::LedOnTx, true)]);
+        let keypad = FlagLayout::Keypad.decode(0b0001_1000);
+        assert_eq!(keypad.len(), 8);
+        assert!(keypad.contains(&(OpFlag::EightKey, true)));
+        assert!(keypad.contains(&(OpFlag::LedOff, true)));
+        assert!(keypad.contains(&(OpFlag::Beeper, false)));
+        assert!(!FlagLayout::Switch.decode(0xFF).iter().any(|&(flag, _)| flag == OpFlag::ResumeDim));
+    }
+
+    #[test]
+    fn sets_bit_n_with_2n_and_clears_it_with_2n_plus_1() {
+        assert_eq!(FlagLayout::Dimmer.set_code(OpFlag::ResumeDim, true), Some(0x04));
+        assert_eq!(FlagLayout::Dimmer.set_code(OpFlag::ResumeDim, false), Some(0x05));
+        assert_eq!(FlagLayout::Keypad.set_code(OpFlag::EightKey, true), Some(0x06));
+        assert_eq!(FlagLayout::IoLinc.set_code(OpFlag::Momentary, false), Some(0x07));
+        assert_eq!(FlagLayout::Switch.set_code(OpFlag::EightKey, true), None);
+    }
+
+    #[test]
+    fn applies_accepted_codes_to_the_flags() {
+        let layout = FlagLayout::Dimmer;
+        assert_eq!(layout.apply(0x00, 0x04), 0b0000_0100);
+        assert_eq!(layout.apply(0xFF, 0x05), 0b1111_1011);
+        assert_eq!(layout.apply(0x00, 0x0F), 0b0000_0000);
+        // The dimmer has no bit 3, so the code leaves the flags alone.
+        assert_eq!(layout.apply(0x00, 0x06), 0x00);
+    }
+
+    #[test]
+    fn sets_flags_extended_only_for_i2cs() {
+        let set = OpFlagsCommand::Set(0x02);
+        match set.to_msg(a(), Dialect::Extended) {
+            Some(InsteonMsg::SendStandardMsg { cmd1, cmd2, .. }) => assert_eq!((cmd1, cmd2), (0x20, 0x02)),
+            other => panic!("{:?}", other),
+        }
+        match set.to_msg(a(), Dialect::ChecksummedExtended(Checksum::Classic)) {
+            Some(InsteonMsg::SendExtendedMsg { cmd1, cmd2, user_data, .. }) => {
+                assert_eq!((cmd1, cmd2), (0x20, 0x02));
+                assert!(Checksum::Classic.verify(cmd1, cmd2, &user_data));
+            },
+            other => panic!("{:?}", other),
+        }
+        match OpFlagsCommand::Get.to_msg(a(), Dialect::ChecksummedExtended(Checksum::Classic)) {
+            Some(InsteonMsg::SendStandardMsg { cmd1, cmd2, .. }) => assert_eq!((cmd1, cmd2), (0x1F, 0x00)),
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use robots::actors::{Actor, ActorCell, ActorContext, ActorRef, Any};
+
+use address::InsteonAddress;
+use insteon_structs::*;
+use timer;
+
+pub const PLM_WAIT_INTERVAL_MS :u64 = 1000;
+pub const PLM_RETRIES :usize = 4;
+/// A NAK ends the record walk only once it has been repeated this many
+/// times, since a busy PLM NAKs as well.
+pub const PLM_END_CONFIRMATIONS :usize = 2;
+
+/// One record of the modem's own ALL-Link database.
+#[derive(Debug)]
+#[derive(Copy, Clone)]
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct PlmLink {
+    /// Controller of `address` for `group` if set, responder otherwise.
+    pub controller: bool,
+    pub group: u8,
+    pub address: InsteonAddress,
+    pub data: [u8; 3],
+}
+
+impl PlmLink {
+    const CONTROLLER :u8 = 0b0100_0000;
+
+    pub fn from_response(msg: &InsteonMsg) -> Option<PlmLink> {
+        match *msg {
+            InsteonMsg::AllLinkRecordResponse {
+                all_link_record_flags, all_link_group, id, link_data } => Some(PlmLink {
+                    controller: all_link_record_flags & PlmLink::CONTROLLER != 0,
+                    group: all_link_group,
+                    address: id,
+                    data: link_data,
+                }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PlmLinks {
+    pub success: bool,
+    pub links: Vec<PlmLink>,
+}
+
+#[derive(Clone)]
+pub enum PlmLinksActorMsg {
+    Read(ActorRef),
+    Timeout(usize),
+    Resend(usize),
+}
+
+struct WalkState {
+    future: ActorRef,
+    links: Vec<PlmLink>,
+    /// The query in flight, `GetFirstAllLinkRecord` or `GetNextAllLinkRecord`.
+    query: InsteonMsg,
+    /// Bumped on every send so that stale timers are ignored.
+    attempt: usize,
+    retries: usize,
+    naks: usize,
+}
+
+/// Walks the modem's database with Get First/Next ALL-Link Record, collecting
+/// every 0x57 record it answers with until it NAKs the next query.
+pub struct PlmLinksActor {
+    ser_tx_actor : ActorRef,
+    state        : Mutex<Option<WalkState>>,
+}
+
+impl PlmLinksActor {
+    pub fn new(ser_tx_actor: ActorRef) -> PlmLinksActor {
+        PlmLinksActor {
+            ser_tx_actor: ser_tx_actor,
+            state: Mutex::new(None),
+        }
+    }
+
+    fn send(&self, state: &mut WalkState, context: &ActorCell) {
+        state.attempt += 1;
+        self.ser_tx_actor.tell_to(self.ser_tx_actor.clone(), ActorMsg::Send(state.query));
+        timer::schedule(context.actor_ref(), PlmLinksActorMsg::Timeout(state.attempt),
+                        Duration::from_millis(PLM_WAIT_INTERVAL_MS));
+    }
+
+    fn finish(&self, state: WalkState, success: bool, context: &ActorCell) {
+        info!("Read {} link(s) from the modem", state.links.len());
+        context.complete(state.future, PlmLinks {
+            success: success,
+            links: state.links,
+        });
+        context.kill_me();
+    }
+
+    pub fn handle_insteon_msg(&self, message: InsteonMsg, context: ActorCell) {
+        let mut interior = self.state.lock().unwrap();
+
+        let done = match *interior {
+            Some(ref mut state) => match message {
+                InsteonMsg::AllLinkRecordResponse { .. } => {
+                    let link = PlmLink::from_response(&message).unwrap();
+                    trace!("Modem link: {:?}", link);
+                    state.links.push(link);
+                    state.query = InsteonMsg::GetNextAllLinkRecord {};
+                    state.retries = 0;
+                    state.naks = 0;
+                    self.send(state, &context);
+                    false
+                },
+
+                InsteonMsg::GetFirstAllLinkRecordEcho { status: PlmStatus::Nak } |
+                InsteonMsg::GetNextAllLinkRecordEcho { status: PlmStatus::Nak } => {
+                    state.naks += 1;
+                    if state.naks >= PLM_END_CONFIRMATIONS {
+                        true
+                    } else {
+                        state.attempt += 1;
+                        timer::schedule(context.actor_ref(), PlmLinksActorMsg::Resend(state.attempt),
+                                        Duration::from_millis(PLM_WAIT_INTERVAL_MS));
+                        false
+                    }
+                },
+
+                _ => false,
+            },
+            None => false,
+        };
+
+        if done {
+            let state = interior.take().unwrap();
+            self.finish(state, true, &context);
+        }
+    }
+
+    pub fn handle_links_msg(&self, message: PlmLinksActorMsg, context: ActorCell) {
+        let mut interior = self.state.lock().unwrap();
+
+        match message {
+            PlmLinksActorMsg::Read(future) => {
+                let mut state = WalkState {
+                    future: future,
+                    links: Vec::new(),
+                    query: InsteonMsg::GetFirstAllLinkRecord {},
+                    attempt: 0,
+                    retries: 0,
+                    naks: 0,
+                };
+                self.send(&mut state, &context);
+                *interior = Some(state);
+            },
+
+            PlmLinksActorMsg::Resend(attempt) => {
+                if let Some(ref mut state) = *interior {
+                    if state.attempt == attempt {
+                        self.send(state, &context);
+                    }
+                }
+            },
+
+            PlmLinksActorMsg::Timeout(attempt) => {
+                let give_up = match *interior {
+                    Some(ref mut state) if state.attempt == attempt => {
+                        state.retries += 1;
+                        if state.retries > PLM_RETRIES {
+                            true
+                        } else {
+                            debug!("No record from the modem, asking again");
+                            self.send(state, &context);
+                            false
+                        }
+                    },
+                    _ => false,
+                };
+
+                if give_up {
+                    warn!("The modem stopped answering record queries");
+                    let state = interior.take().unwrap();
+                    self.finish(state, false, &context);
+                }
+            },
+        }
+    }
+}
+
+impl Actor for PlmLinksActor {
+    fn receive(&self, msg: Box<Any>, context: ActorCell) {
+        match msg.downcast_ref::<PlmLinksActorMsg>() {
+            Some(links_msg) => self.handle_links_msg(links_msg.clone(), context),
+            None => match msg.downcast_ref::<InsteonMsg>() {
+                Some(insteon_msg) => self.handle_insteon_msg(*insteon_msg, context),
+                None => unreachable!(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_links_from_record_responses() {
+        let response = InsteonMsg::AllLinkRecordResponse {
+            all_link_record_flags: 0xE2,
+            all_link_group: 0x01,
+            id: InsteonAddress::new(0x1A, 0xD0, 0xF4),
+            link_data: [0x01, 0x20, 0x41],
+        };
+        assert_eq!(PlmLink::from_response(&response), Some(PlmLink {
+            controller: true,
+            group: 0x01,
+            address: InsteonAddress::new(0x1A, 0xD0, 0xF4),
+            data: [0x01, 0x20, 0x41],
+        }));
+
+        let responder = InsteonMsg::AllLinkRecordResponse {
+            all_link_record_flags: 0xA2,
+            all_link_group: 0x00,
+            id: InsteonAddress::new(0x1A, 0xD0, 0xF4),
+            link_data: [0x00; 3],
+        };
+        assert!(!PlmLink::from_response(&responder).unwrap().controller);
+
+        assert_eq!(PlmLink::from_response(&InsteonMsg::GetNextAllLinkRecord {}), None);
+    }
+}
@@ -2,7 +2,6 @@ package main
 
 import (
 	"./messages"
-	"encoding/binary"
 	"golang.org/x/net/context"
 	"google.golang.org/grpc"
 	"log"
@@ -19,14 +18,6 @@ func toUint32(target string) uint32 {
 	return uint32(result)
 }
 
-func toByte(target string) uint8 {
-	result, err := strconv.ParseUint(target, 16, 8)
-	if err != nil {
-		log.Fatalf("unable to convert %s to a byte: %s" ,result , err)
-	}
-	return uint8(result)
-}
-
 func connectToController(hostname string) *grpc.ClientConn {
 	conn, err := grpc.Dial(hostname + ":50051", grpc.WithInsecure())
 	if err != nil {
@@ -44,12 +35,10 @@ func main() {
 
 	ctx, cancel := context.WithTimeout(context.Background(), time.Second)
 	defer cancel()
-	var addrSlice = []byte{0, toByte(os.Args[2]), toByte(os.Args[3]), toByte(os.Args[4])}
-
-	data := binary.BigEndian.Uint32(addrSlice)
-	level := toUint32(os.Args[5])
+	address := os.Args[2]
+	level := toUint32(os.Args[3])
 	lightCtl := messages.CmdMsg_LightControl{
-		LightControl: &messages.LightControl{Device: data, Level: level}}
+		LightControl: &messages.LightControl{Address: address, Level: level}}
 	msg := messages.CmdMsg{Cmd: &lightCtl}
 	client.SendCmd(ctx, &msg)
 }
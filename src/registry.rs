@@ -0,0 +1,353 @@
+use std::collections::HashMap;
+
+use address::InsteonAddress;
+use catalog;
+use engine::{EngineVersion, Dialect};
+use fan::FanSpeed;
+use garage::{GarageDoor, SENSOR_INPUT};
+use io::{IoEvent, IoState, IO_CATEGORY};
+use insteon_structs::*;
+use keypad::{ButtonEvent, KeypadState, KEYPAD_DEVICES};
+use plm::PlmLink;
+use sensors::{unix_time, SensorEvent, SensorKind, SensorState, SENSOR_CATEGORY};
+use thermostat::{ThermostatEvent, ThermostatState};
+
+/// The category, subcategory and firmware a device reports about itself.
+#[derive(Debug)]
+#[derive(Copy, Clone)]
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct Identity {
+    pub category: u8,
+    pub subcategory: u8,
+    pub firmware: u8,
+}
+
+impl Identity {
+    const SET_BUTTON_RESPONDER :u8 = 0x01;
+    const SET_BUTTON_CONTROLLER :u8 = 0x02;
+
+    /// Decodes the SET-button broadcast a device sends when linked or when
+    /// asked for its ID; the to-address carries the identity.
+    pub fn from_broadcast(msg: &InsteonMsg) -> Option<(InsteonAddress, Identity)> {
+        match *msg {
+            InsteonMsg::StandardMsg { addr_from, addr_to, msg_flags, cmd1, .. }
+                if msg_flags.msg_type == MessageType::Broadcast &&
+                   (cmd1 == Identity::SET_BUTTON_RESPONDER ||
+                    cmd1 == Identity::SET_BUTTON_CONTROLLER) => {
+                let id = addr_to.bytes();
+                Some((addr_from, Identity {
+                    category: id[0],
+                    subcategory: id[1],
+                    firmware: id[2],
+                }))
+            },
+
+            InsteonMsg::AllLinkingCompleted {
+                id, device_category, device_subcategory, firmware_version, .. } =>
+                Some((id, Identity {
+                    category: device_category,
+                    subcategory: device_subcategory,
+                    firmware: firmware_version,
+                })),
+
+            _ => None,
+        }
+    }
+
+    /// The modem keeps the category, subcategory and firmware a device
+    /// reported while linking in the data of the controller record it made
+    /// for it, so they survive restarts. Responder records carry the
+    /// device's own link data instead.
+    pub fn from_link(link: &PlmLink) -> Option<Identity> {
+        match link.data {
+            _ if !link.controller => None,
+            [0, 0, 0] => None,
+            [category, subcategory, firmware] => Some(Identity {
+                category: category,
+                subcategory: subcategory,
+                firmware: firmware,
+            }),
+        }
+    }
+
+    pub fn model(&self) -> Option<&'static str> {
+        catalog::lookup(self.category, self.subcategory).map(|product| product.model)
+    }
+
+    pub fn description(&self) -> Option<&'static str> {
+        catalog::lookup(self.category, self.subcategory).map(|product| product.description)
+    }
+
+    pub fn is_keypad(&self) -> bool {
+        KEYPAD_DEVICES.contains(&(self.category, self.subcategory))
+    }
+
+    pub fn category_name(&self) -> Option<&'static str> {
+        catalog::category_name(self.category)
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Serialize, Deserialize)]
+pub struct DeviceInfo {
+    pub address: InsteonAddress,
+    pub identity: Option<Identity>,
+    pub engine: Option<EngineVersion>,
+    pub thermostat: Option<ThermostatState>,
+    pub io: Option<IoState>,
+    pub garage: Option<GarageDoor>,
+    pub keypad: Option<KeypadState>,
+    pub sensor: Option<SensorState>,
+    /// The last known speed of a FanLinc's fan.
+    pub fan: Option<FanSpeed>,
+    /// The operating flags byte, as GetOpFlags last returned it.
+    pub op_flags: Option<u8>,
+}
+
+impl DeviceInfo {
+    pub fn new(address: InsteonAddress) -> DeviceInfo {
+        DeviceInfo {
+            address: address,
+            identity: None,
+            engine: None,
+            thermostat: None,
+            io: None,
+            garage: None,
+            keypad: None,
+            sensor: None,
+            fan: None,
+            op_flags: None,
+        }
+    }
+}
+
+/// Everything the daemon has learned about the devices it has heard from.
+#[derive(Default)]
+pub struct DeviceRegistry {
+    devices: HashMap<InsteonAddress, DeviceInfo>,
+}
+
+impl DeviceRegistry {
+    pub fn new() -> DeviceRegistry {
+        DeviceRegistry::default()
+    }
+
+    pub fn get(&self, address: &InsteonAddress) -> Option<&DeviceInfo> {
+        self.devices.get(address)
+    }
+
+    pub fn devices(&self) -> Vec<DeviceInfo> {
+        let mut devices : Vec<DeviceInfo> = self.devices.values().cloned().collect();
+        devices.sort_by_key(|info| info.address);
+        devices
+    }
+
+    pub fn identity(&self, address: &InsteonAddress) -> Option<Identity> {
+        self.devices.get(address).and_then(|info| info.identity)
+    }
+
+    pub fn engine(&self, address: &InsteonAddress) -> Option<EngineVersion> {
+        self.devices.get(address).and_then(|info| info.engine)
+    }
+
+    /// How extended operations must be phrased for the device, once its
+    /// engine is known.
+    pub fn dialect(&self, address: &InsteonAddress) -> Option<Dialect> {
+        self.devices.get(address)
+            .and_then(|info| info.engine.map(|engine| engine.dialect(info.identity)))
+    }
+
+    pub fn set_engine(&mut self, address: InsteonAddress, engine: EngineVersion) {
+        info!("{} runs engine {:?}", address, engine);
+        self.entry(address).engine = Some(engine);
+    }
+
+    pub fn thermostat(&self, address: &InsteonAddress) -> Option<ThermostatState> {
+        self.devices.get(address).and_then(|info| info.thermostat)
+    }
+
+    pub fn set_thermostat(&mut self, address: InsteonAddress, state: ThermostatState) {
+        self.entry(address).thermostat = Some(state);
+    }
+
+    pub fn io(&self, address: &InsteonAddress) -> Option<IoState> {
+        self.devices.get(address).and_then(|info| info.io)
+    }
+
+    pub fn set_io_inputs(&mut self, address: InsteonAddress, inputs: u8) {
+        let entry = self.entry(address);
+        let io = entry.io.get_or_insert_with(IoState::default);
+        io.inputs = inputs;
+        if let Some(ref mut door) = entry.garage {
+            door.on_sensor(io.input(SENSOR_INPUT));
+        }
+    }
+
+    pub fn garage(&self, address: &InsteonAddress) -> Option<GarageDoor> {
+        self.devices.get(address).and_then(|info| info.garage)
+    }
+
+    pub fn garage_mut(&mut self, address: &InsteonAddress) -> Option<&mut GarageDoor> {
+        self.devices.get_mut(address).and_then(|info| info.garage.as_mut())
+    }
+
+    pub fn set_garage(&mut self, address: InsteonAddress, door: GarageDoor) {
+        self.entry(address).garage = Some(door);
+    }
+
+    pub fn set_keypad_leds(&mut self, address: InsteonAddress, leds: u8) {
+        self.entry(address).keypad.get_or_insert_with(KeypadState::default).leds = leds;
+    }
+
+    pub fn fan(&self, address: &InsteonAddress) -> Option<FanSpeed> {
+        self.devices.get(address).and_then(|info| info.fan)
+    }
+
+    pub fn set_fan(&mut self, address: InsteonAddress, speed: FanSpeed) {
+        info!("FanLinc {} fan is {:?}", address, speed);
+        self.entry(address).fan = Some(speed);
+    }
+
+    pub fn op_flags(&self, address: &InsteonAddress) -> Option<u8> {
+        self.devices.get(address).and_then(|info| info.op_flags)
+    }
+
+    pub fn set_op_flags(&mut self, address: InsteonAddress, flags: u8) {
+        self.entry(address).op_flags = Some(flags);
+    }
+
+    /// Whether `address` is a battery device that sleeps between transmissions.
+    pub fn is_sleepy(&self, address: &InsteonAddress) -> bool {
+        self.devices.get(address)
+            .and_then(|info| info.identity)
+            .map_or(false, |identity| identity.category == SENSOR_CATEGORY)
+    }
+
+    pub fn sensors(&self) -> Vec<(InsteonAddress, SensorState)> {
+        self.devices().iter()
+            .filter_map(|info| info.sensor.map(|sensor| (info.address, sensor)))
+            .collect()
+    }
+
+    /// The typed event `msg` carries, if it comes from a known battery sensor.
+    pub fn sensor_event(&self, msg: &InsteonMsg) -> Option<(InsteonAddress, SensorEvent)> {
+        let addr_from = match *msg {
+            InsteonMsg::StandardMsg { addr_from, .. } => addr_from,
+            _ => return None,
+        };
+
+        self.devices.get(&addr_from)
+            .and_then(|info| info.identity)
+            .and_then(|identity| SensorKind::from_identity(&identity))
+            .and_then(|kind| SensorEvent::from_broadcast(kind, msg))
+    }
+
+    /// Fills in the identity of every device the modem is a controller of
+    /// and that has not identified itself; what a device reports about
+    /// itself always wins.
+    pub fn learn_links(&mut self, links: &[PlmLink]) {
+        for link in links {
+            if let Some(identity) = Identity::from_link(link) {
+                let entry = self.entry(link.address);
+                if entry.identity.is_none() {
+                    debug!("{} is linked to the modem as {:?}", link.address, identity);
+                    entry.identity = Some(identity);
+                }
+            }
+        }
+    }
+
+    fn entry(&mut self, address: InsteonAddress) -> &mut DeviceInfo {
+        self.devices.entry(address).or_insert_with(|| DeviceInfo::new(address))
+    }
+
+    /// Records whatever `msg` reveals about the device that sent it.
+    pub fn observe(&mut self, msg: &InsteonMsg) {
+        if let Some((address, identity)) = Identity::from_broadcast(msg) {
+            info!("{} identified as {} {}", address,
+                  identity.model().unwrap_or("unknown model"),
+                  identity.description().unwrap_or(""));
+            self.entry(address).identity = Some(identity);
+        }
+
+        if let Some((address, event)) = ThermostatEvent::from_msg(msg) {
+            info!("Thermostat {} reported {:?}", address, event);
+            self.entry(address).thermostat.get_or_insert_with(ThermostatState::default).apply(event);
+        }
+
+        if let InsteonMsg::StandardMsg { addr_from, .. } = *msg {
+            let event = self.sensor_event(msg);
+            let entry = self.entry(addr_from);
+            if let Some(kind) = entry.identity.and_then(|identity| SensorKind::from_identity(&identity)) {
+                let sensor = entry.sensor.get_or_insert(SensorState::new(kind));
+                sensor.last_seen = unix_time();
+                if let Some((_, event)) = event {
+                    info!("Sensor {} reported {:?}", addr_from, event);
+                    sensor.apply(event);
+                }
+            }
+        }
+
+        if let Some((address, event)) = ButtonEvent::from_broadcast(msg) {
+            let entry = self.entry(address);
+            if entry.identity.map_or(false, |identity| identity.is_keypad()) {
+                info!("KeypadLinc {} button {}: {:?}", address, event.button, event.action);
+                entry.keypad.get_or_insert_with(KeypadState::default).apply(event);
+            }
+        }
+
+        if let Some((address, event)) = IoEvent::from_broadcast(msg) {
+            let entry = self.entry(address);
+            if entry.identity.map(|identity| identity.category) == Some(IO_CATEGORY) ||
+               entry.garage.is_some() {
+                info!("I/O module {} input {} is {}", address, event.input,
+                      if event.on { "on" } else { "off" });
+                entry.io.get_or_insert_with(IoState::default).apply(event);
+            }
+            if let Some(ref mut door) = entry.garage {
+                if event.input == SENSOR_INPUT {
+                    door.on_sensor(event.on);
+                    info!("Garage door {} is {:?}", address, door.state);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn a() -> InsteonAddress {
+        InsteonAddress::new(0x3C, 0x11, 0x9E)
+    }
+
+    fn link(controller: bool, data: [u8; 3]) -> PlmLink {
+        PlmLink { controller: controller, group: 0x01, address: a(), data: data }
+    }
+
+    #[test]
+    fn learns_identities_only_from_controller_links() {
+        let mut registry = DeviceRegistry::new();
+        registry.learn_links(&[link(false, [0x10, 0x01, 0x45]), link(true, [0, 0, 0])]);
+        assert_eq!(registry.identity(&a()), None);
+
+        registry.learn_links(&[link(true, [0x10, 0x01, 0x45])]);
+        assert_eq!(registry.identity(&a()),
+                   Some(Identity { category: 0x10, subcategory: 0x01, firmware: 0x45 }));
+        assert!(registry.is_sleepy(&a()));
+    }
+
+    #[test]
+    fn never_replaces_a_reported_identity() {
+        let mut registry = DeviceRegistry::new();
+        registry.observe(&InsteonMsg::AllLinkingCompleted {
+            link_code: 0x01, all_link_group: 0x01, id: a(),
+            device_category: 0x10, device_subcategory: 0x02, firmware_version: 0x41,
+        });
+        registry.learn_links(&[link(true, [0x10, 0x01, 0x45])]);
+        assert_eq!(registry.identity(&a()),
+                   Some(Identity { category: 0x10, subcategory: 0x02, firmware: 0x41 }));
+    }
+}
@@ -1,127 +1,466 @@
 use grpc;
 use bus::Bus;
 
-use std::thread;
+use std::collections::{HashMap, VecDeque};
 use std::time::Duration;
 use std::fmt::Debug;
 use std::sync::Mutex;
 use std::sync::Arc;
-use futures::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use futures::Stream;
+use futures::sync::mpsc;
+use protobuf::RepeatedField;
 
-use tokio_timer::sleep;
 use tokio_core::reactor::Remote;
-use robots::actors::{ActorContext, ActorRef, ActorSystem, Actor, Any, ActorCell, Props};
+use robots::actors::{ActorContext, ActorRef, ActorSystem, Actor, Any, ActorCell, Props, Message};
 
 use messages_grpc::*;
 use messages::*;
 use insteon_structs::*;
+use address::InsteonAddress;
+use codec::FramingErrors;
+use timer;
+use device_control::*;
+use registry::{DeviceRegistry, DeviceInfo, Identity};
+use engine::*;
+use fan::*;
+use opflags::*;
+use aldb::*;
+use plm::*;
+use linking::*;
+use group::*;
+use thermostat::*;
+use io::*;
+use garage::*;
+use keypad::*;
+use sensors::*;
+use wake::{TicketState, WakeActor, WakeActorMsg, WakeQueue};
 
 #[derive(Clone)]
 pub enum RpcActorMsg {
-    Set(LightControl),
-    SetReliable(CmdMsg),
+    Set(CmdMsg),
+    Reliable(Request),
+    Aldb(InsteonAddress, Dialect, AldbOp),
+    ModemLinks,
+    StartLinking(LinkCode, u8, Duration),
+    CancelLinking,
+    Group(u8, DeviceCommand),
+    /// A battery device with requests held for it has woken up.
+    Wake(InsteonAddress),
+    /// The reliable request, ALDB operation or wake on the device has finished.
+    Done(InsteonAddress),
+}
+
+impl RpcActorMsg {
+    /// The device the message keeps busy until it is `Done`.
+    fn device(&self) -> Option<InsteonAddress> {
+        match *self {
+            RpcActorMsg::Reliable(ref req) => req.device(),
+            RpcActorMsg::Aldb(device, _, _) | RpcActorMsg::Wake(device) => Some(device),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Clone)]
 pub enum RpcReqActorMsg {
-    Set(ActorRef, LightControl),
-    SetReliable(ActorRef, CmdMsg),
+    Set(ActorRef, CmdMsg),
+    Reliable(ActorRef, Request),
     Timeout(usize)
 }
 
+/// A request the `RpcReqActor` resends until the device answers it.
+///
+/// The dialect is `None` only in requests held for a battery device whose
+/// engine is not known yet; they are phrased once it has woken up and
+/// answered the engine version request held in front of them.
+#[derive(Clone)]
+pub enum Request {
+    Cmd(CmdMsg),
+    Status(InsteonAddress),
+    Identify(InsteonAddress),
+    EngineVersion(InsteonAddress),
+    /// Only held for battery devices; the `AldbActor` writes to the others.
+    WriteAldb(InsteonAddress, Option<Dialect>, AldbRecord),
+    ThermostatStatus(InsteonAddress, Option<Dialect>),
+    Thermostat(InsteonAddress, Option<Dialect>, ThermostatCommand),
+    Io(InsteonAddress, Option<Dialect>, IoCommand),
+    KeypadLeds(InsteonAddress),
+    Keypad(InsteonAddress, Option<Dialect>, KeypadCommand),
+    FanSpeed(InsteonAddress, Option<Dialect>, FanSpeed),
+    FanStatus(InsteonAddress),
+    OpFlags(InsteonAddress, Option<Dialect>, OpFlagsCommand),
+}
+
+/// What a `Request` completes its future with.
+pub enum Reply {
+    Ack(Ack),
+    Status(DeviceStatus),
+    Identity(DeviceIdentity),
+    Engine(EngineInfo),
+    Thermostat(ThermostatStatus),
+    Zone(ZoneReading),
+    Io(IoReading),
+    Leds(KeypadLeds),
+    Fan(FanStatus),
+    OpFlags(OpFlagsReport),
+}
+
+impl Request {
+    pub fn msg(&self) -> Option<InsteonMsg> {
+        match *self {
+            Request::Cmd(ref cmd) => cmd_to_msg(cmd),
+            Request::Status(device) => Some(status_request(device)),
+            Request::Identify(device) => Some(id_request(device)),
+            Request::EngineVersion(device) => Some(engine_version_request(device)),
+            Request::WriteAldb(device, Some(dialect), ref record) => write_request(dialect, device, record),
+            Request::ThermostatStatus(device, Some(dialect)) => status_data_request(device, dialect),
+            Request::Thermostat(device, Some(dialect), cmd) => Some(cmd.to_msg(device, dialect)),
+            Request::Io(device, Some(dialect), cmd) => cmd.to_msg(device, dialect),
+            Request::KeypadLeds(device) => Some(led_status_request(device)),
+            Request::Keypad(device, Some(dialect), cmd) => cmd.to_msg(device, dialect),
+            Request::FanSpeed(device, Some(dialect), speed) => speed_request(dialect, device, speed),
+            Request::FanStatus(device) => Some(fan_status_request(device)),
+            Request::OpFlags(device, Some(dialect), cmd) => cmd.to_msg(device, dialect),
+            _ => None,
+        }
+    }
+
+    /// The request phrased in `dialect`, if it was held without one.
+    pub fn with_dialect(&self, dialect: Dialect) -> Request {
+        match *self {
+            Request::WriteAldb(device, None, record) => Request::WriteAldb(device, Some(dialect), record),
+            Request::ThermostatStatus(device, None) => Request::ThermostatStatus(device, Some(dialect)),
+            Request::Thermostat(device, None, cmd) => Request::Thermostat(device, Some(dialect), cmd),
+            Request::Io(device, None, cmd) => Request::Io(device, Some(dialect), cmd),
+            Request::Keypad(device, None, cmd) => Request::Keypad(device, Some(dialect), cmd),
+            Request::FanSpeed(device, None, speed) => Request::FanSpeed(device, Some(dialect), speed),
+            Request::OpFlags(device, None, cmd) => Request::OpFlags(device, Some(dialect), cmd),
+            ref request => request.clone(),
+        }
+    }
+
+    pub fn device(&self) -> Option<InsteonAddress> {
+        match *self {
+            Request::Cmd(ref cmd) => cmd_device(cmd),
+            Request::Status(device) | Request::Identify(device) |
+            Request::EngineVersion(device) | Request::WriteAldb(device, _, _) |
+            Request::ThermostatStatus(device, _) | Request::Thermostat(device, _, _) |
+            Request::Io(device, _, _) | Request::KeypadLeds(device) |
+            Request::Keypad(device, _, _) | Request::FanSpeed(device, _, _) |
+            Request::FanStatus(device) | Request::OpFlags(device, _, _) => Some(device),
+        }
+    }
+
+    /// The reply to complete the request with, if `message` answers it.
+    pub fn reply(&self, message: &InsteonMsg) -> Option<Reply> {
+        let device = match self.device() {
+            Some(device) => device,
+            None => return None,
+        };
+
+        match *self {
+            // The ACK to a direct command echoes its cmd1.
+            Request::Cmd(_) => {
+                let cmd1 = match self.msg() {
+                    Some(InsteonMsg::SendStandardMsg { cmd1, .. }) => cmd1,
+                    _ => return None,
+                };
+                match message.direct_ack_from(device) {
+                    Some((acked, _)) if acked == cmd1 => {
+                        let mut ack = Ack::new();
+                        ack.set_success(true);
+                        Some(Reply::Ack(ack))
+                    },
+                    _ => None,
+                }
+            },
+            // Its ACK carries the ALDB delta in cmd1, so it can only be told
+            // apart from other ACKs by being the one request to the device
+            // the `RpcActor` lets through.
+            Request::Status(_) => StatusReport::from_ack(message, device).map(|report| {
+                let mut status = DeviceStatus::new();
+                status.set_success(true);
+                status.set_level(report.level_percent());
+                status.set_raw_level(report.level as u32);
+                status.set_aldb_delta(report.aldb_delta as u32);
+                Reply::Status(status)
+            }),
+            Request::Identify(_) => match Identity::from_broadcast(message) {
+                Some((addr_from, identity)) if addr_from == device => {
+                    let mut info = DeviceInfo::new(device);
+                    info.identity = Some(identity);
+                    Some(Reply::Identity(device_identity(&info)))
+                },
+                _ => None,
+            },
+            Request::EngineVersion(_) =>
+                EngineVersion::from_reply(message, device).map(|engine| Reply::Engine(engine_info(engine))),
+            Request::WriteAldb(..) => match message.direct_ack_from(device) {
+                Some((READ_WRITE_ALDB, _)) => Some(success_ack()),
+                _ => None,
+            },
+            Request::ThermostatStatus(..) => ThermostatState::from_status(message, device)
+                .map(|state| Reply::Thermostat(thermostat_status(&state))),
+            Request::Thermostat(_, _, cmd) => match message.direct_ack_from(device) {
+                Some((cmd1, cmd2)) if cmd1 == cmd.cmd1() => match cmd {
+                    ThermostatCommand::ZoneInfo(info) => Some(Reply::Zone(zone_reading(info, cmd2))),
+                    _ => Some(success_ack()),
+                },
+                _ => None,
+            },
+            Request::Io(_, _, cmd) => match message.direct_ack_from(device) {
+                Some((cmd1, cmd2)) if cmd1 == cmd.cmd1() => match cmd {
+                    IoCommand::ReadInputs | IoCommand::SensorValue(_) => Some(Reply::Io(io_reading(cmd2))),
+                    _ => Some(success_ack()),
+                },
+                _ => None,
+            },
+            Request::KeypadLeds(_) => message.direct_ack_from(device).map(|(_, leds)| {
+                let mut response = KeypadLeds::new();
+                response.set_success(true);
+                response.set_leds(leds as u32);
+                Reply::Leds(response)
+            }),
+            Request::Keypad(_, _, cmd) => match message.direct_ack_from(device) {
+                Some((cmd1, _)) if cmd1 == cmd.cmd1() => Some(success_ack()),
+                _ => None,
+            },
+            // The status ACK carries the ALDB delta in cmd1, not the command.
+            Request::FanStatus(_) =>
+                message.direct_ack_from(device).map(|(_, level)| Reply::Fan(fan_status(level))),
+            Request::FanSpeed(..) => match message.direct_ack_from(device) {
+                Some((cmd1, _)) if cmd1 == u8_command(Command::On) => Some(success_ack()),
+                _ => None,
+            },
+            // The flags only mean something once the device type is known,
+            // so the RPC decodes them from the registry.
+            Request::OpFlags(_, _, OpFlagsCommand::Get) => match message.direct_ack_from(device) {
+                Some((cmd1, flags)) if cmd1 == OpFlagsCommand::Get.cmd1() => {
+                    let mut report = OpFlagsReport::new();
+                    report.set_success(true);
+                    report.set_raw(flags as u32);
+                    Some(Reply::OpFlags(report))
+                },
+                _ => None,
+            },
+            Request::OpFlags(_, _, cmd) => match message.direct_ack_from(device) {
+                Some((cmd1, _)) if cmd1 == cmd.cmd1() => Some(success_ack()),
+                _ => None,
+            },
+        }
+    }
+
+    /// Records what the reply to this request taught us about the device.
+    pub fn learn(&self, message: &InsteonMsg, registry: &mut DeviceRegistry) {
+        match *self {
+            Request::EngineVersion(device) => {
+                if let Some(engine) = EngineVersion::from_reply(message, device) {
+                    registry.set_engine(device, engine);
+                }
+            },
+            Request::ThermostatStatus(device, _) => {
+                if let Some(state) = ThermostatState::from_status(message, device) {
+                    registry.set_thermostat(device, state);
+                }
+            },
+            Request::Io(device, _, IoCommand::ReadInputs) => {
+                if let Some((cmd1, inputs)) = message.direct_ack_from(device) {
+                    if cmd1 == IoCommand::ReadInputs.cmd1() {
+                        registry.set_io_inputs(device, inputs);
+                    }
+                }
+            },
+            Request::KeypadLeds(device) => {
+                if let Some((_, leds)) = message.direct_ack_from(device) {
+                    registry.set_keypad_leds(device, leds);
+                }
+            },
+            Request::Keypad(device, _, KeypadCommand::LedMask(leds)) => {
+                if message.direct_ack_from(device).is_some() {
+                    registry.set_keypad_leds(device, leds);
+                }
+            },
+            Request::FanStatus(device) => {
+                if let Some((_, level)) = message.direct_ack_from(device) {
+                    registry.set_fan(device, FanSpeed::from_level(level));
+                }
+            },
+            Request::FanSpeed(device, _, speed) => {
+                if message.direct_ack_from(device).is_some() {
+                    registry.set_fan(device, speed);
+                }
+            },
+            Request::OpFlags(device, _, OpFlagsCommand::Get) => {
+                if let Some((_, flags)) = message.direct_ack_from(device) {
+                    registry.set_op_flags(device, flags);
+                }
+            },
+            Request::OpFlags(device, _, OpFlagsCommand::Set(code)) => {
+                if message.direct_ack_from(device).is_some() {
+                    if let Some(flags) = registry.op_flags(&device) {
+                        let layout = FlagLayout::from_identity(registry.identity(&device));
+                        registry.set_op_flags(device, layout.apply(flags, code));
+                    }
+                }
+            },
+            _ => (),
+        }
+    }
+
+    fn failure(&self) -> Reply {
+        match *self {
+            Request::Cmd(_) | Request::WriteAldb(..) => Reply::Ack(Ack::new()),
+            Request::Status(_) => Reply::Status(DeviceStatus::new()),
+            Request::Identify(_) => Reply::Identity(DeviceIdentity::new()),
+            Request::EngineVersion(_) => Reply::Engine(EngineInfo::new()),
+            Request::ThermostatStatus(..) => Reply::Thermostat(ThermostatStatus::new()),
+            Request::Thermostat(_, _, ThermostatCommand::ZoneInfo(_)) => Reply::Zone(ZoneReading::new()),
+            Request::Thermostat(..) => Reply::Ack(Ack::new()),
+            Request::Io(_, _, IoCommand::ReadInputs) |
+            Request::Io(_, _, IoCommand::SensorValue(_)) => Reply::Io(IoReading::new()),
+            Request::FanStatus(_) => Reply::Fan(FanStatus::new()),
+            Request::OpFlags(_, _, OpFlagsCommand::Get) => Reply::OpFlags(OpFlagsReport::new()),
+            Request::Io(..) | Request::Keypad(..) | Request::FanSpeed(..) |
+            Request::OpFlags(..) => Reply::Ack(Ack::new()),
+            Request::KeypadLeds(_) => Reply::Leds(KeypadLeds::new()),
+        }
+    }
+}
+
+impl Reply {
+    fn complete(self, context: &ActorCell, future: ActorRef) {
+        match self {
+            Reply::Ack(ack) => context.complete(future, ack),
+            Reply::Status(status) => context.complete(future, status),
+            Reply::Identity(identity) => context.complete(future, identity),
+            Reply::Engine(engine) => context.complete(future, engine),
+            Reply::Thermostat(status) => context.complete(future, status),
+            Reply::Zone(reading) => context.complete(future, reading),
+            Reply::Io(reading) => context.complete(future, reading),
+            Reply::Leds(leds) => context.complete(future, leds),
+            Reply::Fan(status) => context.complete(future, status),
+            Reply::OpFlags(report) => context.complete(future, report),
+        }
+    }
+}
+
 pub struct RpcReqActor {
     pub ser_tx_actor : ActorRef,
     pub msg_bus      : Arc<Mutex<Bus<InsteonMsg>>>,
-    pub req          : Mutex<Option<(ActorRef, CmdMsg)>>,
+    pub req          : Mutex<Option<(ActorRef, Request)>>,
     pub event_loop   : Remote,
+    pub registry     : Arc<Mutex<DeviceRegistry>>,
 }
 
 unsafe impl Sync for RpcReqActor { }
 
 impl RpcReqActor {
-    pub fn new(tuple : (ActorRef, Arc<Mutex<Bus<InsteonMsg>>>, Remote)) -> RpcReqActor {
-        let (ser_tx_actor, msg_bus, event_loop) = tuple;
+    pub fn new(tuple : (ActorRef, Arc<Mutex<Bus<InsteonMsg>>>, Remote, Arc<Mutex<DeviceRegistry>>))
+        -> RpcReqActor {
+        let (ser_tx_actor, msg_bus, event_loop, registry) = tuple;
         RpcReqActor {
             ser_tx_actor : ser_tx_actor,
             msg_bus : msg_bus,
             req : Mutex::new(None),
             event_loop: event_loop,
+            registry: registry,
         }
     }
 
     pub fn handle_insteon_msg(&self, message: InsteonMsg, context: ActorCell) {
         info!("RpcReqActor: Received InsteonMsg: {:?}", message);
-        if let Some((ref future,
-                     CmdMsg{ cmd : Some(CmdMsg_oneof_cmd::lightControl(ref lc)), .. })
-        ) = *self.req.lock().unwrap() {
-            let _device_addr = u32_u8(lc.device);
-            match message {
-                InsteonMsg::StandardMsg {addr_from : _device_addr, ..} => {
-                    info!("Received the ACK: {:?}", message);
-                    context.complete(future.clone(), Ack::new());
-                    info!("Killing myself...");
-                    context.kill_me();
+        let mut interior = self.req.lock().unwrap();
+
+        let reply = match *interior {
+            Some((_, ref req)) => match req.reply(&message) {
+                Some(reply) => {
+                    req.learn(&message, &mut self.registry.lock().unwrap());
+                    reply
                 },
-                _ => (),
-            }
+                None => match req.device() {
+                    Some(device) if message.is_direct_nak_from(device) => {
+                        info!("Device refused the request: {:?}", message);
+                        req.failure()
+                    },
+                    _ => return,
+                },
+            },
+            None => return,
+        };
+
+        if let Some((future, req)) = interior.take() {
+            info!("Received the ACK: {:?}", message);
+            reply.complete(&context, future);
+            self.finish(&context, &req);
         }
     }
 
     pub fn handle_rpc_msg(&self, message: RpcReqActorMsg, context: ActorCell) {
         match message {
-            RpcReqActorMsg::Set(ref future, ref light_control) => {
+            RpcReqActorMsg::Set(ref future, ref cmd) => {
                 info!("RpcReqActor received RpcReqActorMsg::Set");
-                self.ser_tx_actor.tell_to(self.ser_tx_actor.clone(),
-                    ActorMsg::Level((u32_u8(light_control.device), light_control.level)));
-                context.complete(future.clone(), Ack::new());
+                let response = self.send_cmd_once(cmd.clone());
+                context.complete(future.clone(), response);
             },
-            RpcReqActorMsg::SetReliable(ref future, ref cmd) => {
-                info!("RpcReqActor received RpcReqActorMsg::SetReliable");
-                self.send_cmd_once(cmd.clone());
+            RpcReqActorMsg::Reliable(ref future, ref req) => {
+                info!("RpcReqActor received RpcReqActorMsg::Reliable");
+                self.send_req_once(req);
 
                 let mut interior = self.req.lock().unwrap();
-                *interior = Some((future.clone(), cmd.clone()));;
+                *interior = Some((future.clone(), req.clone()));
 
-                let actor_ref = context.actor_ref().clone();
-                thread::spawn(move ||{
-                    sleep(Duration::from_secs(1))
-                        .wait().expect("Unable to sleep.");;
-                    actor_ref.tell_to(actor_ref.clone(), RpcReqActorMsg::Timeout(0));
-                });
+                timer::schedule(context.actor_ref(), RpcReqActorMsg::Timeout(0),
+                                Duration::from_secs(1));
             },
             RpcReqActorMsg::Timeout(SEND_RETRIES) => {
                 info!("Reached the maximum number of retries, giving up...");
-                info!("Killing myself...", );
-                context.kill_me();
+                if let Some((future, req)) = self.req.lock().unwrap().take() {
+                    req.failure().complete(&context, future);
+                    self.finish(&context, &req);
+                }
             },
 
             RpcReqActorMsg::Timeout(retries) => {
                 info!("Retrying...");
-                if let Some((_, ref cmd)) = *self.req.lock().unwrap() {
-                    self.send_cmd_once(cmd.clone());
-                    let actor_ref = context.actor_ref().clone();
-                    thread::spawn(move ||{
-                        sleep(Duration::from_secs(ACK_WAIT_INTERVAL_SEC))
-                            .wait().expect("Unable to sleep.");
-                        actor_ref.tell_to(actor_ref.clone(), RpcReqActorMsg::Timeout(retries + 1));
-                    });
+                if let Some((_, ref req)) = *self.req.lock().unwrap() {
+                    self.send_req_once(req);
+                    timer::schedule(context.actor_ref(), RpcReqActorMsg::Timeout(retries + 1),
+                                    Duration::from_secs(ACK_WAIT_INTERVAL_SEC));
                 }
             },
         }
     }
 
+    /// Lets the `RpcActor` send the next request waiting for the device.
+    fn finish(&self, context: &ActorCell, req: &Request) {
+        if let Some(device) = req.device() {
+            context.tell(context.father(), RpcActorMsg::Done(device));
+        }
+        info!("Killing myself...");
+        context.kill_me();
+    }
+
+    fn send_req_once(&self, req: &Request) {
+        match req.msg() {
+            Some(msg) => self.ser_tx_actor.tell_to(self.ser_tx_actor.clone(), ActorMsg::Send(msg)),
+            None => error!("Unknown command"),
+        }
+    }
+
     fn send_cmd_once(&self, req: CmdMsg) -> Ack {
-        match req.cmd {
-            Some(CmdMsg_oneof_cmd::lightControl(light_control)) => {
-                let dst = u32_u8(light_control.device);
-                self.ser_tx_actor.tell_to(self.ser_tx_actor.clone(),
-                                          ActorMsg::Level((dst, light_control.level)));
-            },
+        let mut ack = Ack::new();
 
-            _ => error!("Unknown command"),
+        match cmd_to_msg(&req) {
+            Some(msg) => {
+                self.ser_tx_actor.tell_to(self.ser_tx_actor.clone(), ActorMsg::Send(msg));
+                ack.set_success(true);
+            },
+            None => error!("Unknown command"),
         };
 
-        Ack::new()
+        ack
     }
 }
 
@@ -144,49 +483,146 @@ pub struct RpcActor {
     pub ser_tx_actor : ActorRef,
     pub msg_bus      : Arc<Mutex<Bus<InsteonMsg>>>,
     pub event_loop   : Remote,
+    pub registry     : Arc<Mutex<DeviceRegistry>>,
+    pub wake_queue   : Arc<Mutex<WakeQueue<Request>>>,
+    next_req         : AtomicUsize,
+    /// Reliable requests, ALDB operations and wakes waiting for the one in
+    /// flight to the same device.
+    pending          : Mutex<HashMap<InsteonAddress, VecDeque<(ActorRef, RpcActorMsg)>>>,
 }
 
 impl RpcActor {
-    pub fn new(tuple: (ActorRef, Arc<Mutex<Bus<InsteonMsg>>>, Remote)) -> RpcActor {
-        let (ser_tx_actor, msg_bus, event_loop) = tuple;
+    pub fn new(tuple: (ActorRef, Arc<Mutex<Bus<InsteonMsg>>>, Remote, Arc<Mutex<DeviceRegistry>>,
+                       Arc<Mutex<WakeQueue<Request>>>))
+        -> RpcActor {
+        let (ser_tx_actor, msg_bus, event_loop, registry, wake_queue) = tuple;
         RpcActor {
             ser_tx_actor: ser_tx_actor,
             msg_bus: msg_bus,
             event_loop: event_loop,
+            registry: registry,
+            wake_queue: wake_queue,
+            next_req: AtomicUsize::new(0),
+            pending: Mutex::new(HashMap::new()),
         }
     }
 
     pub fn handle_insteon_msg(&self, message: InsteonMsg, context: ActorCell) {
         info!("RpcActor: Received InsteonMsg: {:?}", message);
+        if message.has_valid_checksum() == Some(false) {
+            warn!("Extended message with an invalid checksum: {:?}", message);
+        }
+        self.registry.lock().unwrap().observe(&message);
         for (_path, actor) in &context.children() {
             context.tell(actor.clone(), message);
         }
+
+        // A battery device only listens right after it transmits. The wake
+        // queues behind whatever is in flight to the device like any other
+        // job, and while it runs, later wakes leave new work to it.
+        if let InsteonMsg::StandardMsg { addr_from, .. } = message {
+            if self.wake_queue.lock().unwrap().wake(&addr_from) {
+                context.tell(context.actor_ref(), RpcActorMsg::Wake(addr_from));
+            }
+        }
+    }
+
+    fn req_name(&self) -> String {
+        format!("req_{}", self.next_req.fetch_add(1, Ordering::Relaxed))
+    }
+
+    fn spawn_req_actor(&self, context: &ActorCell) -> ActorRef {
+        let props = Props::new(Arc::new(RpcReqActor::new),
+                               (self.ser_tx_actor.clone(), self.msg_bus.clone(),
+                                self.event_loop.clone(), self.registry.clone()));
+        context.actor_of(props, self.req_name()).unwrap()
     }
 
     pub fn handle_rpc_msg(&self, message: RpcActorMsg, context: ActorCell) {
 
         //self.event_loop.execute()
         match message {
-            RpcActorMsg::Set(light_control) => {
-                let props = Props::new(Arc::new(RpcReqActor::new),
-                                       (self.ser_tx_actor.clone(), self.msg_bus.clone(),
-                                       self.event_loop.clone()));
-                let req_actor = context.actor_of(props, "req_1".to_owned());
+            RpcActorMsg::Set(cmd) => {
+                let req_actor = self.spawn_req_actor(&context);
                 info!("RpcActor received a message");
-                context.tell(req_actor.unwrap(), RpcReqActorMsg::Set(
-                    context.sender().clone(), light_control.clone()));
-            },
-            RpcActorMsg::SetReliable(light_control) => {
-                let props = Props::new(Arc::new(RpcReqActor::new),
-                                       (self.ser_tx_actor.clone(), self.msg_bus.clone(),
-                                        self.event_loop.clone()));
-                let req_actor = context.actor_of(props, "req_1".to_owned());
+                context.tell(req_actor, RpcReqActorMsg::Set(
+                    context.sender().clone(), cmd.clone()));
+            },
+            RpcActorMsg::Done(device) => {
+                let next = {
+                    let mut pending = self.pending.lock().unwrap();
+                    let next = pending.get_mut(&device).and_then(|queue| queue.pop_front());
+                    if next.is_none() {
+                        pending.remove(&device);
+                    }
+                    next
+                };
+                if let Some((future, job)) = next {
+                    self.start(future, job, &context);
+                }
+            },
+            job => {
                 info!("RpcActor received a message");
-                context.tell(req_actor.unwrap(), RpcReqActorMsg::SetReliable(
-                    context.sender().clone(), light_control.clone()));
+                let future = context.sender().clone();
+                if let Some(device) = job.device() {
+                    let mut pending = self.pending.lock().unwrap();
+                    if let Some(queue) = pending.get_mut(&device) {
+                        debug!("{} is busy, queueing the request", device);
+                        queue.push_back((future, job));
+                        return
+                    }
+                    pending.insert(device, VecDeque::new());
+                }
+                self.start(future, job, &context);
             },
         }
     }
+
+    fn start(&self, future: ActorRef, job: RpcActorMsg, context: &ActorCell) {
+        match job {
+            RpcActorMsg::Reliable(req) => {
+                let req_actor = self.spawn_req_actor(context);
+                context.tell(req_actor, RpcReqActorMsg::Reliable(future, req));
+            },
+            RpcActorMsg::Aldb(device, dialect, op) => {
+                let props = Props::new(Arc::new(AldbActor::new),
+                                       (self.ser_tx_actor.clone(), device, dialect, op));
+                let aldb_actor = context.actor_of(props, self.req_name()).unwrap();
+                context.tell(aldb_actor, AldbActorMsg::Start(future));
+            },
+            RpcActorMsg::ModemLinks => {
+                let props = Props::new(Arc::new(PlmLinksActor::new), self.ser_tx_actor.clone());
+                let links_actor = context.actor_of(props, self.req_name()).unwrap();
+                context.tell(links_actor, PlmLinksActorMsg::Read(future));
+            },
+            RpcActorMsg::StartLinking(code, group, timeout) => {
+                let props = Props::new(Arc::new(LinkingActor::new),
+                                       (self.ser_tx_actor.clone(), code, group, timeout));
+                let linking_actor = context.actor_of(props, self.req_name()).unwrap();
+                context.tell(linking_actor, LinkingActorMsg::Start(future));
+            },
+            RpcActorMsg::Group(group, cmd) => {
+                let props = Props::new(Arc::new(GroupActor::new),
+                                       (self.ser_tx_actor.clone(), group, cmd));
+                let group_actor = context.actor_of(props, self.req_name()).unwrap();
+                context.tell(group_actor, GroupActorMsg::Send(future));
+            },
+            RpcActorMsg::Wake(device) => {
+                let props = Props::new(Arc::new(WakeActor::new),
+                                       (self.ser_tx_actor.clone(), self.registry.clone(),
+                                        self.wake_queue.clone()));
+                let wake_actor = context.actor_of(props, self.req_name()).unwrap();
+                context.tell(wake_actor, WakeActorMsg::Run(device));
+            },
+            RpcActorMsg::CancelLinking => {
+                self.ser_tx_actor.tell_to(self.ser_tx_actor.clone(), ActorMsg::Send(cancel_linking()));
+                let mut ack = Ack::new();
+                ack.set_success(true);
+                context.complete(future, ack);
+            },
+            RpcActorMsg::Set(_) | RpcActorMsg::Done(_) => unreachable!(),
+        }
+    }
 }
 
 impl Actor for RpcActor {
@@ -206,7 +642,166 @@ pub struct VinsteonRpcImpl {
     pub actor_system        : ActorSystem,
     pub rpc_actor           : ActorRef,
     pub ser_tx_actor        : ActorRef,
-    pub msg_bus             : Arc<Mutex<Bus<InsteonMsg>>>
+    pub msg_bus             : Arc<Mutex<Bus<InsteonMsg>>>,
+    pub framing_errors      : Arc<FramingErrors>,
+    pub next_future         : Arc<AtomicUsize>,
+    pub registry            : Arc<Mutex<DeviceRegistry>>,
+    pub wake_queue          : Arc<Mutex<WakeQueue<Request>>>,
+    /// One lock per garage door, held from the state check to the relay
+    /// pulse so that two commands cannot both pass the check.
+    pub garage_locks        : Arc<Mutex<HashMap<InsteonAddress, Arc<Mutex<()>>>>>,
+}
+
+impl VinsteonRpcImpl {
+    fn ask<T: Message>(&self, message: RpcActorMsg) -> T {
+        let name = format!("req_{}", self.next_future.fetch_add(1, Ordering::Relaxed));
+        let future = self.actor_system.ask(self.rpc_actor.clone(), message, name);
+        self.actor_system.extract_result(future)
+    }
+
+    /// Sends `requests` one after the other, stopping at the first that fails.
+    fn ask_all(&self, requests: Vec<Request>) -> Ack {
+        let mut response = Ack::new();
+        for request in requests {
+            response = self.ask(RpcActorMsg::Reliable(request));
+            if !response.success {
+                break
+            }
+        }
+        response
+    }
+
+    /// Holds `requests` until `device` wakes up, under one ticket the caller
+    /// can poll.
+    fn hold(&self, device: InsteonAddress, requests: Vec<Request>) -> Ack {
+        let mut ack = Ack::new();
+        ack.set_success(true);
+        ack.set_pending(true);
+        ack.set_ticket(self.wake_queue.lock().unwrap().push(device, requests));
+        ack
+    }
+
+    /// Sends `request`, or holds it until the device wakes up if it is a
+    /// battery device; the caller then gets a ticket to poll.
+    fn ask_or_hold(&self, request: Request) -> Ack {
+        self.ask_all_or_hold(vec![request])
+    }
+
+    /// `ask_all` for requests to one device. A battery device has them held
+    /// as one batch, whose ticket fails as soon as one of them does.
+    fn ask_all_or_hold(&self, requests: Vec<Request>) -> Ack {
+        let sleepy = match requests.first().and_then(|request| request.device()) {
+            Some(device) if self.registry.lock().unwrap().is_sleepy(&device) => Some(device),
+            _ => None,
+        };
+
+        match sleepy {
+            Some(device) => self.hold(device, requests),
+            None => self.ask_all(requests),
+        }
+    }
+
+    /// The device's engine version, asking the device if it is not cached
+    /// yet. A battery device is not asked, since it would only time out.
+    fn engine(&self, device: InsteonAddress) -> Option<EngineVersion> {
+        let cached = self.registry.lock().unwrap().engine(&device);
+        if cached.is_some() || self.registry.lock().unwrap().is_sleepy(&device) {
+            return cached
+        }
+
+        let _ : EngineInfo = self.ask(RpcActorMsg::Reliable(Request::EngineVersion(device)));
+        self.registry.lock().unwrap().engine(&device)
+    }
+
+    /// The device's identity, asking the device if it is not cached yet. A
+    /// battery device is not asked, since it would only time out.
+    fn identity(&self, device: InsteonAddress) -> Option<Identity> {
+        let cached = self.registry.lock().unwrap().identity(&device);
+        if cached.is_some() || self.registry.lock().unwrap().is_sleepy(&device) {
+            return cached
+        }
+
+        let _ : DeviceIdentity = self.ask(RpcActorMsg::Reliable(Request::Identify(device)));
+        self.registry.lock().unwrap().identity(&device)
+    }
+
+    /// How to phrase extended operations for the device, learning its engine
+    /// version, and for i2cs devices its product, first if need be.
+    fn dialect(&self, device: InsteonAddress) -> Option<Dialect> {
+        let engine = match self.engine(device) {
+            Some(engine) => engine,
+            None => {
+                error!("Unable to learn the engine version of {}", device);
+                return None
+            },
+        };
+
+        let identified = self.registry.lock().unwrap().identity(&device).is_some();
+        if engine == EngineVersion::I2cs && !identified {
+            let _ : DeviceIdentity = self.ask(RpcActorMsg::Reliable(Request::Identify(device)));
+        }
+        self.registry.lock().unwrap().dialect(&device)
+    }
+
+    /// The dialect of a device that takes extended get/set (0x2E), which
+    /// i1 devices do not.
+    fn get_set_dialect(&self, device: InsteonAddress) -> Option<Dialect> {
+        match self.dialect(device) {
+            Some(dialect) if !takes_get_set(device, dialect) => None,
+            dialect => dialect,
+        }
+    }
+
+    /// The dialect of a request that may be held, `Err` if it cannot be
+    /// learned. For a battery device whose engine is not known yet, an engine
+    /// version request is held for its next wake and the dialect is `None`.
+    fn held_dialect(&self, device: InsteonAddress) -> Result<Option<Dialect>, ()> {
+        if !self.registry.lock().unwrap().is_sleepy(&device) {
+            return self.dialect(device).map(Some).ok_or(())
+        }
+
+        let known = self.registry.lock().unwrap().dialect(&device);
+        if known.is_some() {
+            return Ok(known)
+        }
+
+        let mut wake_queue = self.wake_queue.lock().unwrap();
+        let asking = wake_queue.holds(&device, |request| match *request {
+            Request::EngineVersion(_) => true,
+            _ => false,
+        });
+        if !asking {
+            info!("Learning the engine version of {} when it wakes up", device);
+            wake_queue.push(device, vec![Request::EngineVersion(device)]);
+        }
+        Ok(None)
+    }
+
+    /// `held_dialect` for a device that takes extended get/set (0x2E).
+    fn held_get_set_dialect(&self, device: InsteonAddress) -> Result<Option<Dialect>, ()> {
+        match self.held_dialect(device) {
+            Ok(Some(dialect)) if !takes_get_set(device, dialect) => Err(()),
+            dialect => dialect,
+        }
+    }
+
+    fn write_aldb(&self, device: InsteonAddress, record: AldbRecord) -> Ack {
+        // A battery device takes the record in one extended write when it
+        // wakes up, rather than walking its database with the `AldbActor`.
+        if self.registry.lock().unwrap().is_sleepy(&device) {
+            return match self.held_dialect(device) {
+                Ok(dialect) => self.hold(device, vec![Request::WriteAldb(device, dialect, record)]),
+                Err(()) => Ack::new(),
+            }
+        }
+
+        let mut ack = Ack::new();
+        if let Some(dialect) = self.dialect(device) {
+            let success : bool = self.ask(RpcActorMsg::Aldb(device, dialect, AldbOp::Write(record)));
+            ack.set_success(success);
+        }
+        ack
+    }
 }
 
 fn _log_result<T, E : Debug>(result: Result<T, E>) -> Result<(()), (())>{
@@ -218,12 +813,411 @@ fn _log_result<T, E : Debug>(result: Result<T, E>) -> Result<(()), (())>{
     result.map(move |_| (())).map_err(move |_| (()))
 }
 
-fn u32_u8(x:u32) -> [u8; 3] {
-    let _b1 : u8 = ((x >> 24) & 0xff) as u8;
-    let b2 : u8 = ((x >> 16) & 0xff) as u8;
-    let b3 : u8 = ((x >> 8) & 0xff) as u8;
-    let b4 : u8 = (x & 0xff) as u8;
-    [b2, b3, b4]
+/// Whether `device` takes extended get/set (0x2E), logging why not.
+fn takes_get_set(device: InsteonAddress, dialect: Dialect) -> bool {
+    if dialect == Dialect::PeekPoke {
+        error!("{} is an i1 device and does not take extended get/set (0x2E)", device);
+        return false
+    }
+    true
+}
+
+/// The device a `LightControl` names: its `address`, or the packed `device`
+/// number clients written before addresses were strings still send.
+fn light_control_device(light_control: &LightControl) -> Option<InsteonAddress> {
+    if light_control.address.is_empty() {
+        return Some(InsteonAddress::from(light_control.device))
+    }
+
+    parse_device(&light_control.address)
+}
+
+/// The device a message names, or `None` after logging why it names none.
+fn parse_device(device: &str) -> Option<InsteonAddress> {
+    match device.parse() {
+        Ok(address) => Some(address),
+        Err(e) => {
+            error!("{}", e);
+            None
+        },
+    }
+}
+
+/// The device `$device` names, or an early return with `$failure` if it
+/// is not an address.
+macro_rules! device_or {
+    ($device:expr, $failure:expr) => {
+        match parse_device(&$device) {
+            Some(device) => device,
+            None => return grpc::SingleResponse::completed($failure),
+        }
+    }
+}
+
+fn device_command(command: DeviceControl_Command, level: u32, ramp_seconds: f32) -> DeviceCommand {
+    let ramp = RampRate::from_seconds(ramp_seconds);
+    match command {
+        DeviceControl_Command::ON => DeviceCommand::On { level : level },
+        DeviceControl_Command::FAST_ON => DeviceCommand::FastOn,
+        DeviceControl_Command::OFF => DeviceCommand::Off,
+        DeviceControl_Command::FAST_OFF => DeviceCommand::FastOff,
+        DeviceControl_Command::BRIGHT_STEP => DeviceCommand::BrightStep,
+        DeviceControl_Command::DIM_STEP => DeviceCommand::DimStep,
+        DeviceControl_Command::START_BRIGHTEN => DeviceCommand::StartChange(Direction::Up),
+        DeviceControl_Command::START_DIM => DeviceCommand::StartChange(Direction::Down),
+        DeviceControl_Command::STOP_CHANGE => DeviceCommand::StopChange,
+        DeviceControl_Command::ON_AT_RATE =>
+            DeviceCommand::OnAtRate { level : level, ramp : ramp },
+        DeviceControl_Command::OFF_AT_RATE => DeviceCommand::OffAtRate { ramp : ramp },
+    }
+}
+
+fn cmd_to_msg(req: &CmdMsg) -> Option<InsteonMsg> {
+    match req.cmd {
+        Some(CmdMsg_oneof_cmd::lightControl(ref light_control)) =>
+            light_control_device(light_control)
+                .map(|dst| DeviceCommand::On { level : light_control.level }.to_msg(dst)),
+        Some(CmdMsg_oneof_cmd::deviceControl(ref device_control)) => {
+            let cmd = device_command(device_control.command, device_control.level,
+                                     device_control.ramp_seconds);
+            parse_device(&device_control.device).map(|dst| cmd.to_msg(dst))
+        },
+        None => None,
+    }
+}
+
+fn cmd_device(req: &CmdMsg) -> Option<InsteonAddress> {
+    match req.cmd {
+        Some(CmdMsg_oneof_cmd::lightControl(ref light_control)) => light_control_device(light_control),
+        Some(CmdMsg_oneof_cmd::deviceControl(ref device_control)) => parse_device(&device_control.device),
+        None => None,
+    }
+}
+
+fn success_ack() -> Reply {
+    let mut ack = Ack::new();
+    ack.set_success(true);
+    Reply::Ack(ack)
+}
+
+fn link_record(record: &AldbRecord) -> LinkRecord {
+    let mut msg = LinkRecord::new();
+    msg.set_mem_addr(record.mem_addr as u32);
+    msg.set_in_use(record.in_use);
+    msg.set_controller(record.controller);
+    msg.set_group(record.group as u32);
+    msg.set_device(record.address.to_string());
+    msg.set_data1(record.data[0] as u32);
+    msg.set_data2(record.data[1] as u32);
+    msg.set_data3(record.data[2] as u32);
+    msg
+}
+
+/// Modem records have no memory address, so `mem_addr` is left at zero.
+fn modem_link_record(link: &PlmLink) -> LinkRecord {
+    let mut msg = LinkRecord::new();
+    msg.set_in_use(true);
+    msg.set_controller(link.controller);
+    msg.set_group(link.group as u32);
+    msg.set_device(link.address.to_string());
+    msg.set_data1(link.data[0] as u32);
+    msg.set_data2(link.data[1] as u32);
+    msg.set_data3(link.data[2] as u32);
+    msg
+}
+
+fn aldb_record(msg: &LinkRecord) -> Option<AldbRecord> {
+    parse_device(&msg.device).map(|address| AldbRecord {
+        mem_addr: msg.mem_addr as u16,
+        in_use: msg.in_use,
+        controller: msg.controller,
+        used: true,
+        group: msg.group as u8,
+        address: address,
+        data: [msg.data1 as u8, msg.data2 as u8, msg.data3 as u8],
+    })
+}
+
+fn device_identity(info: &DeviceInfo) -> DeviceIdentity {
+    let mut response = DeviceIdentity::new();
+    response.set_device(info.address.to_string());
+
+    if let Some(identity) = info.identity {
+        response.set_success(true);
+        response.set_category(identity.category as u32);
+        response.set_subcategory(identity.subcategory as u32);
+        response.set_firmware(identity.firmware as u32);
+        response.set_category_name(identity.category_name().unwrap_or("").to_owned());
+        response.set_model(identity.model().unwrap_or("").to_owned());
+        response.set_description(identity.description().unwrap_or("").to_owned());
+    }
+
+    response
+}
+
+fn thermostat_status(state: &ThermostatState) -> ThermostatStatus {
+    let mut status = ThermostatStatus::new();
+    status.set_success(true);
+    status.set_mode(match state.mode {
+        Some(ThermostatMode::Off) => ThermostatStatus_Mode::OFF,
+        Some(ThermostatMode::Heat) => ThermostatStatus_Mode::HEAT,
+        Some(ThermostatMode::Cool) => ThermostatStatus_Mode::COOL,
+        Some(ThermostatMode::Auto) => ThermostatStatus_Mode::AUTO,
+        Some(ThermostatMode::Program) => ThermostatStatus_Mode::PROGRAM,
+        None => ThermostatStatus_Mode::UNKNOWN,
+    });
+    status.set_fan(match state.fan {
+        Some(FanMode::Auto) => ThermostatStatus_Fan::FAN_AUTO,
+        Some(FanMode::On) => ThermostatStatus_Fan::FAN_ON,
+        None => ThermostatStatus_Fan::FAN_UNKNOWN,
+    });
+    status.set_temperature(state.temperature.unwrap_or(0.0));
+    status.set_humidity(state.humidity.unwrap_or(0) as u32);
+    status.set_cool_setpoint(state.cool_setpoint.unwrap_or(0) as u32);
+    status.set_heat_setpoint(state.heat_setpoint.unwrap_or(0) as u32);
+    status.set_cooling(state.cooling);
+    status.set_heating(state.heating);
+    status.set_celsius(state.celsius);
+    status
+}
+
+fn thermostat_command(req: &ThermostatCmdReq) -> ThermostatCommand {
+    match req.command {
+        ThermostatCmdReq_Command::MODE_OFF => ThermostatCommand::Mode(ThermostatMode::Off),
+        ThermostatCmdReq_Command::MODE_HEAT => ThermostatCommand::Mode(ThermostatMode::Heat),
+        ThermostatCmdReq_Command::MODE_COOL => ThermostatCommand::Mode(ThermostatMode::Cool),
+        ThermostatCmdReq_Command::MODE_AUTO => ThermostatCommand::Mode(ThermostatMode::Auto),
+        ThermostatCmdReq_Command::MODE_PROGRAM => ThermostatCommand::Mode(ThermostatMode::Program),
+        ThermostatCmdReq_Command::FAN_AUTO => ThermostatCommand::Fan(FanMode::Auto),
+        ThermostatCmdReq_Command::FAN_ON => ThermostatCommand::Fan(FanMode::On),
+        ThermostatCmdReq_Command::COOL_SETPOINT => ThermostatCommand::CoolSetpoint(req.degrees as u8),
+        ThermostatCmdReq_Command::HEAT_SETPOINT => ThermostatCommand::HeatSetpoint(req.degrees as u8),
+    }
+}
+
+fn zone_info(reading: ZoneReq_Reading) -> ZoneInfo {
+    match reading {
+        ZoneReq_Reading::TEMPERATURE => ZoneInfo::Temperature,
+        ZoneReq_Reading::SETPOINT => ZoneInfo::Setpoint,
+        ZoneReq_Reading::DEADBAND => ZoneInfo::Deadband,
+        ZoneReq_Reading::HUMIDITY => ZoneInfo::Humidity,
+    }
+}
+
+fn zone_reading(info: ZoneInfo, raw: u8) -> ZoneReading {
+    let mut reading = ZoneReading::new();
+    reading.set_success(true);
+    reading.set_raw(raw as u32);
+    reading.set_value(info.value(raw));
+    reading
+}
+
+fn io_command(req: &IoCmdReq) -> IoCommand {
+    let value = req.value as u8;
+    match req.command {
+        IoCmdReq_Command::RELAY_ON => IoCommand::Relay(true),
+        IoCmdReq_Command::RELAY_OFF => IoCommand::Relay(false),
+        IoCmdReq_Command::OUTPUT_ON => IoCommand::OutputOn(value),
+        IoCmdReq_Command::OUTPUT_OFF => IoCommand::OutputOff(value),
+        IoCmdReq_Command::WRITE_OUTPUTS => IoCommand::WriteOutputs(value),
+        IoCmdReq_Command::MODULE_CONTROL => IoCommand::ModuleControl(value),
+    }
+}
+
+fn relay_mode(mode: RelayModeReq_Mode) -> RelayMode {
+    match mode {
+        RelayModeReq_Mode::LATCHING => RelayMode::Latching,
+        RelayModeReq_Mode::MOMENTARY_A => RelayMode::MomentaryA,
+        RelayModeReq_Mode::MOMENTARY_B => RelayMode::MomentaryB,
+        RelayModeReq_Mode::MOMENTARY_C => RelayMode::MomentaryC,
+    }
+}
+
+fn io_reading(value: u8) -> IoReading {
+    let mut reading = IoReading::new();
+    reading.set_success(true);
+    reading.set_value(value as u32);
+    reading
+}
+
+fn button_mode(mode: ButtonModeReq_Mode) -> ButtonMode {
+    match mode {
+        ButtonModeReq_Mode::TOGGLE => ButtonMode::Toggle,
+        ButtonModeReq_Mode::ALWAYS_ON => ButtonMode::AlwaysOn,
+        ButtonModeReq_Mode::ALWAYS_OFF => ButtonMode::AlwaysOff,
+    }
+}
+
+fn event_type(event: SensorEvent) -> Event_EventType {
+    match event {
+        SensorEvent::Motion => Event_EventType::MOTION,
+        SensorEvent::Clear => Event_EventType::CLEAR,
+        SensorEvent::Open => Event_EventType::OPEN,
+        SensorEvent::Closed => Event_EventType::CLOSED,
+        SensorEvent::Wet => Event_EventType::WET,
+        SensorEvent::Dry => Event_EventType::DRY,
+        SensorEvent::Dark => Event_EventType::DARK,
+        SensorEvent::Light => Event_EventType::LIGHT,
+        SensorEvent::LowBattery => Event_EventType::LOW_BATTERY,
+        SensorEvent::Heartbeat => Event_EventType::HEARTBEAT,
+    }
+}
+
+fn sensor_event(device: InsteonAddress, event: SensorEvent) -> Event {
+    let mut msg = Event::new();
+    msg.set_device(device.to_string());
+    msg.set_field_type(event_type(event));
+    msg.set_timestamp(unix_time());
+    msg
+}
+
+fn sensor_status(device: InsteonAddress, sensor: &SensorState) -> SensorStatus {
+    let mut status = SensorStatus::new();
+    status.set_device(device.to_string());
+    status.set_kind(match sensor.kind {
+        SensorKind::Motion => SensorStatus_Kind::MOTION_SENSOR,
+        SensorKind::OpenClose => SensorStatus_Kind::OPEN_CLOSE_SENSOR,
+        SensorKind::Leak => SensorStatus_Kind::LEAK_SENSOR,
+    });
+    if let Some(event) = sensor.last_event {
+        status.set_has_event(true);
+        status.set_last_event(event_type(event));
+    }
+    status.set_last_seen(sensor.last_seen);
+    status.set_low_battery(sensor.low_battery);
+    status
+}
+
+fn op_flag(flag: OpFlagState_Flag) -> OpFlag {
+    match flag {
+        OpFlagState_Flag::PROGRAM_LOCK => OpFlag::ProgramLock,
+        OpFlagState_Flag::LED_ON_TX => OpFlag::LedOnTx,
+        OpFlagState_Flag::RESUME_DIM => OpFlag::ResumeDim,
+        OpFlagState_Flag::EIGHT_KEY => OpFlag::EightKey,
+        OpFlagState_Flag::LED_OFF => OpFlag::LedOff,
+        OpFlagState_Flag::BEEPER => OpFlag::Beeper,
+        OpFlagState_Flag::RF_DISABLE => OpFlag::RfDisable,
+        OpFlagState_Flag::POWERLINE_DISABLE => OpFlag::PowerlineDisable,
+        OpFlagState_Flag::RELAY_FOLLOWS_INPUT => OpFlag::RelayFollowsInput,
+        OpFlagState_Flag::MOMENTARY => OpFlag::Momentary,
+    }
+}
+
+fn op_flag_state(flag: OpFlag, on: bool) -> OpFlagState {
+    let mut state = OpFlagState::new();
+    state.set_flag(match flag {
+        OpFlag::ProgramLock => OpFlagState_Flag::PROGRAM_LOCK,
+        OpFlag::LedOnTx => OpFlagState_Flag::LED_ON_TX,
+        OpFlag::ResumeDim => OpFlagState_Flag::RESUME_DIM,
+        OpFlag::EightKey => OpFlagState_Flag::EIGHT_KEY,
+        OpFlag::LedOff => OpFlagState_Flag::LED_OFF,
+        OpFlag::Beeper => OpFlagState_Flag::BEEPER,
+        OpFlag::RfDisable => OpFlagState_Flag::RF_DISABLE,
+        OpFlag::PowerlineDisable => OpFlagState_Flag::POWERLINE_DISABLE,
+        OpFlag::RelayFollowsInput => OpFlagState_Flag::RELAY_FOLLOWS_INPUT,
+        OpFlag::Momentary => OpFlagState_Flag::MOMENTARY,
+    });
+    state.set_on(on);
+    state
+}
+
+fn fan_speed(speed: FanStatus_Speed) -> FanSpeed {
+    match speed {
+        FanStatus_Speed::OFF => FanSpeed::Off,
+        FanStatus_Speed::LOW => FanSpeed::Low,
+        FanStatus_Speed::MEDIUM => FanSpeed::Medium,
+        FanStatus_Speed::HIGH => FanSpeed::High,
+    }
+}
+
+fn fan_status(level: u8) -> FanStatus {
+    let mut status = FanStatus::new();
+    status.set_success(true);
+    status.set_speed(match FanSpeed::from_level(level) {
+        FanSpeed::Off => FanStatus_Speed::OFF,
+        FanSpeed::Low => FanStatus_Speed::LOW,
+        FanSpeed::Medium => FanStatus_Speed::MEDIUM,
+        FanSpeed::High => FanStatus_Speed::HIGH,
+    });
+    status.set_raw_level(level as u32);
+    status
+}
+
+fn garage_status(door: &GarageDoor) -> GarageStatus {
+    let mut status = GarageStatus::new();
+    status.set_success(true);
+    status.set_state(match door.state {
+        DoorState::Unknown => GarageStatus_State::UNKNOWN,
+        DoorState::Closed => GarageStatus_State::CLOSED,
+        DoorState::Open => GarageStatus_State::OPEN,
+        DoorState::Opening => GarageStatus_State::OPENING,
+        DoorState::Closing => GarageStatus_State::CLOSING,
+    });
+    status.set_fault(door.fault);
+    status
+}
+
+fn garage_error(error: &str) -> GarageStatus {
+    let mut status = GarageStatus::new();
+    status.set_error(error.to_owned());
+    status
+}
+
+fn addresses(devices: &[InsteonAddress]) -> RepeatedField<String> {
+    RepeatedField::from_vec(devices.iter().map(|device| device.to_string()).collect())
+}
+
+fn group_result(cleanup: &GroupCleanup) -> GroupResult {
+    let mut result = GroupResult::new();
+    result.set_success(cleanup.success());
+    result.set_cleanup_finished(cleanup.finished);
+    result.set_acknowledged(addresses(&cleanup.acknowledged));
+    result.set_failed(addresses(&cleanup.failed));
+    result.set_recovered(addresses(&cleanup.recovered));
+    result
+}
+
+fn link_code(mode: LinkingReq_Mode) -> LinkCode {
+    match mode {
+        LinkingReq_Mode::EITHER => LinkCode::Either,
+        LinkingReq_Mode::CONTROLLER => LinkCode::Controller,
+        LinkingReq_Mode::RESPONDER => LinkCode::Responder,
+        LinkingReq_Mode::DELETE => LinkCode::Delete,
+    }
+}
+
+fn linking_report(result: &LinkingResult) -> LinkingReport {
+    let mut report = LinkingReport::new();
+    match *result {
+        LinkingResult::Completed(ref linked) => {
+            report.set_success(true);
+            report.set_mode(match linked.link_code {
+                Some(LinkCode::Controller) => LinkingReq_Mode::CONTROLLER,
+                Some(LinkCode::Responder) => LinkingReq_Mode::RESPONDER,
+                Some(LinkCode::Delete) => LinkingReq_Mode::DELETE,
+                Some(LinkCode::Either) | None => LinkingReq_Mode::EITHER,
+            });
+            report.set_group(linked.group as u32);
+            let mut info = DeviceInfo::new(linked.address);
+            info.identity = Some(linked.identity);
+            report.set_identity(device_identity(&info));
+        },
+        LinkingResult::TimedOut => report.set_timed_out(true),
+        LinkingResult::Cancelled => report.set_cancelled(true),
+        LinkingResult::Refused => report.set_refused(true),
+    }
+    report
+}
+
+fn engine_info(engine: EngineVersion) -> EngineInfo {
+    let mut response = EngineInfo::new();
+    response.set_success(true);
+    response.set_engine(match engine {
+        EngineVersion::I1 => EngineInfo_Engine::I1,
+        EngineVersion::I2 => EngineInfo_Engine::I2,
+        EngineVersion::I2cs => EngineInfo_Engine::I2CS,
+    });
+    response
 }
 
 pub const ACK_WAIT_INTERVAL_SEC : u64 = 1;
@@ -232,28 +1226,518 @@ pub const SEND_RETRIES : usize = 8;
 impl VinsteonRPC for VinsteonRpcImpl {
 
     fn send_cmd(&self, _m: grpc::RequestOptions, req: CmdMsg) -> grpc::SingleResponse<Ack> {
+        let response = self.ask(RpcActorMsg::Set(req));
+        grpc::SingleResponse::completed(response)
+    }
 
-        let mut response = Ack::new();
+    fn send_cmd_reliable(&self, _m: grpc::RequestOptions, req: CmdMsg) -> grpc::SingleResponse<Ack> {
+        let response = self.ask_or_hold(Request::Cmd(req));
+        grpc::SingleResponse::completed(response)
+    }
+
+    fn get_status(&self, _m: grpc::RequestOptions, req: StatusReq)
+        -> grpc::SingleResponse<DeviceStatus> {
+
+        let device = device_or!(req.device, DeviceStatus::new());
+        let response = self.ask(RpcActorMsg::Reliable(Request::Status(device)));
+        grpc::SingleResponse::completed(response)
+    }
+
+    fn identify(&self, _m: grpc::RequestOptions, req: IdentifyReq)
+        -> grpc::SingleResponse<DeviceIdentity> {
+
+        let device = device_or!(req.device, DeviceIdentity::new());
+        let response = self.ask(RpcActorMsg::Reliable(Request::Identify(device)));
+        grpc::SingleResponse::completed(response)
+    }
+
+    fn get_engine_version(&self, _m: grpc::RequestOptions, req: EngineReq)
+        -> grpc::SingleResponse<EngineInfo> {
+
+        let device = device_or!(req.device, EngineInfo::new());
+        let cached = self.registry.lock().unwrap().engine(&device);
+        let response = match cached {
+            Some(engine) if !req.refresh => engine_info(engine),
+            _ => self.ask(RpcActorMsg::Reliable(Request::EngineVersion(device))),
+        };
+        grpc::SingleResponse::completed(response)
+    }
+
+    fn read_aldb(&self, _m: grpc::RequestOptions, req: AldbReq)
+        -> grpc::SingleResponse<LinkTable> {
+
+        let device = device_or!(req.device, LinkTable::new());
+        let mut response = LinkTable::new();
+
+        if let Some(dialect) = self.dialect(device) {
+            let dump : AldbDump = self.ask(RpcActorMsg::Aldb(device, dialect, AldbOp::Read));
+            response.set_success(dump.success);
+            response.set_records(RepeatedField::from_vec(dump.records.iter().map(link_record).collect()));
+        }
+
+        grpc::SingleResponse::completed(response)
+    }
+
+    fn write_aldb_record(&self, _m: grpc::RequestOptions, req: WriteLinkReq)
+        -> grpc::SingleResponse<Ack> {
+
+        let device = device_or!(req.device, Ack::new());
+        let record = match aldb_record(req.get_record()) {
+            Some(record) => record,
+            None => return grpc::SingleResponse::completed(Ack::new()),
+        };
+
+        grpc::SingleResponse::completed(self.write_aldb(device, record))
+    }
 
-        match req.cmd {
-            Some(CmdMsg_oneof_cmd::lightControl(light_control)) => {
-                let future = self.actor_system.ask(
-                    self.rpc_actor.clone(),
-                    RpcActorMsg::Set(light_control.clone()), "req_1".to_owned());
-                response = self.actor_system.extract_result(future);
+    fn delete_aldb_record(&self, _m: grpc::RequestOptions, req: DeleteLinkReq)
+        -> grpc::SingleResponse<Ack> {
+
+        let device = device_or!(req.device, Ack::new());
+        let record = AldbRecord::deleted(req.mem_addr as u16);
+        grpc::SingleResponse::completed(self.write_aldb(device, record))
+    }
+
+    fn get_modem_links(&self, _m: grpc::RequestOptions, _req: ModemLinksReq)
+        -> grpc::SingleResponse<LinkTable> {
+
+        let links : PlmLinks = self.ask(RpcActorMsg::ModemLinks);
+        self.registry.lock().unwrap().learn_links(&links.links);
+        let mut response = LinkTable::new();
+        response.set_success(links.success);
+        response.set_records(RepeatedField::from_vec(links.links.iter().map(modem_link_record).collect()));
+        grpc::SingleResponse::completed(response)
+    }
+
+    fn get_thermostat_status(&self, _m: grpc::RequestOptions, req: ThermostatReq)
+        -> grpc::SingleResponse<ThermostatStatus> {
+
+        let device = device_or!(req.device, ThermostatStatus::new());
+        let cached = self.registry.lock().unwrap().thermostat(&device);
+        let response = match cached {
+            Some(ref state) if !req.refresh => thermostat_status(state),
+            _ => match self.get_set_dialect(device) {
+                Some(dialect) => self.ask(RpcActorMsg::Reliable(Request::ThermostatStatus(device, Some(dialect)))),
+                None => ThermostatStatus::new(),
+            },
+        };
+        grpc::SingleResponse::completed(response)
+    }
+
+    fn send_thermostat_cmd(&self, _m: grpc::RequestOptions, req: ThermostatCmdReq)
+        -> grpc::SingleResponse<Ack> {
+
+        let device = device_or!(req.device, Ack::new());
+        let response = match self.held_dialect(device) {
+            Ok(dialect) => self.ask_or_hold(Request::Thermostat(device, dialect, thermostat_command(&req))),
+            Err(()) => Ack::new(),
+        };
+        grpc::SingleResponse::completed(response)
+    }
+
+    fn get_zone_info(&self, _m: grpc::RequestOptions, req: ZoneReq)
+        -> grpc::SingleResponse<ZoneReading> {
+
+        let device = device_or!(req.device, ZoneReading::new());
+        let cmd = ThermostatCommand::ZoneInfo(zone_info(req.reading));
+        let response = match self.dialect(device) {
+            Some(dialect) => self.ask(RpcActorMsg::Reliable(Request::Thermostat(device, Some(dialect), cmd))),
+            None => ZoneReading::new(),
+        };
+        grpc::SingleResponse::completed(response)
+    }
+
+    fn send_io_cmd(&self, _m: grpc::RequestOptions, req: IoCmdReq)
+        -> grpc::SingleResponse<Ack> {
+
+        let device = device_or!(req.device, Ack::new());
+        let response = match self.held_dialect(device) {
+            Ok(dialect) => self.ask_or_hold(Request::Io(device, dialect, io_command(&req))),
+            Err(()) => Ack::new(),
+        };
+        grpc::SingleResponse::completed(response)
+    }
+
+    fn read_io_inputs(&self, _m: grpc::RequestOptions, req: IoReadReq)
+        -> grpc::SingleResponse<IoReading> {
+
+        let device = device_or!(req.device, IoReading::new());
+        let response = match self.dialect(device) {
+            Some(dialect) => self.ask(RpcActorMsg::Reliable(Request::Io(device, Some(dialect), IoCommand::ReadInputs))),
+            None => IoReading::new(),
+        };
+        grpc::SingleResponse::completed(response)
+    }
+
+    fn read_io_sensor(&self, _m: grpc::RequestOptions, req: IoReadReq)
+        -> grpc::SingleResponse<IoReading> {
+
+        let device = device_or!(req.device, IoReading::new());
+        let cmd = IoCommand::SensorValue(req.sensor as u8);
+        let response = match self.dialect(device) {
+            Some(dialect) => self.ask(RpcActorMsg::Reliable(Request::Io(device, Some(dialect), cmd))),
+            None => IoReading::new(),
+        };
+        grpc::SingleResponse::completed(response)
+    }
+
+    fn set_relay_mode(&self, _m: grpc::RequestOptions, req: RelayModeReq)
+        -> grpc::SingleResponse<Ack> {
+
+        let device = device_or!(req.device, Ack::new());
+        // Only the momentary time is set with extended get/set.
+        let dialect = if req.momentary_tenths != 0 {
+            self.held_get_set_dialect(device)
+        } else {
+            self.held_dialect(device)
+        };
+        let dialect = match dialect {
+            Ok(dialect) => dialect,
+            Err(()) => return grpc::SingleResponse::completed(Ack::new()),
+        };
+
+        let mut cmds : Vec<IoCommand> = relay_mode(req.mode).op_flags().iter()
+            .map(|&flag| IoCommand::OpFlag(flag)).collect();
+        if req.momentary_tenths != 0 {
+            cmds.push(IoCommand::MomentaryTime(req.momentary_tenths as u8));
+        }
+
+        let response = self.ask_all_or_hold(cmds.into_iter().map(|cmd| Request::Io(device, dialect, cmd)).collect());
+        grpc::SingleResponse::completed(response)
+    }
+
+    fn get_keypad_leds(&self, _m: grpc::RequestOptions, req: KeypadReq)
+        -> grpc::SingleResponse<KeypadLeds> {
+
+        let device = device_or!(req.device, KeypadLeds::new());
+        let response = self.ask(RpcActorMsg::Reliable(Request::KeypadLeds(device)));
+        grpc::SingleResponse::completed(response)
+    }
+
+    fn set_keypad_leds(&self, _m: grpc::RequestOptions, req: KeypadLedsReq)
+        -> grpc::SingleResponse<Ack> {
+
+        let device = device_or!(req.device, Ack::new());
+        let mut cmds = vec![KeypadCommand::LedMask(req.leds as u8)];
+        if req.brightness != 0 {
+            cmds.push(KeypadCommand::LedBrightness(req.brightness as u8));
+        }
+
+        let response = match self.held_get_set_dialect(device) {
+            Ok(dialect) => self.ask_all_or_hold(cmds.into_iter().map(|cmd| Request::Keypad(device, dialect, cmd)).collect()),
+            Err(()) => Ack::new(),
+        };
+        grpc::SingleResponse::completed(response)
+    }
+
+    fn set_button_modes(&self, _m: grpc::RequestOptions, req: ButtonModesReq)
+        -> grpc::SingleResponse<Ack> {
+
+        let device = device_or!(req.device, Ack::new());
+        let modes : Vec<(u8, ButtonMode)> = req.get_buttons().iter()
+            .map(|button| (button.button as u8, button_mode(button.mode))).collect();
+
+        let response = match self.held_get_set_dialect(device) {
+            Ok(dialect) => self.ask_all_or_hold(button_mode_commands(&modes).iter()
+                .map(|&cmd| Request::Keypad(device, dialect, cmd)).collect()),
+            Err(()) => Ack::new(),
+        };
+        grpc::SingleResponse::completed(response)
+    }
+
+    fn set_radio_group(&self, _m: grpc::RequestOptions, req: RadioGroupReq)
+        -> grpc::SingleResponse<Ack> {
+
+        let device = device_or!(req.device, Ack::new());
+        let buttons : Vec<u8> = req.get_buttons().iter().map(|&button| button as u8).collect();
+
+        let response = match self.held_get_set_dialect(device) {
+            Ok(dialect) => self.ask_all_or_hold(radio_group_commands(&buttons).into_iter()
+                .map(|cmd| Request::Keypad(device, dialect, cmd)).collect()),
+            Err(()) => Ack::new(),
+        };
+        grpc::SingleResponse::completed(response)
+    }
+
+    fn set_fan_speed(&self, _m: grpc::RequestOptions, req: FanSpeedReq)
+        -> grpc::SingleResponse<Ack> {
+
+        let device = device_or!(req.device, Ack::new());
+        let speed = fan_speed(req.speed);
+        let response = match self.held_dialect(device) {
+            Ok(dialect) => self.ask_or_hold(Request::FanSpeed(device, dialect, speed)),
+            Err(()) => Ack::new(),
+        };
+        grpc::SingleResponse::completed(response)
+    }
+
+    fn get_fan_status(&self, _m: grpc::RequestOptions, req: FanReq)
+        -> grpc::SingleResponse<FanStatus> {
+
+        let device = device_or!(req.device, FanStatus::new());
+        let response = self.ask(RpcActorMsg::Reliable(Request::FanStatus(device)));
+        grpc::SingleResponse::completed(response)
+    }
+
+    fn get_op_flags(&self, _m: grpc::RequestOptions, req: OpFlagsReq)
+        -> grpc::SingleResponse<OpFlagsReport> {
+
+        let device = device_or!(req.device, OpFlagsReport::new());
+        let dialect = match self.held_dialect(device) {
+            Ok(dialect) => dialect,
+            Err(()) => return grpc::SingleResponse::completed(OpFlagsReport::new()),
+        };
+
+        let layout = FlagLayout::from_identity(self.identity(device));
+        let request = Request::OpFlags(device, dialect, OpFlagsCommand::Get);
+        let mut response : OpFlagsReport = if self.registry.lock().unwrap().is_sleepy(&device) {
+            // A battery device is read when it wakes up; until then it gets
+            // the flags it last reported, if any.
+            let held = self.hold(device, vec![request]);
+            let mut report = OpFlagsReport::new();
+            if let Some(flags) = self.registry.lock().unwrap().op_flags(&device) {
+                report.set_success(true);
+                report.set_raw(flags as u32);
             }
-            _ => error!("Unknown command"),
+            report.set_pending(held.pending);
+            report.set_ticket(held.ticket);
+            report
+        } else {
+            self.ask(RpcActorMsg::Reliable(request))
+        };
+        if response.success {
+            let flags = layout.decode(response.raw as u8).into_iter()
+                .map(|(flag, on)| op_flag_state(flag, on)).collect();
+            response.set_flags(RepeatedField::from_vec(flags));
         }
+        grpc::SingleResponse::completed(response)
+    }
 
+    fn set_op_flag(&self, _m: grpc::RequestOptions, req: SetOpFlagReq)
+        -> grpc::SingleResponse<Ack> {
+
+        let device = device_or!(req.device, Ack::new());
+        let flag = op_flag(req.flag);
+        let code = match FlagLayout::from_identity(self.identity(device)).set_code(flag, req.on) {
+            Some(code) => code,
+            None => {
+                error!("{} has no {:?} flag", device, flag);
+                return grpc::SingleResponse::completed(Ack::new())
+            },
+        };
+
+        let response = match self.held_dialect(device) {
+            Ok(dialect) => self.ask_or_hold(Request::OpFlags(device, dialect, OpFlagsCommand::Set(code))),
+            Err(()) => Ack::new(),
+        };
         grpc::SingleResponse::completed(response)
     }
 
-    fn send_cmd_reliable(&self, _m: grpc::RequestOptions, req: CmdMsg) -> grpc::SingleResponse<Ack> {
-        let future = self.actor_system.ask(
-            self.rpc_actor.clone(),
-            RpcActorMsg::SetReliable(req.clone()), "req_1".to_owned());
-        let response = self.actor_system.extract_result(future);
+    fn configure_garage_door(&self, _m: grpc::RequestOptions, req: GarageConfigReq)
+        -> grpc::SingleResponse<GarageStatus> {
+
+        let device = device_or!(req.device, GarageStatus::new());
+        let door = GarageDoor::new(req.sensor_on_when_closed, req.travel_seconds);
+        self.registry.lock().unwrap().set_garage(device, door);
+
+        // Learn where the door is from the sensor.
+        if let Some(dialect) = self.dialect(device) {
+            let _ : IoReading = self.ask(RpcActorMsg::Reliable(Request::Io(device, Some(dialect), IoCommand::ReadInputs)));
+        }
 
+        let response = match self.registry.lock().unwrap().garage(&device) {
+            Some(ref door) => garage_status(door),
+            None => garage_error("not a garage door"),
+        };
         grpc::SingleResponse::completed(response)
     }
-}
\ No newline at end of file
+
+    fn get_garage_door(&self, _m: grpc::RequestOptions, req: GarageReq)
+        -> grpc::SingleResponse<GarageStatus> {
+
+        let device = device_or!(req.device, GarageStatus::new());
+        let response = match self.registry.lock().unwrap().garage(&device) {
+            Some(ref door) => garage_status(door),
+            None => garage_error("not a garage door"),
+        };
+        grpc::SingleResponse::completed(response)
+    }
+
+    fn operate_garage_door(&self, _m: grpc::RequestOptions, req: GarageCmdReq)
+        -> grpc::SingleResponse<GarageStatus> {
+
+        let device = device_or!(req.device, GarageStatus::new());
+        let open = req.command == GarageCmdReq_Command::OPEN;
+
+        let door_lock = self.garage_locks.lock().unwrap()
+            .entry(device).or_insert_with(|| Arc::new(Mutex::new(()))).clone();
+        let _operating = door_lock.lock().unwrap();
+
+        let checked = match self.registry.lock().unwrap().garage(&device) {
+            Some(door) => door.check(open).map_err(|e| e.to_string()),
+            None => Err("not a garage door".to_owned()),
+        };
+        if let Err(error) = checked {
+            return grpc::SingleResponse::completed(garage_error(&error))
+        }
+
+        let ack : Ack = match self.dialect(device) {
+            Some(dialect) => self.ask(RpcActorMsg::Reliable(Request::Io(device, Some(dialect), IoCommand::Relay(true)))),
+            None => Ack::new(),
+        };
+        if !ack.success {
+            return grpc::SingleResponse::completed(garage_error("the IOLinc did not answer"))
+        }
+
+        let (door, moves) = {
+            let mut registry = self.registry.lock().unwrap();
+            let door = registry.garage_mut(&device).unwrap();
+            let moves = door.start(open);
+            (*door, moves)
+        };
+
+        let registry = self.registry.clone();
+        timer::after(Duration::from_secs(door.travel_sec as u64), move || {
+            if let Some(door) = registry.lock().unwrap().garage_mut(&device) {
+                door.travel_timeout(moves);
+            }
+        });
+
+        grpc::SingleResponse::completed(garage_status(&door))
+    }
+
+    fn send_group_cmd(&self, _m: grpc::RequestOptions, req: GroupCmdReq)
+        -> grpc::SingleResponse<GroupResult> {
+
+        let cmd = device_command(req.command, req.level, req.ramp_seconds);
+        let cleanup : GroupCleanup = self.ask(RpcActorMsg::Group(req.group as u8, cmd));
+        grpc::SingleResponse::completed(group_result(&cleanup))
+    }
+
+    fn start_linking(&self, _m: grpc::RequestOptions, req: LinkingReq)
+        -> grpc::SingleResponse<LinkingReport> {
+
+        let timeout = match req.timeout_seconds {
+            0 => LINKING_TIMEOUT_SEC,
+            seconds => seconds as u64,
+        };
+        let result : LinkingResult = self.ask(RpcActorMsg::StartLinking(
+            link_code(req.mode), req.group as u8, Duration::from_secs(timeout)));
+        grpc::SingleResponse::completed(linking_report(&result))
+    }
+
+    fn cancel_linking(&self, _m: grpc::RequestOptions, _req: CancelLinkingReq)
+        -> grpc::SingleResponse<Ack> {
+
+        let response = self.ask(RpcActorMsg::CancelLinking);
+        grpc::SingleResponse::completed(response)
+    }
+
+    fn list_sensors(&self, _m: grpc::RequestOptions, _req: ListSensorsReq)
+        -> grpc::SingleResponse<SensorList> {
+
+        let sensors = self.registry.lock().unwrap().sensors();
+        let mut response = SensorList::new();
+        response.set_sensors(RepeatedField::from_vec(
+            sensors.iter().map(|&(device, ref sensor)| sensor_status(device, sensor)).collect()));
+        grpc::SingleResponse::completed(response)
+    }
+
+    fn events(&self, _m: grpc::RequestOptions, _req: EventsReq)
+        -> grpc::StreamingResponse<Event> {
+
+        let mut rx = self.msg_bus.lock().unwrap().add_rx();
+        let registry = self.registry.clone();
+        let (tx, events) = mpsc::unbounded();
+
+        thread::spawn(move ||{
+            for msg in rx.iter() {
+                let event = registry.lock().unwrap().sensor_event(&msg);
+                if let Some((device, event)) = event {
+                    if tx.unbounded_send(sensor_event(device, event)).is_err() {
+                        debug!("Event subscriber went away");
+                        break
+                    }
+                }
+            }
+        });
+
+        grpc::StreamingResponse::no_metadata(
+            events.map_err(|()| grpc::Error::Other("event stream closed")))
+    }
+
+    fn get_ticket(&self, _m: grpc::RequestOptions, req: TicketReq)
+        -> grpc::SingleResponse<TicketStatus> {
+
+        let mut response = TicketStatus::new();
+        response.set_state(match self.wake_queue.lock().unwrap().state(req.ticket) {
+            Some(TicketState::Pending) => TicketStatus_State::PENDING,
+            Some(TicketState::Done) => TicketStatus_State::DONE,
+            Some(TicketState::Failed) => TicketStatus_State::FAILED,
+            None => TicketStatus_State::UNKNOWN,
+        });
+        grpc::SingleResponse::completed(response)
+    }
+
+    fn list_devices(&self, _m: grpc::RequestOptions, _req: ListDevicesReq)
+        -> grpc::SingleResponse<DeviceList> {
+
+        let devices = self.registry.lock().unwrap().devices();
+        let mut response = DeviceList::new();
+        response.set_devices(RepeatedField::from_vec(devices.iter().map(device_identity).collect()));
+        grpc::SingleResponse::completed(response)
+    }
+
+    fn get_framing_stats(&self, _m: grpc::RequestOptions, _req: FramingStatsReq)
+        -> grpc::SingleResponse<FramingStats> {
+
+        let mut stats = FramingStats::new();
+        stats.set_discarded_bytes(self.framing_errors.discarded_bytes.load(Ordering::Relaxed) as u64);
+        stats.set_unknown_frames(self.framing_errors.unknown_frames.load(Ordering::Relaxed) as u64);
+        stats.set_malformed_frames(self.framing_errors.malformed_frames.load(Ordering::Relaxed) as u64);
+        stats.set_timed_out_frames(self.framing_errors.timed_out_frames.load(Ordering::Relaxed) as u64);
+
+        grpc::SingleResponse::completed(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn a() -> InsteonAddress {
+        InsteonAddress::new(0x1A, 0xD0, 0xF4)
+    }
+
+    fn ack(cmd1: u8, cmd2: u8) -> InsteonMsg {
+        InsteonMsg::StandardMsg { addr_from: a(), addr_to: InsteonAddress::new(0x44, 0x85, 0x11),
+                                  msg_flags: MessageFlags::new(MessageType::DirectAck, false),
+                                  cmd1: cmd1, cmd2: cmd2 }
+    }
+
+    fn fast_off() -> Request {
+        let mut control = DeviceControl::new();
+        control.set_device("1A.D0.F4".to_owned());
+        control.set_command(DeviceControl_Command::FAST_OFF);
+        let mut cmd = CmdMsg::new();
+        cmd.set_deviceControl(control);
+        Request::Cmd(cmd)
+    }
+
+    #[test]
+    fn a_command_is_answered_by_the_ack_echoing_its_cmd1() {
+        assert!(fast_off().reply(&ack(0x11, 0xFF)).is_none());
+        assert!(fast_off().reply(&ack(0x14, 0x00)).is_some());
+    }
+
+    #[test]
+    fn a_status_request_takes_any_ack_from_the_device() {
+        match Request::Status(a()).reply(&ack(0x03, 0x80)) {
+            Some(Reply::Status(status)) => {
+                assert_eq!(status.get_aldb_delta(), 0x03);
+                assert_eq!(status.get_level(), 50);
+            },
+            _ => panic!("no status"),
+        }
+    }
+}
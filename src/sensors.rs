@@ -0,0 +1,167 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use address::InsteonAddress;
+use insteon_structs::*;
+use registry::Identity;
+
+/// Device category of the battery-powered sensors.
+pub const SENSOR_CATEGORY :u8 = 0x10;
+
+const SENSOR_GROUP :u8 = 0x01;
+/// Dusk/dawn on motion sensors, wet on leak sensors.
+const SECOND_GROUP :u8 = 0x02;
+const LOW_BATTERY_GROUP :u8 = 0x03;
+const HEARTBEAT_GROUP :u8 = 0x04;
+
+#[derive(Debug)]
+#[derive(Copy, Clone)]
+#[derive(Serialize, Deserialize, PartialEq)]
+pub enum SensorKind {
+    Motion,
+    OpenClose,
+    Leak,
+}
+
+impl SensorKind {
+    pub fn from_identity(identity: &Identity) -> Option<SensorKind> {
+        if identity.category != SENSOR_CATEGORY {
+            return None
+        }
+
+        match identity.subcategory {
+            0x01 | 0x16 => Some(SensorKind::Motion),
+            0x02 | 0x11 => Some(SensorKind::OpenClose),
+            0x08 => Some(SensorKind::Leak),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[derive(Copy, Clone)]
+#[derive(Serialize, Deserialize, PartialEq)]
+pub enum SensorEvent {
+    Motion,
+    Clear,
+    Open,
+    Closed,
+    Wet,
+    Dry,
+    Dark,
+    Light,
+    LowBattery,
+    Heartbeat,
+}
+
+impl SensorEvent {
+    /// Decodes the group broadcast a sensor of `kind` sent.
+    pub fn from_broadcast(kind: SensorKind, msg: &InsteonMsg) -> Option<(InsteonAddress, SensorEvent)> {
+        let (addr_from, group, on) = match msg.group_on_off() {
+            Some(broadcast) => broadcast,
+            None => return None,
+        };
+
+        let event = match (kind, group, on) {
+            (_, HEARTBEAT_GROUP, _) => SensorEvent::Heartbeat,
+            (_, LOW_BATTERY_GROUP, true) => SensorEvent::LowBattery,
+            (SensorKind::Motion, SENSOR_GROUP, true) => SensorEvent::Motion,
+            (SensorKind::Motion, SENSOR_GROUP, false) => SensorEvent::Clear,
+            (SensorKind::Motion, SECOND_GROUP, true) => SensorEvent::Dark,
+            (SensorKind::Motion, SECOND_GROUP, false) => SensorEvent::Light,
+            (SensorKind::OpenClose, SENSOR_GROUP, true) => SensorEvent::Open,
+            (SensorKind::OpenClose, SENSOR_GROUP, false) => SensorEvent::Closed,
+            (SensorKind::Leak, SENSOR_GROUP, true) => SensorEvent::Dry,
+            (SensorKind::Leak, SECOND_GROUP, true) => SensorEvent::Wet,
+            _ => return None,
+        };
+
+        Some((addr_from, event))
+    }
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0)
+}
+
+/// What the daemon knows about a battery sensor.
+#[derive(Debug)]
+#[derive(Copy, Clone)]
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct SensorState {
+    pub kind: SensorKind,
+    pub last_event: Option<SensorEvent>,
+    /// Unix time of the last message the sensor sent, whatever it was.
+    pub last_seen: u64,
+    pub low_battery: bool,
+}
+
+impl SensorState {
+    pub fn new(kind: SensorKind) -> SensorState {
+        SensorState {
+            kind: kind,
+            last_event: None,
+            last_seen: 0,
+            low_battery: false,
+        }
+    }
+
+    pub fn apply(&mut self, event: SensorEvent) {
+        match event {
+            SensorEvent::LowBattery => self.low_battery = true,
+            SensorEvent::Heartbeat => (),
+            _ => self.last_event = Some(event),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn a() -> InsteonAddress {
+        InsteonAddress::new(0x3C, 0x11, 0x9E)
+    }
+
+    fn broadcast(cmd1: u8, group: u8) -> InsteonMsg {
+        InsteonMsg::StandardMsg { addr_from: a(), addr_to: InsteonAddress::new(0x00, 0x00, group),
+                                  msg_flags: MessageFlags::new(MessageType::GroupBroadcast, false),
+                                  cmd1: cmd1, cmd2: 0x00 }
+    }
+
+    fn event(kind: SensorKind, cmd1: u8, group: u8) -> Option<SensorEvent> {
+        SensorEvent::from_broadcast(kind, &broadcast(cmd1, group)).map(|(_, event)| event)
+    }
+
+    #[test]
+    fn decodes_each_kind_of_sensor() {
+        assert_eq!(event(SensorKind::Motion, 0x11, 1), Some(SensorEvent::Motion));
+        assert_eq!(event(SensorKind::Motion, 0x13, 1), Some(SensorEvent::Clear));
+        assert_eq!(event(SensorKind::Motion, 0x11, 2), Some(SensorEvent::Dark));
+        assert_eq!(event(SensorKind::Motion, 0x13, 2), Some(SensorEvent::Light));
+        assert_eq!(event(SensorKind::OpenClose, 0x11, 1), Some(SensorEvent::Open));
+        assert_eq!(event(SensorKind::OpenClose, 0x13, 1), Some(SensorEvent::Closed));
+        assert_eq!(event(SensorKind::Leak, 0x11, 1), Some(SensorEvent::Dry));
+        assert_eq!(event(SensorKind::Leak, 0x11, 2), Some(SensorEvent::Wet));
+        assert_eq!(event(SensorKind::OpenClose, 0x11, 2), None);
+    }
+
+    #[test]
+    fn decodes_battery_and_heartbeat_groups_for_every_kind() {
+        assert_eq!(event(SensorKind::Leak, 0x11, 3), Some(SensorEvent::LowBattery));
+        assert_eq!(event(SensorKind::Leak, 0x13, 3), None);
+        assert_eq!(event(SensorKind::Motion, 0x11, 4), Some(SensorEvent::Heartbeat));
+        assert_eq!(event(SensorKind::OpenClose, 0x13, 4), Some(SensorEvent::Heartbeat));
+        // Only On and Off broadcasts carry sensor events.
+        assert_eq!(event(SensorKind::Motion, 0x12, 1), None);
+    }
+
+    #[test]
+    fn keeps_the_last_event_apart_from_the_battery() {
+        let mut state = SensorState::new(SensorKind::OpenClose);
+        state.apply(SensorEvent::Open);
+        state.apply(SensorEvent::LowBattery);
+        state.apply(SensorEvent::Heartbeat);
+        assert_eq!(state.last_event, Some(SensorEvent::Open));
+        assert!(state.low_battery);
+    }
+}
@@ -2,67 +2,343 @@ extern crate tokio_serial;
 extern crate tokio_io;
 extern crate tokio_codec;
 
-use robots::actors::{Actor, ActorCell};
-use bincode::{serialize, Infinite};
+use robots::actors::{Actor, ActorCell, ActorContext};
 
 use std::any::Any;
+use std::collections::VecDeque;
 use std::sync::Mutex;
 use std::sync::Arc;
+use std::time::Duration;
 
 use futures::Sink;
-use futures::stream::SplitSink;
+use tokio_io::io::WriteHalf;
 
 use insteon_structs::*;
 use codec::*;
+use timer;
 
-pub struct SerialWriterActor {
-    writer_arc : Arc<Mutex<SplitSink<tokio_codec::Framed<tokio_serial::Serial, LineCodec>>>>,
+/// How long to wait for the PLM to echo a command before sending it again.
+pub const ECHO_TIMEOUT_MS : u64 = 500;
+/// First delay after a NAK; doubled on every further NAK of the same frame.
+pub const NAK_BACKOFF_MS : u64 = 100;
+/// Attempts per frame, counting NAKs and missing echoes, before it is dropped.
+pub const MAX_SEND_ATTEMPTS : usize = 6;
+
+#[derive(Copy, Clone)]
+enum SerialWriterMsg {
+    EchoTimeout(usize),
+    Resend(usize),
 }
 
-impl Actor for SerialWriterActor {
-    fn receive(&self, message: Box<Any>, _context: ActorCell) {
-        if let Ok(message) = Box::<Any>::downcast::<ActorMsg>(message) {
-            match *message {
-                ActorMsg::Level((device, level)) => {
+/// What the writer has to do next, tagged with the sequence number of the
+/// only timer still allowed to act on the frame.
+#[derive(Debug, PartialEq)]
+enum Step {
+    /// Write the frame and wait for its echo.
+    Write(InsteonMsg, usize),
+    /// Write the in-flight frame again after this many milliseconds.
+    Backoff(u64, usize),
+}
 
-                    let shared_writer = self.writer_arc.clone();
+struct InFlight {
+    msg: InsteonMsg,
+    /// Sequence number of the only timer still allowed to act on this frame.
+    seq: usize,
+    attempts: usize,
+}
 
-                    let scale = level as f64 / 100.0;
-                    let brightness = (scale * 255.0) as u8;
-                    trace!("Brightness set to {}", brightness);
+/// The queue of frames waiting for the PLM, and the one it is working on.
+struct WriterState {
+    queue: VecDeque<InsteonMsg>,
+    in_flight: Option<InFlight>,
+    next_seq: usize,
+}
 
-                    let msg = InsteonMsg::SendStandardMsg{
-                        addr_to : device,
-                        msg_flags : Flags::DIRECT_MSG | Flags::STANDARD_MSG |
-                                    Flags::MSG_REMAINING_3 | Flags::RETRANSMIT_3,
-                        cmd1 : u8_command(Command::On),
-                        cmd2 : brightness
-                    };
+impl WriterState {
+    fn new() -> WriterState {
+        WriterState {
+            queue : VecDeque::new(),
+            in_flight : None,
+            next_seq : 0,
+        }
+    }
 
-                    debug!("Sending command: {:?}", msg);
+    fn enqueue(&mut self, msg: InsteonMsg) -> Option<Step> {
+        self.queue.push_back(msg);
+        trace!("Queued {:?}, {} frame(s) waiting", msg, self.queue.len());
+        self.send_next()
+    }
 
-                    let mut struct_repr = serialize(&msg, Infinite).unwrap();
-                    struct_repr.drain(..DISCRIMINANT_SIZE);
+    fn echo(&mut self, message: InsteonMsg) -> Option<Step> {
+        let (status, nak_means_busy) = match self.in_flight {
+            Some(ref in_flight) => match message.echo_status(&in_flight.msg) {
+                Some(status) => (status, in_flight.msg.nak_means_busy()),
+                None => return None,
+            },
+            None => return None,
+        };
 
-                    let encoded_msg = [vec![MSG_BEGIN, SEND_STANDARD_MSG], struct_repr].concat();
-                    trace!("Encoded command: {:?}", encoded_msg);
+        match status {
+            PlmStatus::Nak if !nak_means_busy => {
+                trace!("PLM answered {:?}", message);
+                self.in_flight = None;
+                self.send_next()
+            },
+            PlmStatus::Ack => {
+                trace!("PLM acknowledged {:?}", message);
+                self.in_flight = None;
+                self.send_next()
+            },
+            PlmStatus::Nak => {
+                let attempts = {
+                    let in_flight = self.in_flight.as_mut().unwrap();
+                    in_flight.attempts += 1;
+                    in_flight.attempts
+                };
 
-                    let mut exclusive_writer = shared_writer.lock().unwrap();
-                    exclusive_writer.start_send(encoded_msg).unwrap();
-                    exclusive_writer.poll_complete().unwrap();
+                if attempts >= MAX_SEND_ATTEMPTS {
+                    warn!("PLM kept refusing {:?}, dropping it", message);
+                    self.in_flight = None;
+                    self.send_next()
+                } else {
+                    let backoff = NAK_BACKOFF_MS << (attempts - 1);
+                    debug!("PLM busy, resending in {}ms", backoff);
+                    Some(Step::Backoff(backoff, self.rearm()))
                 }
+            },
+        }
+    }
+
+    fn echo_timeout(&mut self, seq: usize) -> Option<Step> {
+        let attempts = match self.in_flight {
+            Some(ref mut in_flight) if in_flight.seq == seq => {
+                in_flight.attempts += 1;
+                in_flight.attempts
+            },
+            _ => return None,
+        };
+
+        if attempts >= MAX_SEND_ATTEMPTS {
+            warn!("PLM never echoed {:?}, dropping it", self.in_flight.as_ref().unwrap().msg);
+            self.in_flight = None;
+            self.send_next()
+        } else {
+            debug!("No echo from the PLM, resending");
+            self.write()
+        }
+    }
+
+    fn resend(&mut self, seq: usize) -> Option<Step> {
+        match self.in_flight {
+            Some(ref in_flight) if in_flight.seq == seq => (),
+            _ => return None,
+        }
+
+        self.write()
+    }
+
+    fn send_next(&mut self) -> Option<Step> {
+        if self.in_flight.is_some() {
+            return None
+        }
+
+        match self.queue.pop_front() {
+            Some(msg) => {
+                self.in_flight = Some(InFlight {
+                    msg : msg,
+                    seq : 0,
+                    attempts : 0,
+                });
+                self.write()
+            },
+            None => None,
+        }
+    }
+
+    fn write(&mut self) -> Option<Step> {
+        let msg = match self.in_flight {
+            Some(ref in_flight) => in_flight.msg,
+            None => return None,
+        };
+        Some(Step::Write(msg, self.rearm()))
+    }
+
+    /// Invalidates any timer pending for the in-flight frame and returns the
+    /// sequence number of the next one.
+    fn rearm(&mut self) -> usize {
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        if let Some(ref mut in_flight) = self.in_flight {
+            in_flight.seq = seq;
+        }
+        seq
+    }
+}
+
+/// Writes frames to the PLM one at a time. A frame stays in flight until the
+/// modem echoes it back with an ACK; a NAK means the modem is busy and the
+/// frame is resent after a backoff.
+pub struct SerialWriterActor {
+    writer_arc : Arc<Mutex<tokio_codec::FramedWrite<WriteHalf<tokio_serial::Serial>, LineCodec>>>,
+    state : Mutex<WriterState>,
+}
+
+impl Actor for SerialWriterActor {
+    fn receive(&self, message: Box<Any>, context: ActorCell) {
+        let step = if let Some(actor_msg) = message.downcast_ref::<ActorMsg>() {
+            match *actor_msg {
+                ActorMsg::Send(msg) => self.state.lock().unwrap().enqueue(msg),
             }
+        } else if let Some(writer_msg) = message.downcast_ref::<SerialWriterMsg>() {
+            match *writer_msg {
+                SerialWriterMsg::EchoTimeout(seq) => self.state.lock().unwrap().echo_timeout(seq),
+                SerialWriterMsg::Resend(seq) => self.state.lock().unwrap().resend(seq),
+            }
+        } else if let Some(insteon_msg) = message.downcast_ref::<InsteonMsg>() {
+            self.state.lock().unwrap().echo(*insteon_msg)
+        } else {
+            None
+        };
+
+        match step {
+            Some(Step::Write(msg, seq)) => self.write(msg, seq, &context),
+            Some(Step::Backoff(backoff, seq)) =>
+                timer::schedule(context.actor_ref(), SerialWriterMsg::Resend(seq),
+                                Duration::from_millis(backoff)),
+            None => (),
         }
     }
 }
 
 impl SerialWriterActor {
     pub fn new(writer_arc :
-        Arc<Mutex<SplitSink<tokio_codec::Framed<tokio_serial::Serial, LineCodec>>>>,
+        Arc<Mutex<tokio_codec::FramedWrite<WriteHalf<tokio_serial::Serial>, LineCodec>>>,
     ) -> SerialWriterActor {
 
         SerialWriterActor{
             writer_arc : writer_arc,
+            state : Mutex::new(WriterState::new()),
         }
     }
-}
\ No newline at end of file
+
+    fn write(&self, msg: InsteonMsg, seq: usize, context: &ActorCell) {
+        debug!("Sending command: {:?}", msg);
+
+        {
+            let mut exclusive_writer = self.writer_arc.lock().unwrap();
+            if let Err(e) = exclusive_writer.start_send(msg)
+                .and_then(|_| exclusive_writer.poll_complete()) {
+                error!("Unable to send {:?}: {}", msg, e);
+            }
+        }
+
+        timer::schedule(context.actor_ref(), SerialWriterMsg::EchoTimeout(seq),
+                        Duration::from_millis(ECHO_TIMEOUT_MS));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use address::InsteonAddress;
+
+    fn frame(cmd1: u8) -> InsteonMsg {
+        InsteonMsg::SendStandardMsg { addr_to: InsteonAddress::new(0x1A, 0xD0, 0xF4), msg_flags: MessageFlags::direct(), cmd1: cmd1, cmd2: 0xFF }
+    }
+
+    fn echo(cmd1: u8, status: PlmStatus) -> InsteonMsg {
+        InsteonMsg::SendStandardMsgEcho { addr_to: InsteonAddress::new(0x1A, 0xD0, 0xF4), msg_flags: MessageFlags::direct(), cmd1: cmd1, cmd2: 0xFF,
+                                          status: status }
+    }
+
+    fn seq_of(step: Option<Step>) -> usize {
+        match step {
+            Some(Step::Write(_, seq)) | Some(Step::Backoff(_, seq)) => seq,
+            None => panic!("no step"),
+        }
+    }
+
+    #[test]
+    fn writes_one_frame_at_a_time() {
+        let mut state = WriterState::new();
+        assert_eq!(state.enqueue(frame(0x11)), Some(Step::Write(frame(0x11), 0)));
+        assert_eq!(state.enqueue(frame(0x13)), None);
+
+        // Echoes of anything but the frame in flight are not for the writer.
+        assert_eq!(state.echo(echo(0x13, PlmStatus::Ack)), None);
+        assert_eq!(state.echo(echo(0x11, PlmStatus::Ack)), Some(Step::Write(frame(0x13), 1)));
+        assert_eq!(state.echo(echo(0x13, PlmStatus::Ack)), None);
+        assert!(state.in_flight.is_none());
+    }
+
+    #[test]
+    fn resends_when_the_echo_times_out() {
+        let mut state = WriterState::new();
+        let first = seq_of(state.enqueue(frame(0x11)));
+
+        assert_eq!(state.echo_timeout(first), Some(Step::Write(frame(0x11), first + 1)));
+        // The timer of the first write no longer counts.
+        assert_eq!(state.echo_timeout(first), None);
+        assert_eq!(state.in_flight.as_ref().unwrap().attempts, 1);
+    }
+
+    #[test]
+    fn backs_off_exponentially_on_nak() {
+        let mut state = WriterState::new();
+        state.enqueue(frame(0x11));
+
+        for &expected in &[100, 200, 400, 800, 1600] {
+            match state.echo(echo(0x11, PlmStatus::Nak)) {
+                Some(Step::Backoff(backoff, seq)) => {
+                    assert_eq!(backoff, expected);
+                    // Only the latest backoff may resend, and its echo timer is the next one.
+                    assert_eq!(state.resend(seq.wrapping_sub(1)), None);
+                    assert_eq!(state.resend(seq), Some(Step::Write(frame(0x11), seq + 1)));
+                },
+                other => panic!("NAK led to {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn drops_a_frame_after_the_last_attempt() {
+        let mut state = WriterState::new();
+        state.enqueue(frame(0x11));
+        state.enqueue(frame(0x13));
+
+        for _ in 1..MAX_SEND_ATTEMPTS {
+            let seq = seq_of(state.echo(echo(0x11, PlmStatus::Nak)));
+            state.resend(seq);
+        }
+        match state.echo(echo(0x11, PlmStatus::Nak)) {
+            Some(Step::Write(msg, _)) => assert_eq!(msg, frame(0x13)),
+            other => panic!("last NAK led to {:?}", other),
+        }
+    }
+
+    #[test]
+    fn counts_missing_echoes_and_naks_together() {
+        let mut state = WriterState::new();
+        let mut seq = seq_of(state.enqueue(frame(0x11)));
+
+        for _ in 0..2 {
+            seq = seq_of(state.echo_timeout(seq));
+        }
+        for _ in 0..MAX_SEND_ATTEMPTS - 3 {
+            let backoff = seq_of(state.echo(echo(0x11, PlmStatus::Nak)));
+            seq = seq_of(state.resend(backoff));
+        }
+        assert_eq!(state.echo_timeout(seq), None);
+        assert!(state.in_flight.is_none());
+    }
+
+    #[test]
+    fn takes_a_nak_to_a_record_query_as_the_answer() {
+        let mut state = WriterState::new();
+        state.enqueue(InsteonMsg::GetNextAllLinkRecord {});
+        state.enqueue(frame(0x11));
+
+        let end = InsteonMsg::GetNextAllLinkRecordEcho { status: PlmStatus::Nak };
+        assert_eq!(state.echo(end), Some(Step::Write(frame(0x11), 1)));
+    }
+}
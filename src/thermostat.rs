@@ -0,0 +1,363 @@
+use address::InsteonAddress;
+use engine::Dialect;
+use insteon_structs::*;
+
+pub const THERMOSTAT_CONTROL :u8 = 0x6B;
+pub const SET_COOL_SETPOINT :u8 = 0x6C;
+pub const SET_HEAT_SETPOINT :u8 = 0x6D;
+
+/// What a thermostat linked to the modem reports by itself whenever it changes.
+pub const TEMPERATURE_CHANGED :u8 = 0x6E;
+pub const HUMIDITY_CHANGED :u8 = 0x6F;
+pub const MODE_CHANGED :u8 = 0x70;
+pub const COOL_SETPOINT_CHANGED :u8 = 0x71;
+pub const HEAT_SETPOINT_CHANGED :u8 = 0x72;
+
+/// Extended get/set data set holding the full thermostat status.
+const STATUS_DATA_SET :u8 = 0x02;
+const STATUS_RESPONSE :u8 = 0x01;
+
+const STATUS_COOLING :u8 = 0b0000_0001;
+const STATUS_HEATING :u8 = 0b0000_0010;
+const STATUS_CELSIUS :u8 = 0b0000_1000;
+
+#[derive(Debug)]
+#[derive(Copy, Clone)]
+#[derive(Serialize, Deserialize, PartialEq)]
+pub enum ThermostatMode {
+    Off,
+    Heat,
+    Cool,
+    Auto,
+    /// Follows the thermostat's own schedule.
+    Program,
+}
+
+impl ThermostatMode {
+    /// Decodes the low nibble of a mode report.
+    pub fn from_nibble(nibble: u8) -> Option<ThermostatMode> {
+        match nibble & 0x0F {
+            0x00 => Some(ThermostatMode::Off),
+            0x01 => Some(ThermostatMode::Heat),
+            0x02 => Some(ThermostatMode::Cool),
+            0x03 => Some(ThermostatMode::Auto),
+            0x04 => Some(ThermostatMode::Program),
+            _ => None,
+        }
+    }
+
+    fn control(&self) -> u8 {
+        match *self {
+            ThermostatMode::Heat => 0x04,
+            ThermostatMode::Cool => 0x05,
+            ThermostatMode::Auto => 0x06,
+            ThermostatMode::Off => 0x09,
+            ThermostatMode::Program => 0x0A,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[derive(Copy, Clone)]
+#[derive(Serialize, Deserialize, PartialEq)]
+pub enum FanMode {
+    Auto,
+    On,
+}
+
+impl FanMode {
+    /// Decodes the high nibble of a mode report.
+    pub fn from_nibble(nibble: u8) -> Option<FanMode> {
+        match nibble & 0x0F {
+            0x00 => Some(FanMode::Auto),
+            0x01 => Some(FanMode::On),
+            _ => None,
+        }
+    }
+
+    fn control(&self) -> u8 {
+        match *self {
+            FanMode::On => 0x07,
+            FanMode::Auto => 0x08,
+        }
+    }
+}
+
+/// The readings a Thermal Zone Info (0x6A) request can ask for; the ACK
+/// carries the value in cmd2.
+#[derive(Debug)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum ZoneInfo {
+    /// In half degrees.
+    Temperature,
+    /// In half degrees.
+    Setpoint,
+    Deadband,
+    Humidity,
+}
+
+impl ZoneInfo {
+    /// The reading the raw cmd2 of the ACK stands for, temperatures in
+    /// degrees like everywhere else.
+    pub fn value(&self, raw: u8) -> f32 {
+        match *self {
+            ZoneInfo::Temperature | ZoneInfo::Setpoint => half_degrees(raw),
+            ZoneInfo::Deadband | ZoneInfo::Humidity => raw as f32,
+        }
+    }
+
+    fn cmd2(&self) -> u8 {
+        match *self {
+            ZoneInfo::Temperature => 0x00,
+            ZoneInfo::Setpoint => 0x20,
+            ZoneInfo::Deadband => 0x40,
+            ZoneInfo::Humidity => 0x60,
+        }
+    }
+}
+
+/// The direct commands of the 2441TH/2441ZTH. Setpoints are in whole
+/// degrees of whatever unit the thermostat displays.
+#[derive(Debug)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum ThermostatCommand {
+    Mode(ThermostatMode),
+    Fan(FanMode),
+    CoolSetpoint(u8),
+    HeatSetpoint(u8),
+    ZoneInfo(ZoneInfo),
+}
+
+impl ThermostatCommand {
+    pub fn cmd1(&self) -> u8 {
+        match *self {
+            ThermostatCommand::Mode(_) | ThermostatCommand::Fan(_) => THERMOSTAT_CONTROL,
+            ThermostatCommand::CoolSetpoint(_) => SET_COOL_SETPOINT,
+            ThermostatCommand::HeatSetpoint(_) => SET_HEAT_SETPOINT,
+            ThermostatCommand::ZoneInfo(_) => u8_command(Command::ThermalZoneInfo),
+        }
+    }
+
+    pub fn cmd2(&self) -> u8 {
+        match *self {
+            ThermostatCommand::Mode(mode) => mode.control(),
+            ThermostatCommand::Fan(fan) => fan.control(),
+            ThermostatCommand::CoolSetpoint(degrees) |
+            ThermostatCommand::HeatSetpoint(degrees) => degrees.saturating_mul(2),
+            ThermostatCommand::ZoneInfo(info) => info.cmd2(),
+        }
+    }
+
+    /// i2cs thermostats ignore these commands unless they are sent extended.
+    pub fn to_msg(&self, device: InsteonAddress, dialect: Dialect) -> InsteonMsg {
+        match dialect.checksum() {
+            Some(checksum) =>
+                InsteonMsg::send_extended(device, self.cmd1(), self.cmd2(), [0u8; 14],
+                                          Some(checksum)),
+            None => InsteonMsg::SendStandardMsg {
+                addr_to: device,
+                msg_flags: MessageFlags::direct(),
+                cmd1: self.cmd1(),
+                cmd2: self.cmd2(),
+            },
+        }
+    }
+}
+
+/// Temperature reports and zone info readings count half degrees.
+fn half_degrees(raw: u8) -> f32 {
+    raw as f32 / 2.0
+}
+
+/// An unsolicited report from a thermostat.
+#[derive(Debug)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum ThermostatEvent {
+    /// In degrees.
+    Temperature(f32),
+    Humidity(u8),
+    Mode(Option<ThermostatMode>, Option<FanMode>),
+    CoolSetpoint(u8),
+    HeatSetpoint(u8),
+}
+
+impl ThermostatEvent {
+    pub fn from_msg(msg: &InsteonMsg) -> Option<(InsteonAddress, ThermostatEvent)> {
+        let (addr_from, cmd1, cmd2) = match *msg {
+            InsteonMsg::StandardMsg { addr_from, msg_flags, cmd1, cmd2, .. }
+                if msg_flags.msg_type == MessageType::Direct ||
+                   msg_flags.msg_type == MessageType::GroupBroadcast => (addr_from, cmd1, cmd2),
+            _ => return None,
+        };
+
+        let event = match cmd1 {
+            TEMPERATURE_CHANGED => ThermostatEvent::Temperature(half_degrees(cmd2)),
+            HUMIDITY_CHANGED => ThermostatEvent::Humidity(cmd2),
+            MODE_CHANGED => ThermostatEvent::Mode(ThermostatMode::from_nibble(cmd2),
+                                                  FanMode::from_nibble(cmd2 >> 4)),
+            COOL_SETPOINT_CHANGED => ThermostatEvent::CoolSetpoint(cmd2),
+            HEAT_SETPOINT_CHANGED => ThermostatEvent::HeatSetpoint(cmd2),
+            _ => return None,
+        };
+
+        Some((addr_from, event))
+    }
+}
+
+/// Everything known about a thermostat, from its status data set or pieced
+/// together from its unsolicited reports. Temperatures are in degrees of
+/// the unit the thermostat displays, like its setpoints.
+#[derive(Debug)]
+#[derive(Copy, Clone, Default)]
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct ThermostatState {
+    pub mode: Option<ThermostatMode>,
+    pub fan: Option<FanMode>,
+    pub temperature: Option<f32>,
+    pub humidity: Option<u8>,
+    pub cool_setpoint: Option<u8>,
+    pub heat_setpoint: Option<u8>,
+    pub cooling: bool,
+    pub heating: bool,
+    pub celsius: bool,
+}
+
+impl ThermostatState {
+    pub fn apply(&mut self, event: ThermostatEvent) {
+        match event {
+            ThermostatEvent::Temperature(degrees) => self.temperature = Some(degrees),
+            ThermostatEvent::Humidity(percent) => self.humidity = Some(percent),
+            ThermostatEvent::Mode(mode, fan) => {
+                self.mode = mode;
+                self.fan = fan;
+            },
+            ThermostatEvent::CoolSetpoint(degrees) => self.cool_setpoint = Some(degrees),
+            ThermostatEvent::HeatSetpoint(degrees) => self.heat_setpoint = Some(degrees),
+        }
+    }
+
+    /// Decodes the status data set `device` sends in answer to `status_request`.
+    pub fn from_status(msg: &InsteonMsg, device: InsteonAddress) -> Option<ThermostatState> {
+        match *msg {
+            InsteonMsg::ExtendedMsg { addr_from, cmd1, cmd2, ref user_data, .. }
+                if addr_from == device && cmd1 == EXTENDED_GET_SET &&
+                   cmd2 == STATUS_DATA_SET && user_data[0] == STATUS_RESPONSE => {
+                // D10-D11 hold the ambient temperature in tenths of a degree
+                // Celsius, whichever unit the thermostat displays.
+                let tenths = (user_data[9] as u16) << 8 | user_data[10] as u16;
+                let celsius = user_data[11] & STATUS_CELSIUS != 0;
+                let temperature = tenths as f32 / 10.0;
+                Some(ThermostatState {
+                    mode: ThermostatMode::from_nibble(user_data[5]),
+                    fan: FanMode::from_nibble(user_data[5] >> 4),
+                    temperature: Some(if celsius { temperature } else { temperature * 1.8 + 32.0 }),
+                    humidity: Some(user_data[8]),
+                    cool_setpoint: Some(user_data[7]),
+                    heat_setpoint: Some(user_data[12]),
+                    cooling: user_data[11] & STATUS_COOLING != 0,
+                    heating: user_data[11] & STATUS_HEATING != 0,
+                    celsius: celsius,
+                })
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Asks a thermostat for its full status data set.
+pub fn status_data_request(device: InsteonAddress, dialect: Dialect) -> Option<InsteonMsg> {
+    dialect.extended(device, EXTENDED_GET_SET, STATUS_DATA_SET, [0u8; 14])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use checksum::Checksum;
+
+    fn a() -> InsteonAddress {
+        InsteonAddress::new(0x1A, 0xD0, 0xF4)
+    }
+
+    fn report(cmd1: u8, cmd2: u8) -> InsteonMsg {
+        InsteonMsg::StandardMsg { addr_from: a(), addr_to: InsteonAddress::new(0x00, 0x00, 0x01),
+                                  msg_flags: MessageFlags::new(MessageType::GroupBroadcast, false),
+                                  cmd1: cmd1, cmd2: cmd2 }
+    }
+
+    /// A status data set laid out as the 2441TH documents it: mode in D6,
+    /// cool setpoint in D8, humidity in D9, temperature in D10-D11, status
+    /// flags in D12 and heat setpoint in D13.
+    fn data_set(temperature_tenths: u16, flags: u8) -> InsteonMsg {
+        let mut user_data = [0u8; 14];
+        user_data[0] = STATUS_RESPONSE;
+        user_data[5] = 0x13;
+        user_data[7] = 78;
+        user_data[8] = 45;
+        user_data[9] = (temperature_tenths >> 8) as u8;
+        user_data[10] = temperature_tenths as u8;
+        user_data[11] = flags;
+        user_data[12] = 68;
+        InsteonMsg::ExtendedMsg { addr_from: a(), addr_to: InsteonAddress::new(0x44, 0x85, 0x11),
+                                  msg_flags: MessageFlags::new(MessageType::Direct, true),
+                                  cmd1: EXTENDED_GET_SET, cmd2: STATUS_DATA_SET,
+                                  user_data: user_data }
+    }
+
+    #[test]
+    fn decodes_the_unsolicited_reports() {
+        let decode = |cmd1, cmd2| ThermostatEvent::from_msg(&report(cmd1, cmd2)).map(|(_, event)| event);
+
+        assert_eq!(decode(TEMPERATURE_CHANGED, 143), Some(ThermostatEvent::Temperature(71.5)));
+        assert_eq!(decode(HUMIDITY_CHANGED, 45), Some(ThermostatEvent::Humidity(45)));
+        assert_eq!(decode(MODE_CHANGED, 0x13),
+                   Some(ThermostatEvent::Mode(Some(ThermostatMode::Auto), Some(FanMode::On))));
+        assert_eq!(decode(COOL_SETPOINT_CHANGED, 78), Some(ThermostatEvent::CoolSetpoint(78)));
+        assert_eq!(decode(HEAT_SETPOINT_CHANGED, 68), Some(ThermostatEvent::HeatSetpoint(68)));
+        assert_eq!(decode(0x11, 0xFF), None);
+    }
+
+    #[test]
+    fn decodes_the_status_data_set_in_the_displayed_unit() {
+        let state = ThermostatState::from_status(&data_set(215, STATUS_HEATING), a()).unwrap();
+        assert_eq!(state, ThermostatState {
+            mode: Some(ThermostatMode::Auto),
+            fan: Some(FanMode::On),
+            temperature: Some(70.7),
+            humidity: Some(45),
+            cool_setpoint: Some(78),
+            heat_setpoint: Some(68),
+            cooling: false,
+            heating: true,
+            celsius: false,
+        });
+
+        let state = ThermostatState::from_status(&data_set(215, STATUS_CELSIUS | STATUS_COOLING), a()).unwrap();
+        assert_eq!(state.temperature, Some(21.5));
+        assert!(state.celsius && state.cooling);
+
+        assert_eq!(ThermostatState::from_status(&data_set(215, 0), InsteonAddress::new(1, 2, 3)), None);
+    }
+
+    #[test]
+    fn every_source_reports_temperatures_in_degrees() {
+        let mut state = ThermostatState::default();
+        state.apply(ThermostatEvent::from_msg(&report(TEMPERATURE_CHANGED, 143)).unwrap().1);
+        assert_eq!(state.temperature, Some(71.5));
+        assert_eq!(ZoneInfo::Temperature.value(143), 71.5);
+        assert_eq!(ZoneInfo::Humidity.value(45), 45.0);
+    }
+
+    #[test]
+    fn sends_setpoints_in_half_degrees_extended_to_i2cs_thermostats() {
+        let cmd = ThermostatCommand::HeatSetpoint(68);
+        assert_eq!(cmd.to_msg(a(), Dialect::Extended), InsteonMsg::SendStandardMsg {
+            addr_to: a(),
+            msg_flags: MessageFlags::direct(),
+            cmd1: SET_HEAT_SETPOINT,
+            cmd2: 136,
+        });
+        assert_eq!(cmd.to_msg(a(), Dialect::ChecksummedExtended(Checksum::Crc)),
+                   InsteonMsg::send_extended(a(), SET_HEAT_SETPOINT, 136, [0u8; 14], Some(Checksum::Crc)));
+        assert_eq!(status_data_request(a(), Dialect::PeekPoke), None);
+    }
+}
@@ -0,0 +1,107 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::mpsc::{channel, Sender, RecvTimeoutError};
+use std::sync::{Mutex, Once, ONCE_INIT};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use robots::actors::{ActorRef, Message};
+
+struct Timer {
+    deadline: Instant,
+    /// Breaks ties between equal deadlines, so timers fire in the order they were set.
+    seq: u64,
+    fire: Box<FnMut() + Send>,
+}
+
+// Reversed so that the `BinaryHeap` pops the earliest deadline first.
+impl Ord for Timer {
+    fn cmp(&self, other: &Timer) -> Ordering {
+        (other.deadline, other.seq).cmp(&(self.deadline, self.seq))
+    }
+}
+
+impl PartialOrd for Timer {
+    fn partial_cmp(&self, other: &Timer) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Timer {
+    fn eq(&self, other: &Timer) -> bool {
+        self.seq == other.seq
+    }
+}
+
+impl Eq for Timer {}
+
+struct Timers {
+    tx: Sender<Timer>,
+    next_seq: u64,
+}
+
+static START: Once = ONCE_INIT;
+static mut TIMERS: *const Mutex<Timers> = 0 as *const Mutex<Timers>;
+
+fn run(rx: ::std::sync::mpsc::Receiver<Timer>) {
+    let mut pending : BinaryHeap<Timer> = BinaryHeap::new();
+    loop {
+        let received = match pending.peek().map(|timer| timer.deadline) {
+            Some(deadline) => {
+                let now = Instant::now();
+                if deadline <= now {
+                    let mut timer = pending.pop().unwrap();
+                    (timer.fire)();
+                    continue
+                }
+                rx.recv_timeout(deadline - now)
+            },
+            None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        match received {
+            Ok(timer) => pending.push(timer),
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+/// The daemon's single timer thread, started on first use.
+fn timers() -> &'static Mutex<Timers> {
+    START.call_once(|| {
+        let (tx, rx) = channel();
+        thread::Builder::new().name("timer".to_owned()).spawn(move || run(rx))
+            .expect("Unable to start the timer thread.");
+        let timers = Box::new(Mutex::new(Timers { tx: tx, next_seq: 0 }));
+        unsafe {
+            TIMERS = Box::into_raw(timers);
+        }
+    });
+
+    unsafe { &*TIMERS }
+}
+
+/// Runs `f` on the timer thread once `delay` is over; `f` must not block.
+///
+/// Timers cannot be cancelled. An actor that rearms a timeout tags its
+/// message with a counter bumped on every attempt, and ignores a timeout
+/// that does not carry the current one.
+pub fn after<F: FnOnce() + Send + 'static>(delay: Duration, f: F) {
+    let mut f = Some(f);
+    let mut timers = timers().lock().unwrap();
+    timers.next_seq += 1;
+    let timer = Timer {
+        deadline: Instant::now() + delay,
+        seq: timers.next_seq,
+        fire: Box::new(move || if let Some(f) = f.take() { f() }),
+    };
+    if timers.tx.send(timer).is_err() {
+        error!("The timer thread is gone, dropping a timer");
+    }
+}
+
+/// Sends `message` to `actor` once `delay` is over.
+pub fn schedule<M: Message>(actor: ActorRef, message: M, delay: Duration) {
+    after(delay, move || actor.tell_to(actor.clone(), message));
+}
@@ -0,0 +1,361 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use robots::actors::{ActorContext, ActorRef, Actor, Any, ActorCell};
+
+use address::InsteonAddress;
+use insteon_structs::*;
+use registry::DeviceRegistry;
+use rpc::{Request, RpcActorMsg};
+use timer;
+
+/// Wakes a batch may be sent on before it is given up on.
+pub const MAX_WAKES :usize = 3;
+
+pub const WAKE_WAIT_INTERVAL_MS : u64 = 500;
+pub const WAKE_SEND_RETRIES : usize = 2;
+
+#[derive(Debug)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum TicketState {
+    /// Waiting for the device to wake up, or being sent to it.
+    Pending,
+    Done,
+    Failed,
+}
+
+/// Requests held for battery devices, which only listen for a moment after
+/// they transmit. The requests of one call are held together as a batch,
+/// and the batch gets a ticket its caller can poll.
+pub struct WakeQueue<T> {
+    next_ticket: u64,
+    queued: HashMap<InsteonAddress, VecDeque<(u64, VecDeque<T>)>>,
+    /// Devices whose held batches are being sent while they are awake.
+    sending: HashSet<InsteonAddress>,
+    tickets: HashMap<u64, TicketState>,
+    wakes: HashMap<u64, usize>,
+}
+
+impl<T> WakeQueue<T> {
+    pub fn new() -> WakeQueue<T> {
+        WakeQueue {
+            next_ticket: 1,
+            queued: HashMap::new(),
+            sending: HashSet::new(),
+            tickets: HashMap::new(),
+            wakes: HashMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, device: InsteonAddress, requests: Vec<T>) -> u64 {
+        let ticket = self.next_ticket;
+        self.next_ticket += 1;
+        info!("Holding {} request(s) as {} until {} wakes up", requests.len(), ticket, device);
+        self.queued.entry(device).or_insert_with(VecDeque::new)
+            .push_back((ticket, requests.into_iter().collect()));
+        self.tickets.insert(ticket, TicketState::Pending);
+        ticket
+    }
+
+    /// Whether a request matching `f` is held for `device`.
+    pub fn holds<F: Fn(&T) -> bool>(&self, device: &InsteonAddress, f: F) -> bool {
+        self.queued.get(device).map_or(false, |queue| {
+            queue.iter().any(|&(_, ref batch)| batch.iter().any(|request| f(request)))
+        })
+    }
+
+    /// Called whenever `device` transmits. True if something is held for it
+    /// and nothing is sending it yet, in which case the caller is to start
+    /// sending with `next` until it returns `None`.
+    pub fn wake(&mut self, device: &InsteonAddress) -> bool {
+        if self.sending.contains(device) || self.queued.get(device).map_or(true, |queue| queue.is_empty()) {
+            return false
+        }
+        self.sending.insert(*device);
+        true
+    }
+
+    /// The next batch to send to the awake `device`, including any held
+    /// while the previous ones were sent. `None` once there is none left.
+    pub fn next(&mut self, device: &InsteonAddress) -> Option<(u64, VecDeque<T>)> {
+        let next = self.queued.get_mut(device).and_then(|queue| queue.pop_front());
+        match next {
+            Some((ticket, batch)) => {
+                *self.wakes.entry(ticket).or_insert(0) += 1;
+                Some((ticket, batch))
+            },
+            None => {
+                self.queued.remove(device);
+                self.sending.remove(device);
+                None
+            },
+        }
+    }
+
+    pub fn complete(&mut self, ticket: u64, success: bool) {
+        info!("Held request {} {}", ticket, if success { "done" } else { "failed" });
+        self.wakes.remove(&ticket);
+        self.tickets.insert(ticket, if success { TicketState::Done } else { TicketState::Failed });
+    }
+
+    /// `device` went back to sleep before answering the rest of the batch.
+    /// It goes back in front for the next wake, unless it was already sent
+    /// on `MAX_WAKES` wakes.
+    pub fn asleep(&mut self, device: InsteonAddress, ticket: u64, rest: VecDeque<T>) {
+        self.sending.remove(&device);
+        if self.wakes.get(&ticket).map_or(0, |&wakes| wakes) >= MAX_WAKES {
+            warn!("{} never answered held request {}", device, ticket);
+            self.complete(ticket, false);
+        } else {
+            self.queued.entry(device).or_insert_with(VecDeque::new).push_front((ticket, rest));
+        }
+    }
+
+    pub fn state(&self, ticket: u64) -> Option<TicketState> {
+        self.tickets.get(&ticket).cloned()
+    }
+}
+
+#[derive(Clone)]
+pub enum WakeActorMsg {
+    Run(InsteonAddress),
+    Timeout(usize, usize),
+}
+
+struct Batch {
+    device: InsteonAddress,
+    ticket: u64,
+    requests: VecDeque<Request>,
+    /// Sequence number of the only timeout still allowed to resend.
+    seq: usize,
+}
+
+/// Sends the batches held for a battery device while it is awake, one
+/// request at a time. The `RpcActor` runs at most one per device, and it
+/// keeps taking batches from the `WakeQueue` until none is left.
+pub struct WakeActor {
+    ser_tx_actor : ActorRef,
+    registry     : Arc<Mutex<DeviceRegistry>>,
+    wake_queue   : Arc<Mutex<WakeQueue<Request>>>,
+    batch        : Mutex<Option<Batch>>,
+}
+
+impl WakeActor {
+    pub fn new(tuple: (ActorRef, Arc<Mutex<DeviceRegistry>>, Arc<Mutex<WakeQueue<Request>>>))
+        -> WakeActor {
+        let (ser_tx_actor, registry, wake_queue) = tuple;
+        WakeActor {
+            ser_tx_actor: ser_tx_actor,
+            registry: registry,
+            wake_queue: wake_queue,
+            batch: Mutex::new(None),
+        }
+    }
+
+    /// `req` phrased in the dialect `device` has revealed by now.
+    fn phrase(&self, device: InsteonAddress, req: &Request) -> Option<InsteonMsg> {
+        match self.registry.lock().unwrap().dialect(&device) {
+            Some(dialect) => req.with_dialect(dialect).msg(),
+            None => req.msg(),
+        }
+    }
+
+    fn send(&self, batch: &mut Batch, msg: InsteonMsg, attempt: usize, context: &ActorCell) {
+        self.ser_tx_actor.tell_to(self.ser_tx_actor.clone(), ActorMsg::Send(msg));
+
+        batch.seq += 1;
+        timer::schedule(context.actor_ref(), WakeActorMsg::Timeout(batch.seq, attempt),
+                        Duration::from_millis(WAKE_WAIT_INTERVAL_MS));
+    }
+
+    /// Sends the front request of the batch in hand, moving on to the next
+    /// batch once it is done. A batch fails as a whole at the first request
+    /// that is refused or cannot be phrased, and the rest of it is dropped.
+    fn advance(&self, interior: &mut Option<Batch>, device: InsteonAddress, context: &ActorCell) {
+        loop {
+            let msg = match *interior {
+                Some(ref batch) => match batch.requests.front() {
+                    Some(req) => match self.phrase(device, req) {
+                        Some(msg) => Ok(msg),
+                        None => {
+                            error!("Unable to phrase held request {} for {}", batch.ticket, device);
+                            Err(false)
+                        },
+                    },
+                    None => Err(true),
+                },
+                None => Err(true),
+            };
+
+            match msg {
+                Ok(msg) => {
+                    self.send(interior.as_mut().unwrap(), msg, 0, context);
+                    return
+                },
+                Err(success) => {
+                    if let Some(batch) = interior.take() {
+                        self.wake_queue.lock().unwrap().complete(batch.ticket, success);
+                    }
+                },
+            }
+
+            let next = self.wake_queue.lock().unwrap().next(&device);
+            match next {
+                Some((ticket, requests)) => *interior = Some(Batch {
+                    device: device,
+                    ticket: ticket,
+                    requests: requests,
+                    seq: 0,
+                }),
+                None => {
+                    info!("Sent everything held for {}", device);
+                    self.finish(device, context);
+                    return
+                },
+            }
+        }
+    }
+
+    fn finish(&self, device: InsteonAddress, context: &ActorCell) {
+        context.tell(context.father(), RpcActorMsg::Done(device));
+        context.kill_me();
+    }
+
+    pub fn handle_insteon_msg(&self, message: InsteonMsg, context: ActorCell) {
+        let mut interior = self.batch.lock().unwrap();
+
+        let (device, success) = match *interior {
+            Some(ref batch) => match batch.requests.front() {
+                Some(req) => match req.reply(&message) {
+                    Some(_) => {
+                        req.learn(&message, &mut self.registry.lock().unwrap());
+                        (batch.device, true)
+                    },
+                    None if message.is_direct_nak_from(batch.device) => (batch.device, false),
+                    None => return,
+                },
+                None => return,
+            },
+            None => return,
+        };
+
+        if success {
+            interior.as_mut().unwrap().requests.pop_front();
+        } else {
+            let batch = interior.take().unwrap();
+            info!("{} refused held request {}", device, batch.ticket);
+            self.wake_queue.lock().unwrap().complete(batch.ticket, false);
+        }
+        self.advance(&mut interior, device, &context);
+    }
+
+    pub fn handle_wake_msg(&self, message: WakeActorMsg, context: ActorCell) {
+        let mut interior = self.batch.lock().unwrap();
+
+        match message {
+            WakeActorMsg::Run(device) => {
+                info!("{} is awake, sending what is held for it", device);
+                self.advance(&mut interior, device, &context);
+            },
+
+            WakeActorMsg::Timeout(seq, attempt) => {
+                let (device, msg) = match *interior {
+                    Some(ref batch) if batch.seq == seq =>
+                        (batch.device, batch.requests.front().and_then(|req| self.phrase(batch.device, req))),
+                    _ => return,
+                };
+
+                match msg {
+                    Some(msg) if attempt + 1 < WAKE_SEND_RETRIES =>
+                        self.send(interior.as_mut().unwrap(), msg, attempt + 1, &context),
+                    _ => {
+                        let batch = interior.take().unwrap();
+                        info!("{} went back to sleep during held request {}", device, batch.ticket);
+                        self.wake_queue.lock().unwrap().asleep(device, batch.ticket, batch.requests);
+                        self.finish(device, &context);
+                    },
+                }
+            },
+        }
+    }
+}
+
+impl Actor for WakeActor {
+    fn receive(&self, msg: Box<Any>, context: ActorCell) {
+        match msg.downcast_ref::<WakeActorMsg>() {
+            Some(wake_msg) => self.handle_wake_msg(wake_msg.clone(), context),
+            None => match msg.downcast_ref::<InsteonMsg>() {
+                Some(insteon_msg) => self.handle_insteon_msg(*insteon_msg, context),
+                None => unreachable!(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn a() -> InsteonAddress {
+        InsteonAddress::new(0x3C, 0x11, 0x9E)
+    }
+
+    #[test]
+    fn one_sender_takes_every_batch_in_order() {
+        let mut queue = WakeQueue::new();
+        assert!(!queue.wake(&a()));
+
+        let first = queue.push(a(), vec!['a', 'b']);
+        assert!(queue.wake(&a()));
+        assert!(!queue.wake(&a()));
+        assert_eq!(queue.next(&a()), Some((first, vec!['a', 'b'].into_iter().collect())));
+
+        // Held while the first batch is sent, so the running sender takes it.
+        let second = queue.push(a(), vec!['c']);
+        assert!(!queue.wake(&a()));
+        assert_eq!(queue.next(&a()), Some((second, vec!['c'].into_iter().collect())));
+        assert_eq!(queue.next(&a()), None);
+
+        assert!(!queue.wake(&a()));
+        queue.push(a(), vec!['d']);
+        assert!(queue.wake(&a()));
+    }
+
+    #[test]
+    fn a_batch_the_device_sleeps_through_goes_back_in_front() {
+        let mut queue = WakeQueue::new();
+        let first = queue.push(a(), vec!['a', 'b']);
+        let second = queue.push(a(), vec!['c']);
+
+        for _ in 1..MAX_WAKES {
+            assert!(queue.wake(&a()));
+            let (ticket, batch) = queue.next(&a()).unwrap();
+            assert_eq!(ticket, first);
+            queue.asleep(a(), ticket, batch);
+            assert_eq!(queue.state(first), Some(TicketState::Pending));
+        }
+
+        // Only what the device has not answered yet is sent again.
+        assert!(queue.wake(&a()));
+        assert_eq!(queue.next(&a()), Some((first, vec!['a', 'b'].into_iter().collect())));
+        queue.asleep(a(), first, vec!['b'].into_iter().collect());
+        assert_eq!(queue.state(first), Some(TicketState::Failed));
+
+        assert!(queue.wake(&a()));
+        assert_eq!(queue.next(&a()).map(|(ticket, _)| ticket), Some(second));
+        queue.complete(second, true);
+        assert_eq!(queue.state(second), Some(TicketState::Done));
+        assert_eq!(queue.next(&a()), None);
+        assert!(!queue.holds(&a(), |_| true));
+    }
+
+    #[test]
+    fn finds_held_requests() {
+        let mut queue = WakeQueue::new();
+        queue.push(a(), vec![1, 2]);
+        assert!(queue.holds(&a(), |&request| request == 2));
+        assert!(!queue.holds(&a(), |&request| request == 3));
+        assert!(!queue.holds(&InsteonAddress::new(0x1A, 0xD0, 0xF4), |_| true));
+    }
+}
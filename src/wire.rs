@@ -0,0 +1,573 @@
+use std::error;
+use std::fmt;
+
+use bytes::BytesMut;
+
+use insteon_structs::*;
+use address::InsteonAddress;
+
+/// Explicit byte layout of the frames exchanged with the PLM.
+pub trait WireFormat: Sized {
+    /// Appends the frame, including the leading `MSG_BEGIN`, to `dst`.
+    fn encode(&self, dst: &mut BytesMut) -> Result<(), EncodeError>;
+
+    /// Decodes the frame at the start of `src`, which must begin with `MSG_BEGIN`.
+    /// Returns `Ok(None)` while the frame is still incomplete, and the number
+    /// of consumed bytes once it is decoded.
+    fn decode(src: &[u8]) -> Result<Option<(Self, usize)>, DecodeError>;
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    MissingStart(u8),
+    UnknownType(u8),
+    InvalidStatus(u8),
+    /// The frame body is shorter than the fields its type carries.
+    Truncated { msg_type: u8, size: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DecodeError::MissingStart(byte) =>
+                write!(f, "expected frame start {:#04x}, found {:#04x}", MSG_BEGIN, byte),
+            DecodeError::UnknownType(byte) =>
+                write!(f, "unknown message type {:#04x}", byte),
+            DecodeError::InvalidStatus(byte) =>
+                write!(f, "invalid ACK/NAK byte {:#04x}", byte),
+            DecodeError::Truncated { msg_type, size } =>
+                write!(f, "{}-byte body too short for message type {:#04x}", size, msg_type),
+        }
+    }
+}
+
+impl error::Error for DecodeError {
+    fn description(&self) -> &str {
+        "unable to decode PLM frame"
+    }
+}
+
+#[derive(Debug)]
+pub enum EncodeError {
+    NotSendable(InsteonMsg),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EncodeError::NotSendable(ref msg) =>
+                write!(f, "message can only be received from the PLM: {:?}", msg),
+        }
+    }
+}
+
+impl error::Error for EncodeError {
+    fn description(&self) -> &str {
+        "unable to encode PLM frame"
+    }
+}
+
+/// The body of a frame, which hands out its fields as long as they fit.
+struct Body<'a> {
+    msg_type: u8,
+    bytes: &'a [u8],
+}
+
+impl<'a> Body<'a> {
+    fn slice(&self, start: usize, len: usize) -> Result<&'a [u8], DecodeError> {
+        self.bytes.get(start..start + len).ok_or(DecodeError::Truncated {
+            msg_type: self.msg_type,
+            size: self.bytes.len(),
+        })
+    }
+
+    fn byte(&self, i: usize) -> Result<u8, DecodeError> {
+        self.slice(i, 1).map(|b| b[0])
+    }
+
+    fn addr(&self, i: usize) -> Result<InsteonAddress, DecodeError> {
+        self.slice(i, 3).map(|b| InsteonAddress::new(b[0], b[1], b[2]))
+    }
+
+    fn bytes3(&self, i: usize) -> Result<[u8; 3], DecodeError> {
+        self.slice(i, 3).map(|b| [b[0], b[1], b[2]])
+    }
+
+    fn user_data(&self, i: usize) -> Result<[u8; 14], DecodeError> {
+        let mut user_data = [0u8; 14];
+        user_data.copy_from_slice(self.slice(i, 14)?);
+        Ok(user_data)
+    }
+
+    fn flags(&self, i: usize) -> Result<MessageFlags, DecodeError> {
+        self.byte(i).map(MessageFlags::from)
+    }
+
+    fn status(&self, i: usize) -> Result<PlmStatus, DecodeError> {
+        let byte = self.byte(i)?;
+        PlmStatus::from_u8(byte).ok_or(DecodeError::InvalidStatus(byte))
+    }
+}
+
+impl WireFormat for InsteonMsg {
+    fn encode(&self, dst: &mut BytesMut) -> Result<(), EncodeError> {
+        match *self {
+            InsteonMsg::SendStandardMsg { addr_to, msg_flags, cmd1, cmd2 } => {
+                dst.extend_from_slice(&[MSG_BEGIN, SEND_STANDARD_MSG]);
+                dst.extend_from_slice(&addr_to.bytes());
+                dst.extend_from_slice(&[u8::from(msg_flags), cmd1, cmd2]);
+                Ok(())
+            },
+
+            InsteonMsg::SendExtendedMsg { addr_to, msg_flags, cmd1, cmd2, ref user_data } => {
+                dst.extend_from_slice(&[MSG_BEGIN, SEND_STANDARD_MSG]);
+                dst.extend_from_slice(&addr_to.bytes());
+                dst.extend_from_slice(&[u8::from(msg_flags), cmd1, cmd2]);
+                dst.extend_from_slice(user_data);
+                Ok(())
+            },
+
+            InsteonMsg::SendAllLinkCmd { all_link_group, cmd1, cmd2 } => {
+                dst.extend_from_slice(&[MSG_BEGIN, SEND_ALL_LINK_CMD, all_link_group, cmd1, cmd2]);
+                Ok(())
+            },
+
+            InsteonMsg::StartAllLinking { link_code, all_link_group } => {
+                dst.extend_from_slice(&[MSG_BEGIN, START_ALL_LINKING, link_code, all_link_group]);
+                Ok(())
+            },
+
+            InsteonMsg::CancelAllLinking {} => {
+                dst.extend_from_slice(&[MSG_BEGIN, CANCEL_ALL_LINKING]);
+                Ok(())
+            },
+
+            InsteonMsg::GetFirstAllLinkRecord {} => {
+                dst.extend_from_slice(&[MSG_BEGIN, GET_FIRST_ALL_LINK_RECORD]);
+                Ok(())
+            },
+
+            InsteonMsg::GetNextAllLinkRecord {} => {
+                dst.extend_from_slice(&[MSG_BEGIN, GET_NEXT_ALL_LINK_RECORD]);
+                Ok(())
+            },
+
+            _ => Err(EncodeError::NotSendable(*self)),
+        }
+    }
+
+    fn decode(src: &[u8]) -> Result<Option<(InsteonMsg, usize)>, DecodeError> {
+        const HEADER_SIZE : usize = 2;
+
+        if src.len() < HEADER_SIZE {
+            return Ok(None)
+        }
+
+        if src[0] != MSG_BEGIN {
+            return Err(DecodeError::MissingStart(src[0]))
+        }
+
+        let msg_type = src[1];
+        let mut size = match get_msg_size(&msg_type) {
+            Some(size) => size,
+            None => return Err(DecodeError::UnknownType(msg_type)),
+        };
+
+        if msg_type == SEND_STANDARD_MSG {
+            const FLAGS_OFFSET : usize = HEADER_SIZE + 3;
+            if src.len() <= FLAGS_OFFSET {
+                return Ok(None)
+            }
+            if MessageFlags::from(src[FLAGS_OFFSET]).extended {
+                size = SEND_EXTENDED_MSG_SIZE;
+            }
+        }
+
+        if src.len() < HEADER_SIZE + size {
+            return Ok(None)
+        }
+
+        let b = Body { msg_type: msg_type, bytes: &src[HEADER_SIZE..HEADER_SIZE + size] };
+        let msg = match msg_type {
+            STANDARD_MSG => InsteonMsg::StandardMsg {
+                addr_from: b.addr(0)?,
+                addr_to: b.addr(3)?,
+                msg_flags: b.flags(6)?,
+                cmd1: b.byte(7)?,
+                cmd2: b.byte(8)?,
+            },
+
+            EXTENDED_MSG => InsteonMsg::ExtendedMsg {
+                addr_from: b.addr(0)?,
+                addr_to: b.addr(3)?,
+                msg_flags: b.flags(6)?,
+                cmd1: b.byte(7)?,
+                cmd2: b.byte(8)?,
+                user_data: b.user_data(9)?,
+            },
+
+            X10_RECEIVED => InsteonMsg::X10Received {
+                raw_x10: b.byte(0)?,
+                x10_flag: b.byte(1)?,
+            },
+
+            ALL_LINKING_COMPLETED => InsteonMsg::AllLinkingCompleted {
+                link_code: b.byte(0)?,
+                all_link_group: b.byte(1)?,
+                id: b.addr(2)?,
+                device_category: b.byte(5)?,
+                device_subcategory: b.byte(6)?,
+                firmware_version: b.byte(7)?,
+            },
+
+            BUTTON_EVENT_REPORT => InsteonMsg::ButtonEventReport {
+                button_event: b.byte(0)?,
+            },
+
+            USER_RESET_DETECTED => InsteonMsg::UserResetDetected {},
+
+            ALL_LINK_CLEANUP_FAILURE_REPORT => InsteonMsg::AllLinkCleanupFailureReport {
+                x01: b.byte(0)?,
+                all_link_group: b.byte(1)?,
+                id: b.addr(2)?,
+            },
+
+            ALL_LINK_RECORD_RESPONSE => InsteonMsg::AllLinkRecordResponse {
+                all_link_record_flags: b.byte(0)?,
+                all_link_group: b.byte(1)?,
+                id: b.addr(2)?,
+                link_data: b.bytes3(5)?,
+            },
+
+            ALL_LINK_CLEANUP_STATUS_REPORT => InsteonMsg::AllLinkCleanupStatusReport {
+                status_byte: b.byte(0)?,
+            },
+
+            GET_IM_INFO => InsteonMsg::GetImInfoEcho {
+                id: b.addr(0)?,
+                device_category: b.byte(3)?,
+                device_subcategory: b.byte(4)?,
+                firmware_version: b.byte(5)?,
+                status: b.status(6)?,
+            },
+
+            SEND_ALL_LINK_CMD => InsteonMsg::SendAllLinkCmdEcho {
+                all_link_group: b.byte(0)?,
+                cmd1: b.byte(1)?,
+                cmd2: b.byte(2)?,
+                status: b.status(3)?,
+            },
+
+            SEND_STANDARD_MSG if size == SEND_EXTENDED_MSG_SIZE => InsteonMsg::SendExtendedMsgEcho {
+                addr_to: b.addr(0)?,
+                msg_flags: b.flags(3)?,
+                cmd1: b.byte(4)?,
+                cmd2: b.byte(5)?,
+                user_data: b.user_data(6)?,
+                status: b.status(20)?,
+            },
+
+            SEND_STANDARD_MSG => InsteonMsg::SendStandardMsgEcho {
+                addr_to: b.addr(0)?,
+                msg_flags: b.flags(3)?,
+                cmd1: b.byte(4)?,
+                cmd2: b.byte(5)?,
+                status: b.status(6)?,
+            },
+
+            SEND_X10 => InsteonMsg::SendX10Echo {
+                raw_x10: b.byte(0)?,
+                x10_flag: b.byte(1)?,
+                status: b.status(2)?,
+            },
+
+            START_ALL_LINKING => InsteonMsg::StartAllLinkingEcho {
+                link_code: b.byte(0)?,
+                all_link_group: b.byte(1)?,
+                status: b.status(2)?,
+            },
+
+            CANCEL_ALL_LINKING => InsteonMsg::CancelAllLinkingEcho {
+                status: b.status(0)?,
+            },
+
+            SET_HOST_DEVICE_CATEGORY => InsteonMsg::SetHostDeviceCategoryEcho {
+                device_category: b.byte(0)?,
+                device_subcategory: b.byte(1)?,
+                firmware_version: b.byte(2)?,
+                status: b.status(3)?,
+            },
+
+            RESET_IM => InsteonMsg::ResetImEcho {
+                status: b.status(0)?,
+            },
+
+            SET_ACK_MESSAGE_BYTE => InsteonMsg::SetAckMessageByteEcho {
+                cmd2: b.byte(0)?,
+                status: b.status(1)?,
+            },
+
+            GET_FIRST_ALL_LINK_RECORD => InsteonMsg::GetFirstAllLinkRecordEcho {
+                status: b.status(0)?,
+            },
+
+            GET_NEXT_ALL_LINK_RECORD => InsteonMsg::GetNextAllLinkRecordEcho {
+                status: b.status(0)?,
+            },
+
+            SET_IM_CONFIG => InsteonMsg::SetImConfigEcho {
+                im_config_flags: b.byte(0)?,
+                status: b.status(1)?,
+            },
+
+            GET_ALL_LINK_RECORD_FOR_SENDER => InsteonMsg::GetAllLinkRecordForSenderEcho {
+                status: b.status(0)?,
+            },
+
+            LED_ON => InsteonMsg::LedOnEcho {
+                status: b.status(0)?,
+            },
+
+            LED_OFF => InsteonMsg::LedOffEcho {
+                status: b.status(0)?,
+            },
+
+            MANAGE_ALL_LINK_RECORD => InsteonMsg::ManageAllLinkRecordEcho {
+                control_code: b.byte(0)?,
+                all_link_record_flags: b.byte(1)?,
+                all_link_group: b.byte(2)?,
+                id: b.addr(3)?,
+                link_data: b.bytes3(6)?,
+                status: b.status(9)?,
+            },
+
+            SET_NAK_MESSAGE_BYTE => InsteonMsg::SetNakMessageByteEcho {
+                cmd2: b.byte(0)?,
+                status: b.status(1)?,
+            },
+
+            SET_ACK_MESSAGE_TWO_BYTES => InsteonMsg::SetAckMessageTwoBytesEcho {
+                cmd1: b.byte(0)?,
+                cmd2: b.byte(1)?,
+                status: b.status(2)?,
+            },
+
+            RF_SLEEP => InsteonMsg::RfSleepEcho {
+                cmd1: b.byte(0)?,
+                cmd2: b.byte(1)?,
+                status: b.status(2)?,
+            },
+
+            GET_IM_CONFIG => InsteonMsg::GetImConfigEcho {
+                im_config_flags: b.byte(0)?,
+                spare1: b.byte(1)?,
+                spare2: b.byte(2)?,
+                status: b.status(3)?,
+            },
+
+            _ => return Err(DecodeError::UnknownType(msg_type)),
+        };
+
+        Ok(Some((msg, HEADER_SIZE + size)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use checksum::Checksum;
+
+    const ACK : u8 = PlmStatus::ACK;
+
+    fn a() -> InsteonAddress {
+        InsteonAddress::new(0x1A, 0xD0, 0xF4)
+    }
+
+    fn b() -> InsteonAddress {
+        InsteonAddress::new(0x44, 0x85, 0x11)
+    }
+
+    fn data() -> [u8; 14] {
+        [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14]
+    }
+
+    /// One well-formed frame for every message type in `SIZE_MAP`.
+    fn frames() -> Vec<(Vec<u8>, InsteonMsg)> {
+        let direct = u8::from(MessageFlags::direct());
+        let extended = u8::from(MessageFlags::direct_extended());
+        let mut extended_echo = vec![0x02, 0x62, 0x1A, 0xD0, 0xF4, extended, 0x2E, 0x00];
+        extended_echo.extend_from_slice(&data());
+        extended_echo.push(ACK);
+        let mut extended_msg = vec![0x02, 0x51, 0x1A, 0xD0, 0xF4, 0x44, 0x85, 0x11, extended, 0x2F, 0x00];
+        extended_msg.extend_from_slice(&data());
+
+        vec![
+            (vec![0x02, 0x50, 0x1A, 0xD0, 0xF4, 0x44, 0x85, 0x11, 0x2B, 0x11, 0xFF],
+             InsteonMsg::StandardMsg { addr_from: a(), addr_to: b(), msg_flags: MessageFlags::from(0x2B),
+                                       cmd1: 0x11, cmd2: 0xFF }),
+            (extended_msg,
+             InsteonMsg::ExtendedMsg { addr_from: a(), addr_to: b(), msg_flags: MessageFlags::direct_extended(),
+                                       cmd1: 0x2F, cmd2: 0x00, user_data: data() }),
+            (vec![0x02, 0x52, 0x66, 0x80],
+             InsteonMsg::X10Received { raw_x10: 0x66, x10_flag: 0x80 }),
+            (vec![0x02, 0x53, 0x01, 0x00, 0x1A, 0xD0, 0xF4, 0x01, 0x20, 0x41],
+             InsteonMsg::AllLinkingCompleted { link_code: 0x01, all_link_group: 0x00, id: a(),
+                                               device_category: 0x01, device_subcategory: 0x20,
+                                               firmware_version: 0x41 }),
+            (vec![0x02, 0x54, 0x02],
+             InsteonMsg::ButtonEventReport { button_event: 0x02 }),
+            (vec![0x02, 0x55],
+             InsteonMsg::UserResetDetected {}),
+            (vec![0x02, 0x56, 0x01, 0x03, 0x1A, 0xD0, 0xF4],
+             InsteonMsg::AllLinkCleanupFailureReport { x01: 0x01, all_link_group: 0x03, id: a() }),
+            (vec![0x02, 0x57, 0xE2, 0x01, 0x1A, 0xD0, 0xF4, 0x01, 0x20, 0x41],
+             InsteonMsg::AllLinkRecordResponse { all_link_record_flags: 0xE2, all_link_group: 0x01, id: a(),
+                                                 link_data: [0x01, 0x20, 0x41] }),
+            (vec![0x02, 0x58, ACK],
+             InsteonMsg::AllLinkCleanupStatusReport { status_byte: ACK }),
+            (vec![0x02, 0x60, 0x44, 0x85, 0x11, 0x03, 0x15, 0x9E, ACK],
+             InsteonMsg::GetImInfoEcho { id: b(), device_category: 0x03, device_subcategory: 0x15,
+                                         firmware_version: 0x9E, status: PlmStatus::Ack }),
+            (vec![0x02, 0x61, 0x01, 0x11, 0x00, ACK],
+             InsteonMsg::SendAllLinkCmdEcho { all_link_group: 0x01, cmd1: 0x11, cmd2: 0x00,
+                                              status: PlmStatus::Ack }),
+            (vec![0x02, 0x62, 0x1A, 0xD0, 0xF4, direct, 0x19, 0x00, PlmStatus::NAK],
+             InsteonMsg::SendStandardMsgEcho { addr_to: a(), msg_flags: MessageFlags::direct(),
+                                               cmd1: 0x19, cmd2: 0x00, status: PlmStatus::Nak }),
+            (extended_echo,
+             InsteonMsg::SendExtendedMsgEcho { addr_to: a(), msg_flags: MessageFlags::direct_extended(),
+                                               cmd1: 0x2E, cmd2: 0x00, user_data: data(),
+                                               status: PlmStatus::Ack }),
+            (vec![0x02, 0x63, 0x66, 0x80, ACK],
+             InsteonMsg::SendX10Echo { raw_x10: 0x66, x10_flag: 0x80, status: PlmStatus::Ack }),
+            (vec![0x02, 0x64, 0x03, 0x01, ACK],
+             InsteonMsg::StartAllLinkingEcho { link_code: 0x03, all_link_group: 0x01, status: PlmStatus::Ack }),
+            (vec![0x02, 0x65, ACK],
+             InsteonMsg::CancelAllLinkingEcho { status: PlmStatus::Ack }),
+            (vec![0x02, 0x66, 0x03, 0x15, 0xFF, ACK],
+             InsteonMsg::SetHostDeviceCategoryEcho { device_category: 0x03, device_subcategory: 0x15,
+                                                     firmware_version: 0xFF, status: PlmStatus::Ack }),
+            (vec![0x02, 0x67, ACK],
+             InsteonMsg::ResetImEcho { status: PlmStatus::Ack }),
+            (vec![0x02, 0x68, 0x42, ACK],
+             InsteonMsg::SetAckMessageByteEcho { cmd2: 0x42, status: PlmStatus::Ack }),
+            (vec![0x02, 0x69, PlmStatus::NAK],
+             InsteonMsg::GetFirstAllLinkRecordEcho { status: PlmStatus::Nak }),
+            (vec![0x02, 0x6A, ACK],
+             InsteonMsg::GetNextAllLinkRecordEcho { status: PlmStatus::Ack }),
+            (vec![0x02, 0x6B, 0x40, ACK],
+             InsteonMsg::SetImConfigEcho { im_config_flags: 0x40, status: PlmStatus::Ack }),
+            (vec![0x02, 0x6C, ACK],
+             InsteonMsg::GetAllLinkRecordForSenderEcho { status: PlmStatus::Ack }),
+            (vec![0x02, 0x6D, ACK],
+             InsteonMsg::LedOnEcho { status: PlmStatus::Ack }),
+            (vec![0x02, 0x6E, ACK],
+             InsteonMsg::LedOffEcho { status: PlmStatus::Ack }),
+            (vec![0x02, 0x6F, 0x40, 0xE2, 0x01, 0x1A, 0xD0, 0xF4, 0x01, 0x20, 0x41, ACK],
+             InsteonMsg::ManageAllLinkRecordEcho { control_code: 0x40, all_link_record_flags: 0xE2,
+                                                   all_link_group: 0x01, id: a(),
+                                                   link_data: [0x01, 0x20, 0x41], status: PlmStatus::Ack }),
+            (vec![0x02, 0x70, 0x42, ACK],
+             InsteonMsg::SetNakMessageByteEcho { cmd2: 0x42, status: PlmStatus::Ack }),
+            (vec![0x02, 0x71, 0x41, 0x42, ACK],
+             InsteonMsg::SetAckMessageTwoBytesEcho { cmd1: 0x41, cmd2: 0x42, status: PlmStatus::Ack }),
+            (vec![0x02, 0x72, 0x00, 0x00, ACK],
+             InsteonMsg::RfSleepEcho { cmd1: 0x00, cmd2: 0x00, status: PlmStatus::Ack }),
+            (vec![0x02, 0x73, 0x40, 0x00, 0x00, ACK],
+             InsteonMsg::GetImConfigEcho { im_config_flags: 0x40, spare1: 0x00, spare2: 0x00,
+                                           status: PlmStatus::Ack }),
+        ]
+    }
+
+    #[test]
+    fn decodes_every_message_type() {
+        let frames = frames();
+        for msg_type in 0..256u32 {
+            let msg_type = msg_type as u8;
+            if get_msg_size(&msg_type).is_some() {
+                assert!(frames.iter().any(|&(ref frame, _)| frame[1] == msg_type),
+                        "no test frame for {:#04x}", msg_type);
+            }
+        }
+
+        for (frame, expected) in frames {
+            match InsteonMsg::decode(&frame) {
+                Ok(Some((msg, used))) => {
+                    assert_eq!(msg, expected);
+                    assert_eq!(used, frame.len(), "{:?}", frame);
+                },
+                other => panic!("{:?} decoded to {:?}", frame, other),
+            }
+        }
+    }
+
+    #[test]
+    fn leaves_trailing_bytes() {
+        let mut frame = vec![0x02, 0x58, ACK];
+        frame.extend_from_slice(&[0x02, 0x50]);
+        match InsteonMsg::decode(&frame) {
+            Ok(Some((_, 3))) => (),
+            other => panic!("decoded to {:?}", other),
+        }
+    }
+
+    #[test]
+    fn waits_for_truncated_frames() {
+        for (frame, _) in frames() {
+            for len in 0..frame.len() {
+                match InsteonMsg::decode(&frame[..len]) {
+                    Ok(None) => (),
+                    other => panic!("{:?} decoded to {:?}", &frame[..len], other),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        match InsteonMsg::decode(&[0x50, 0x02, 0x00]) {
+            Err(DecodeError::MissingStart(0x50)) => (),
+            other => panic!("decoded to {:?}", other),
+        }
+        match InsteonMsg::decode(&[0x02, 0x42, 0x00]) {
+            Err(DecodeError::UnknownType(0x42)) => (),
+            other => panic!("decoded to {:?}", other),
+        }
+        match InsteonMsg::decode(&[0x02, 0x65, 0x07]) {
+            Err(DecodeError::InvalidStatus(0x07)) => (),
+            other => panic!("decoded to {:?}", other),
+        }
+    }
+
+    #[test]
+    fn encodes_what_it_decodes() {
+        let sent = InsteonMsg::SendStandardMsg { addr_to: a(), msg_flags: MessageFlags::direct(), cmd1: 0x19, cmd2: 0x00 };
+        let mut dst = BytesMut::new();
+        sent.encode(&mut dst).unwrap();
+        dst.extend_from_slice(&[ACK]);
+        match InsteonMsg::decode(&dst) {
+            Ok(Some((echo, _))) =>
+                assert_eq!(echo, InsteonMsg::SendStandardMsgEcho { addr_to: a(), msg_flags: MessageFlags::direct(), cmd1: 0x19,
+                                                                   cmd2: 0x00, status: PlmStatus::Ack }),
+            other => panic!("decoded to {:?}", other),
+        }
+    }
+
+    #[test]
+    fn encodes_extended_messages() {
+        let sent = InsteonMsg::send_extended(a(), 0x2E, 0x00, data(), Some(Checksum::Classic));
+        let mut dst = BytesMut::new();
+        sent.encode(&mut dst).unwrap();
+        assert_eq!(dst.len(), 2 + SEND_EXTENDED_MSG_SIZE - 1);
+        assert_eq!(dst[5], u8::from(MessageFlags::direct_extended()));
+
+        dst.extend_from_slice(&[ACK]);
+        match InsteonMsg::decode(&dst) {
+            Ok(Some((echo, used))) => {
+                assert_eq!(used, dst.len());
+                assert_eq!(echo.echo_status(&sent), Some(PlmStatus::Ack));
+            },
+            other => panic!("decoded to {:?}", other),
+        }
+    }
+}